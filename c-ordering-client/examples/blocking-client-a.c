@@ -2,7 +2,7 @@
 
 #include <stdio.h>
 
-OrderingClientApi* api;
+const OrderingClientApi* api;
 ClientAndJoinHandle client_and_jh;
 
 int termination() {
@@ -13,7 +13,7 @@ int main(int argc, char **argv) {
   api = load_ordering_client_api();
   printf("loaded api: %p\n", api);
   // let (client, _jh) = ordering_server::client::BlockingClient::start(("127.0.0.1", 15045), 0);
-  client_and_jh = api->start_client(0);
+  api->start_client(0, &client_and_jh);
   // atexit(termination);
   void* client = client_and_jh.client;
   // client.tracepoint_maybe_do(HookInvocation::from_short(("A99", 0, 0)));
@@ -3,9 +3,10 @@
 #include <stdio.h>
 
 int main(int argc, char **argv) {
-  OrderingClientApi* api = load_ordering_client_api();
+  const OrderingClientApi* api = load_ordering_client_api();
   // let (client, _jh) = ordering_server::client::BlockingClient::start(("127.0.0.1", 15045), 1);
-  ClientAndJoinHandle client_and_jh = api->start_client(-1);
+  ClientAndJoinHandle client_and_jh;
+  api->start_client(-1, &client_and_jh);
   void* client = client_and_jh.client;
   // client.tracepoint_maybe_do(HookInvocation::from_short(("C99", 2, 0)));
   api->tracepoint_maybe_do(client, "C99", -1, 0);
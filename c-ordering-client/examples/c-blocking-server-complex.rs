@@ -1,12 +1,9 @@
 use std::process::Command;
 
 use ordering_server::{
-    server, EnvironmentVariables, Precedence, ORDSERV_PORT_ENV_VAR,
-    ORDSERV_WAIT_TIMEOUT_MILLISECONDS_ENV_VAR,
+    server, EnvironmentVariables, Precedence, ORDSERV_WAIT_TIMEOUT_MILLISECONDS_ENV_VAR,
 };
 
-const PORT: u16 = 15045;
-
 fn compile(name: String) {
     let mut child = Command::new("gcc")
         .args([
@@ -16,6 +13,8 @@ fn compile(name: String) {
             &format!("./c-ordering-client/examples/{}.run", name),
             "-I",
             "./c-ordering-client/examples/",
+            "-I",
+            "./c-ordering-client/include/",
             "./c-ordering-client/examples/c-ordering-client.c",
             &format!("./c-ordering-client/examples/{}.c", name),
         ])
@@ -31,7 +30,6 @@ fn run_federate(name: String, runtime_evars: &EnvironmentVariables) -> std::thre
     std::thread::spawn(move || {
         let mut child = Command::new(format!("./c-ordering-client/examples/{}.run", name))
             .envs(evars)
-            .env(ORDSERV_PORT_ENV_VAR, PORT.to_string())
             .env(ORDSERV_WAIT_TIMEOUT_MILLISECONDS_ENV_VAR, "1000")
             .env(
                 "C_ORDERING_CLIENT_LIBRARY_PATH",
@@ -84,10 +82,15 @@ async fn main() {
     }
     server_handle.updates_acks[0]
         .0
-        .send(Some(precedence.clone()))
+        .send(server::Update::Start(precedence.clone()))
         .await
         .unwrap();
-    let evars = server_handle.updates_acks[0].1.recv().await.unwrap();
+    let evars = server_handle.updates_acks[0]
+        .1
+        .recv()
+        .await
+        .unwrap()
+        .unwrap_or_else(|e| panic!("Precedence was rejected: {}", e));
     compile("blocking-client-a".into());
     compile("blocking-client-b".into());
     compile("blocking-client-c".into());
@@ -99,10 +102,15 @@ async fn main() {
     child_c.join().unwrap();
     server_handle.updates_acks[0]
         .0
-        .send(Some(precedence))
+        .send(server::Update::Start(precedence))
         .await
         .unwrap();
-    let evars = server_handle.updates_acks[0].1.recv().await.unwrap();
+    let evars = server_handle.updates_acks[0]
+        .1
+        .recv()
+        .await
+        .unwrap()
+        .unwrap_or_else(|e| panic!("Precedence was rejected: {}", e));
     compile("blocking-client-a".into());
     compile("blocking-client-b".into());
     compile("blocking-client-c".into());
@@ -112,7 +120,11 @@ async fn main() {
     child_a.join().unwrap();
     child_b.join().unwrap();
     child_c.join().unwrap();
-    server_handle.updates_acks[0].0.send(None).await.unwrap();
-    server_handle.join_handle.await.unwrap();
+    server_handle.updates_acks[0]
+        .0
+        .send(server::Update::Halt)
+        .await
+        .unwrap();
+    server_handle.join().await;
     println!("Server finished");
 }
@@ -6,35 +6,85 @@ const char* LIBRARY_PATH_ENV_VAR = "C_ORDERING_CLIENT_LIBRARY_PATH";
 
 #include "c-ordering-client.h"
 
-ClientAndJoinHandle start_client_nop(int federate_id) {
-  return (ClientAndJoinHandle) { .client = NULL, .join_handle = NULL };
+const char* last_error_nop() { return NULL; }
+OrdservStatus start_client_nop(int federate_id, ClientAndJoinHandle* client_and_join_handle) {
+  *client_and_join_handle = (ClientAndJoinHandle) { .client = NULL, .join_handle = NULL };
+  return ORDSERV_STATUS_OK;
 }
-void finish_nop(ClientAndJoinHandle cajh) {}
-void tracepoint_maybe_wait_nop(void* client, char* hook_id, int federate_id, int sequence_number) {}
-void tracepoint_maybe_notify_nop(void* client, char* hook_id, int federate_id, int sequence_number) {}
-void tracepoint_maybe_do_nop(void* client, char* hook_id, int federate_id, int sequence_number) {}
+OrdservStatus start_client_with_config_nop(
+  const OrdservClientConfig* config, ClientAndJoinHandle* client_and_join_handle
+) {
+  return start_client_nop(config->federate_id, client_and_join_handle);
+}
+OrdservStatus finish_nop(ClientAndJoinHandle cajh) { return ORDSERV_STATUS_OK; }
+OrdservStatus tracepoint_nop(void* client, const char* hook_id, int federate_id, int sequence_number) {
+  return ORDSERV_STATUS_OK;
+}
+OrdservStatus register_hook_nop(void* client, const char* hook_id, int federate_id, int* hook_number) {
+  *hook_number = -1;
+  return ORDSERV_STATUS_OK;
+}
+OrdservStatus tracepoint_numbered_nop(void* client, int hook_number, int sequence_number) {
+  return ORDSERV_STATUS_OK;
+}
+OrdservStatus tracepoint_with_attributes_nop(
+  void* client, const char* hook_id, int federate_id, int sequence_number,
+  const char* const* keys, const char* const* values, int n_attributes
+) {
+  return ORDSERV_STATUS_OK;
+}
+OrdservStatus set_thread_namespace_nop(const char* namespace) { return ORDSERV_STATUS_OK; }
+OrdservStatus tracepoint_next_nop(void* client, const char* hook_id, int federate_id, int* sequence_number) {
+  if (sequence_number) *sequence_number = 0;
+  return ORDSERV_STATUS_OK;
+}
+
+const OrderingClientApi NOP_API = {
+  .size = sizeof(OrderingClientApi),
+  .version = ORDERING_CLIENT_API_VERSION,
+  .last_error = last_error_nop,
+  .start_client = start_client_nop,
+  .finish = finish_nop,
+  .tracepoint_maybe_wait = tracepoint_nop,
+  .tracepoint_maybe_notify = tracepoint_nop,
+  .tracepoint_maybe_do = tracepoint_nop,
+  .register_hook = register_hook_nop,
+  .tracepoint_maybe_wait_numbered = tracepoint_numbered_nop,
+  .tracepoint_maybe_notify_numbered = tracepoint_numbered_nop,
+  .tracepoint_maybe_do_numbered = tracepoint_numbered_nop,
+  .tracepoint_maybe_wait_with_attributes = tracepoint_with_attributes_nop,
+  .tracepoint_maybe_notify_with_attributes = tracepoint_with_attributes_nop,
+  .tracepoint_maybe_do_with_attributes = tracepoint_with_attributes_nop,
+  .set_thread_namespace = set_thread_namespace_nop,
+  .tracepoint_maybe_wait_next = tracepoint_next_nop,
+  .tracepoint_maybe_notify_next = tracepoint_next_nop,
+  .tracepoint_maybe_do_next = tracepoint_next_nop,
+  .start_client_with_config = start_client_with_config_nop,
+};
 
-OrderingClientApi* load_ordering_client_api() {
+const OrderingClientApi* load_ordering_client_api() {
   char* library_path = getenv(LIBRARY_PATH_ENV_VAR);
   if (!library_path) {
     fprintf(stderr, "environment variable %s not set\n", LIBRARY_PATH_ENV_VAR);
-    OrderingClientApi* api = malloc(sizeof(OrderingClientApi));
-    api->start_client = start_client_nop;
-    api->finish = finish_nop;
-    api->tracepoint_maybe_wait = tracepoint_maybe_wait_nop;
-    api->tracepoint_maybe_notify = tracepoint_maybe_notify_nop;
-    api->tracepoint_maybe_do = tracepoint_maybe_do_nop;
-    return api;
+    return &NOP_API;
   }
   void* handle = dlopen(library_path, RTLD_LAZY);
   if (!handle) {
     fprintf(stderr, "%s\n", dlerror());
     exit(1);
   }
-  OrderingClientApi* api = dlsym(handle, "ORDERING_CLIENT_API");
+  const OrderingClientApi* api = dlsym(handle, "ORDERING_CLIENT_API");
   if (!api) {
     fprintf(stderr, "%s\n", dlerror());
     exit(1);
   }
+  // Functions are only ever added at the end of the table, so a larger table is fine.
+  if (api->version != ORDERING_CLIENT_API_VERSION || api->size < sizeof(OrderingClientApi)) {
+    fprintf(
+      stderr, "%s provides version %u of the ordering client API (%zu bytes), but version %u (%zu bytes) is needed\n",
+      library_path, api->version, api->size, ORDERING_CLIENT_API_VERSION, sizeof(OrderingClientApi)
+    );
+    exit(1);
+  }
   return api;
 }
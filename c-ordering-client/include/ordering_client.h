@@ -0,0 +1,401 @@
+#ifndef ORDERING_CLIENT_H
+#define ORDERING_CLIENT_H
+
+/* Generated from src/lib.rs by cbindgen. Do not edit; run update-c-header.sh (ORDSERV_UPDATE_C_HEADER=1) to regenerate. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * The version of [`OrderingClientApi`]. It changes when a function of the table changes or goes
+ * away. Functions that are added go at the end of the table without changing the version, so a
+ * caller may use a function if `size` is large enough for the table to contain it.
+ */
+#define ORDERING_CLIENT_API_VERSION 1
+
+/**
+ * Connect to a server that listens on the `host:port` in the `address` field.
+ */
+#define ORDSERV_TRANSPORT_TCP 0
+
+/**
+ * Use the connection that the process inherited as the file descriptor in the `fd` field.
+ */
+#define ORDSERV_TRANSPORT_INHERITED 1
+
+/**
+ * Use the shared-memory connection that the process inherited as the file descriptor in the `fd`
+ * field.
+ */
+#define ORDSERV_TRANSPORT_SHARED_MEMORY 2
+
+#define ORDSERV_LOG_LEVEL_OFF 0
+
+#define ORDSERV_LOG_LEVEL_ERROR 1
+
+#define ORDSERV_LOG_LEVEL_WARN 2
+
+#define ORDSERV_LOG_LEVEL_INFO 3
+
+#define ORDSERV_LOG_LEVEL_DEBUG 4
+
+#define ORDSERV_LOG_LEVEL_TRACE 5
+
+/**
+ * Functions of the client return their status when they fail.
+ */
+#define ORDSERV_FAILURE_POLICY_RETURN 0
+
+/**
+ * Functions of the client print the error and abort the process when they fail.
+ */
+#define ORDSERV_FAILURE_POLICY_ABORT 1
+
+/**
+ * What became of a call to this library.
+ */
+typedef enum OrdservStatus {
+  ORDSERV_STATUS_OK = 0,
+  /**
+   * An argument was NULL, was not valid UTF-8, or named something that does not exist.
+   */
+  ORDSERV_STATUS_INVALID_ARGUMENT = 1,
+  /**
+   * An environment variable that the server sets for the processes of a run is missing or
+   * malformed, which usually means that the process was not started by the server.
+   */
+  ORDSERV_STATUS_ENVIRONMENT = 2,
+  /**
+   * The library failed in a way that it did not anticipate. The client should not be used
+   * further.
+   */
+  ORDSERV_STATUS_INTERNAL = 3,
+} OrdservStatus;
+
+typedef struct ClientAndJoinHandle {
+  void *client;
+  void *join_handle;
+} ClientAndJoinHandle;
+
+/**
+ * The configuration of a client that is started with `start_client_with_config`. The values that
+ * `start_client` takes from the environment correspond to the fields of this struct.
+ */
+typedef struct OrdservClientConfig {
+  int federate_id;
+  /**
+   * One of the `ORDSERV_TRANSPORT_` constants.
+   */
+  int transport;
+  /**
+   * The `host:port` of the server, for `ORDSERV_TRANSPORT_TCP`.
+   */
+  const char *address;
+  /**
+   * The file descriptor of the connection, for the other transports.
+   */
+  int fd;
+  /**
+   * How long a tracepoint waits before it gives up on the hook invocations that it waits for.
+   */
+  uint64_t wait_timeout_milliseconds;
+  /**
+   * The contents of the precedence file of the run, or NULL to read it from `precedence_path`.
+   */
+  const uint8_t *precedence;
+  size_t precedence_len;
+  const char *precedence_path;
+  /**
+   * The id of the precedence of the run, which the server gives the processes of a run in
+   * `ORDSERV_PRECEDENCE_ID`.
+   */
+  uint32_t precedence_id;
+  /**
+   * One of the `ORDSERV_LOG_LEVEL_` constants.
+   */
+  int log_level;
+  /**
+   * One of the `ORDSERV_FAILURE_POLICY_` constants.
+   */
+  int failure_policy;
+} OrdservClientConfig;
+
+typedef enum OrdservStatus (*Tracepoint)(void *client,
+                                         const char *hook_id,
+                                         int federate_id,
+                                         int sequence_number);
+
+typedef enum OrdservStatus (*TracepointNumbered)(void *client, int hook_number, int sequence_number);
+
+typedef enum OrdservStatus (*TracepointWithAttributes)(void *client,
+                                                       const char *hook_id,
+                                                       int federate_id,
+                                                       int sequence_number,
+                                                       const char *const *keys,
+                                                       const char *const *values,
+                                                       int n_attributes);
+
+typedef enum OrdservStatus (*TracepointNext)(void *client,
+                                             const char *hook_id,
+                                             int federate_id,
+                                             int *sequence_number);
+
+typedef struct OrderingClientApi {
+  /**
+   * The size of the table in bytes.
+   */
+  size_t size;
+  uint32_t version;
+  const char *(*last_error)(void);
+  enum OrdservStatus (*start_client)(int fedid, struct ClientAndJoinHandle *client_and_join_handle);
+  enum OrdservStatus (*finish)(struct ClientAndJoinHandle client_and_join_handle);
+  Tracepoint tracepoint_maybe_wait;
+  Tracepoint tracepoint_maybe_notify;
+  Tracepoint tracepoint_maybe_do;
+  enum OrdservStatus (*register_hook)(void *client,
+                                      const char *hook_id,
+                                      int federate_id,
+                                      int *hook_number);
+  TracepointNumbered tracepoint_maybe_wait_numbered;
+  TracepointNumbered tracepoint_maybe_notify_numbered;
+  TracepointNumbered tracepoint_maybe_do_numbered;
+  TracepointWithAttributes tracepoint_maybe_wait_with_attributes;
+  TracepointWithAttributes tracepoint_maybe_notify_with_attributes;
+  TracepointWithAttributes tracepoint_maybe_do_with_attributes;
+  enum OrdservStatus (*set_thread_namespace)(const char *namespace);
+  TracepointNext tracepoint_maybe_wait_next;
+  TracepointNext tracepoint_maybe_notify_next;
+  TracepointNext tracepoint_maybe_do_next;
+  enum OrdservStatus (*start_client_with_config)(const struct OrdservClientConfig *config,
+                                                 struct ClientAndJoinHandle *client_and_join_handle);
+} OrderingClientApi;
+
+extern const struct OrderingClientApi ORDERING_CLIENT_API;
+
+/**
+ * Describes why the last call to this library from the calling thread failed, or returns NULL if
+ * it succeeded. The string is valid until the next call to this library from the same thread.
+ */
+const char *last_error(void);
+
+/**
+ * Starts the client of federate `fedid` and stores it in `client_and_join_handle`. The client is
+ * configured from the environment variables that the server sets for the processes of a run. It
+ * logs warnings and errors, and its functions return their status when they fail.
+ *
+ * # Safety
+ *
+ * `client_and_join_handle` must point to writable memory. Only operate on the value that is
+ * stored there by passing it, or its "client" field, as the first argument to other functions
+ * defined in this library. It is not necessary to hold a mutex before operating on it; it is
+ * already protected by a mutex internally.
+ *
+ * A process that is forked from a process of the run must not use or finish the client of its
+ * parent. It calls this function again with its own federate id, which it may do at any point of
+ * the run.
+ */
+enum OrdservStatus start_client(int fedid, struct ClientAndJoinHandle *client_and_join_handle);
+
+/**
+ * Like `start_client`, but the client is configured by `config` instead of the environment. The
+ * configuration is copied, so `config` and the memory that it points to may be freed once this
+ * function returns.
+ *
+ * # Safety
+ *
+ * The same as for `start_client`, and `config` must point to a valid `OrdservClientConfig`
+ * whose non-NULL strings are NUL-terminated and whose `precedence`, if it is not NULL, points to
+ * `precedence_len` readable bytes.
+ */
+enum OrdservStatus start_client_with_config(const struct OrdservClientConfig *config,
+                                            struct ClientAndJoinHandle *client_and_join_handle);
+
+/**
+ * Wait for the client thread to finish its final tasks, and GC its resources.
+ *
+ * # Safety
+ *
+ * This function invalidates its argument (by freeing it), even if it fails.
+ */
+enum OrdservStatus finish(struct ClientAndJoinHandle client_and_join_handle);
+
+/**
+ * # Safety
+ *
+ * This function may block the current thread. Its first argument must be the "client" field of
+ * the value stored by `start_client`, and `hook_id` must be a NUL-terminated string.
+ */
+enum OrdservStatus tracepoint_maybe_wait(void *client,
+                                         const char *hook_id,
+                                         int federate_id,
+                                         int sequence_number);
+
+/**
+ * # Safety
+ *
+ * The first argument of this function must be the "client" field of the value stored by
+ * `start_client`, and `hook_id` must be a NUL-terminated string.
+ */
+enum OrdservStatus tracepoint_maybe_notify(void *client,
+                                           const char *hook_id,
+                                           int federate_id,
+                                           int sequence_number);
+
+/**
+ * # Safety
+ *
+ * The same as for `tracepoint_maybe_wait`.
+ */
+enum OrdservStatus tracepoint_maybe_do(void *client,
+                                       const char *hook_id,
+                                       int federate_id,
+                                       int sequence_number);
+
+/**
+ * Puts the invocations that the calling thread makes through the `_next` tracepoints in
+ * `namespace`, so that they are counted apart from those of other threads and a precedence can
+ * refer to them as the invocations of the hook id `<hook_id>/<namespace>`. Takes them out of any
+ * namespace if `namespace` is NULL.
+ *
+ * # Safety
+ *
+ * `namespace` must be NULL or a NUL-terminated string.
+ */
+enum OrdservStatus set_thread_namespace(const char *namespace_);
+
+/**
+ * Like `tracepoint_maybe_wait`, but the sequence number is assigned by the client, atomically and
+ * in the namespace of the calling thread if it has one, and stored in `sequence_number`.
+ *
+ * # Safety
+ *
+ * The same as for `tracepoint_maybe_wait`, and `sequence_number` must be NULL or point to
+ * writable memory.
+ */
+enum OrdservStatus tracepoint_maybe_wait_next(void *client,
+                                              const char *hook_id,
+                                              int federate_id,
+                                              int *sequence_number);
+
+/**
+ * Like `tracepoint_maybe_wait_next`, for `tracepoint_maybe_notify`.
+ *
+ * # Safety
+ *
+ * The same as for `tracepoint_maybe_wait_next`.
+ */
+enum OrdservStatus tracepoint_maybe_notify_next(void *client,
+                                                const char *hook_id,
+                                                int federate_id,
+                                                int *sequence_number);
+
+/**
+ * Like `tracepoint_maybe_wait_next`, for `tracepoint_maybe_do`.
+ *
+ * # Safety
+ *
+ * The same as for `tracepoint_maybe_wait_next`.
+ */
+enum OrdservStatus tracepoint_maybe_do_next(void *client,
+                                            const char *hook_id,
+                                            int federate_id,
+                                            int *sequence_number);
+
+/**
+ * Resolves a hook id to the number by which the `_numbered` tracepoints refer to it, so that the
+ * hook id is looked up only once instead of at every tracepoint, and stores it in `hook_number`.
+ * Stores -1 if the tracepoints of the hook never have anything to do; the `_numbered` tracepoints
+ * return immediately when given -1.
+ *
+ * # Safety
+ *
+ * The first argument of this function must be the "client" field of the value stored by
+ * `start_client`, `hook_id` must be a NUL-terminated string, and `hook_number` must point to
+ * writable memory.
+ */
+enum OrdservStatus register_hook(void *client,
+                                 const char *hook_id,
+                                 int federate_id,
+                                 int *hook_number);
+
+/**
+ * # Safety
+ *
+ * This function may block the current thread. Its first argument must be the "client" field of
+ * the value stored by `start_client`.
+ */
+enum OrdservStatus tracepoint_maybe_wait_numbered(void *client,
+                                                  int hook_number,
+                                                  int sequence_number);
+
+/**
+ * # Safety
+ *
+ * The first argument of this function must be the "client" field of the value stored by
+ * `start_client`.
+ */
+enum OrdservStatus tracepoint_maybe_notify_numbered(void *client,
+                                                    int hook_number,
+                                                    int sequence_number);
+
+/**
+ * # Safety
+ *
+ * The same as for `tracepoint_maybe_wait_numbered`.
+ */
+enum OrdservStatus tracepoint_maybe_do_numbered(void *client, int hook_number, int sequence_number);
+
+/**
+ * Like `tracepoint_maybe_wait`, but the hook invocation also reports `n_attributes` attributes,
+ * the `i`th of which has key `keys[i]` and value `values[i]`, for the precedence to match on.
+ *
+ * # Safety
+ *
+ * This function may block the current thread. Its first argument must be the "client" field of
+ * the value stored by `start_client`, and `keys` and `values` must each point to `n_attributes`
+ * NUL-terminated strings.
+ */
+enum OrdservStatus tracepoint_maybe_wait_with_attributes(void *client,
+                                                         const char *hook_id,
+                                                         int federate_id,
+                                                         int sequence_number,
+                                                         const char *const *keys,
+                                                         const char *const *values,
+                                                         int n_attributes);
+
+/**
+ * Like `tracepoint_maybe_notify`, but the hook invocation also reports attributes, as for
+ * `tracepoint_maybe_wait_with_attributes`.
+ *
+ * # Safety
+ *
+ * The same as for `tracepoint_maybe_wait_with_attributes`.
+ */
+enum OrdservStatus tracepoint_maybe_notify_with_attributes(void *client,
+                                                           const char *hook_id,
+                                                           int federate_id,
+                                                           int sequence_number,
+                                                           const char *const *keys,
+                                                           const char *const *values,
+                                                           int n_attributes);
+
+/**
+ * Like `tracepoint_maybe_do`, but the hook invocation also reports attributes, as for
+ * `tracepoint_maybe_wait_with_attributes`.
+ *
+ * # Safety
+ *
+ * The same as for `tracepoint_maybe_wait_with_attributes`.
+ */
+enum OrdservStatus tracepoint_maybe_do_with_attributes(void *client,
+                                                       const char *hook_id,
+                                                       int federate_id,
+                                                       int sequence_number,
+                                                       const char *const *keys,
+                                                       const char *const *values,
+                                                       int n_attributes);
+
+#endif /* ORDERING_CLIENT_H */
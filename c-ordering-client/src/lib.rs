@@ -1,49 +1,249 @@
+//! The client of the ordering server for programs written in C.
+//!
+//! Programs load this library with `dlopen` and look up [`ORDERING_CLIENT_API`], a table of the
+//! functions of the library that starts with its size and [`ORDERING_CLIENT_API_VERSION`]. The
+//! declarations are in `include/ordering_client.h`, which is generated from this file by
+//! `update-c-header.sh`.
+//!
+//! Every function returns an [`OrdservStatus`]. When it is not [`OrdservStatus::Ok`], `last_error`
+//! describes what went wrong. No function lets a panic unwind into its caller.
+//!
+//! `start_client` configures the client from the environment variables that the server sets for
+//! the processes of a run. A program that wants to configure its clients itself, such as one that
+//! is not started by the server, uses `start_client_with_config` instead.
+
 use std::{
-    env,
-    ffi::{c_char, c_int, c_void},
+    cell::RefCell,
+    ffi::{c_char, c_int, c_void, CStr, CString},
+    panic::{self, AssertUnwindSafe},
     time::Duration,
 };
 
 use ordering_server::{
-    client::{BlockingClient, BlockingClientJoinHandle},
-    connection::{ReadConnection, WriteConnection, UNIX_CONNECTION_MANAGEMENT},
-    FederateId, HookId, HookInvocation, SequenceNumberByFileAndLine,
-    ORDSERV_WAIT_TIMEOUT_MILLISECONDS_ENV_VAR,
+    attributes::Attributes,
+    client::{BlockingClient, ClientConfig, ClientJoinHandle, ClientTransport},
+    intern::{HookNumber, NumberedInvocation},
+    threads, FederateId, HookId, HookInvocation, Precedence, PrecedenceId,
+    SequenceNumberByFileAndLine,
 };
 
-use log::{debug, info};
+use log::{debug, info, LevelFilter};
+
+/// The version of [`OrderingClientApi`]. It changes when a function of the table changes or goes
+/// away. Functions that are added go at the end of the table without changing the version, so a
+/// caller may use a function if `size` is large enough for the table to contain it.
+pub const ORDERING_CLIENT_API_VERSION: u32 = 1;
 
 #[no_mangle]
 pub static ORDERING_CLIENT_API: OrderingClientApi = OrderingClientApi {
+    size: std::mem::size_of::<OrderingClientApi>(),
+    version: ORDERING_CLIENT_API_VERSION,
+    last_error,
     start_client,
     finish,
     tracepoint_maybe_wait,
     tracepoint_maybe_notify,
     tracepoint_maybe_do,
+    register_hook,
+    tracepoint_maybe_wait_numbered,
+    tracepoint_maybe_notify_numbered,
+    tracepoint_maybe_do_numbered,
+    tracepoint_maybe_wait_with_attributes,
+    tracepoint_maybe_notify_with_attributes,
+    tracepoint_maybe_do_with_attributes,
+    set_thread_namespace,
+    tracepoint_maybe_wait_next,
+    tracepoint_maybe_notify_next,
+    tracepoint_maybe_do_next,
+    start_client_with_config,
 };
 
 #[repr(C)]
 pub struct OrderingClientApi {
-    start_client: unsafe extern "C" fn(fedid: c_int) -> ClientAndJoinHandle,
-    finish: unsafe extern "C" fn(client_and_join_handle: ClientAndJoinHandle),
-    tracepoint_maybe_wait: unsafe extern "C" fn(
+    /// The size of the table in bytes.
+    size: usize,
+    version: u32,
+    last_error: unsafe extern "C" fn() -> *const c_char,
+    start_client: unsafe extern "C" fn(
+        fedid: c_int,
+        client_and_join_handle: *mut ClientAndJoinHandle,
+    ) -> OrdservStatus,
+    finish: unsafe extern "C" fn(client_and_join_handle: ClientAndJoinHandle) -> OrdservStatus,
+    tracepoint_maybe_wait: Tracepoint,
+    tracepoint_maybe_notify: Tracepoint,
+    tracepoint_maybe_do: Tracepoint,
+    register_hook: unsafe extern "C" fn(
         client: *mut c_void,
         hook_id: *const c_char,
         federate_id: c_int,
-        sequence_number: c_int,
-    ),
-    tracepoint_maybe_notify: unsafe extern "C" fn(
-        client: *mut c_void,
-        hook_id: *const c_char,
-        federate_id: c_int,
-        sequence_number: c_int,
-    ),
-    tracepoint_maybe_do: unsafe extern "C" fn(
-        client: *mut c_void,
-        hook_id: *const c_char,
-        federate_id: c_int,
-        sequence_number: c_int,
-    ),
+        hook_number: *mut c_int,
+    ) -> OrdservStatus,
+    tracepoint_maybe_wait_numbered: TracepointNumbered,
+    tracepoint_maybe_notify_numbered: TracepointNumbered,
+    tracepoint_maybe_do_numbered: TracepointNumbered,
+    tracepoint_maybe_wait_with_attributes: TracepointWithAttributes,
+    tracepoint_maybe_notify_with_attributes: TracepointWithAttributes,
+    tracepoint_maybe_do_with_attributes: TracepointWithAttributes,
+    set_thread_namespace: unsafe extern "C" fn(namespace: *const c_char) -> OrdservStatus,
+    tracepoint_maybe_wait_next: TracepointNext,
+    tracepoint_maybe_notify_next: TracepointNext,
+    tracepoint_maybe_do_next: TracepointNext,
+    start_client_with_config: unsafe extern "C" fn(
+        config: *const OrdservClientConfig,
+        client_and_join_handle: *mut ClientAndJoinHandle,
+    ) -> OrdservStatus,
+}
+
+type Tracepoint = unsafe extern "C" fn(
+    client: *mut c_void,
+    hook_id: *const c_char,
+    federate_id: c_int,
+    sequence_number: c_int,
+) -> OrdservStatus;
+
+type TracepointNumbered = unsafe extern "C" fn(
+    client: *mut c_void,
+    hook_number: c_int,
+    sequence_number: c_int,
+) -> OrdservStatus;
+
+type TracepointNext = unsafe extern "C" fn(
+    client: *mut c_void,
+    hook_id: *const c_char,
+    federate_id: c_int,
+    sequence_number: *mut c_int,
+) -> OrdservStatus;
+
+type TracepointWithAttributes = unsafe extern "C" fn(
+    client: *mut c_void,
+    hook_id: *const c_char,
+    federate_id: c_int,
+    sequence_number: c_int,
+    keys: *const *const c_char,
+    values: *const *const c_char,
+    n_attributes: c_int,
+) -> OrdservStatus;
+
+/// What became of a call to this library.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrdservStatus {
+    Ok = 0,
+    /// An argument was NULL, was not valid UTF-8, or named something that does not exist.
+    InvalidArgument = 1,
+    /// An environment variable that the server sets for the processes of a run is missing or
+    /// malformed, which usually means that the process was not started by the server.
+    Environment = 2,
+    /// The library failed in a way that it did not anticipate. The client should not be used
+    /// further.
+    Internal = 3,
+}
+
+struct ApiError {
+    status: OrdservStatus,
+    message: String,
+}
+
+impl ApiError {
+    fn invalid_argument(message: impl Into<String>) -> Self {
+        Self {
+            status: OrdservStatus::InvalidArgument,
+            message: message.into(),
+        }
+    }
+    fn environment(message: impl Into<String>) -> Self {
+        Self {
+            status: OrdservStatus::Environment,
+            message: message.into(),
+        }
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Runs the body of a function of the API, turning its error or panic into the status that the
+/// function returns.
+fn guarded(body: impl FnOnce() -> Result<(), ApiError>) -> OrdservStatus {
+    LAST_ERROR.with(|last| *last.borrow_mut() = None);
+    let ApiError { status, message } = match panic::catch_unwind(AssertUnwindSafe(body)) {
+        Ok(Ok(())) => return OrdservStatus::Ok,
+        Ok(Err(e)) => e,
+        Err(payload) => ApiError {
+            status: OrdservStatus::Internal,
+            message: panic_message(payload),
+        },
+    };
+    let message = CString::new(message.replace('\0', "\\0")).unwrap();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+    status
+}
+
+/// Like [`guarded`], for the functions that operate on a client. The body is given the client, and
+/// the failure policy of the client applies to the status.
+unsafe fn guarded_on(
+    client: *mut c_void,
+    body: impl FnOnce(&BlockingClient) -> Result<(), ApiError>,
+) -> OrdservStatus {
+    let state = match as_client_state(client) {
+        Ok(state) => state,
+        Err(e) => return guarded(|| Err(e)),
+    };
+    state.failure_policy.apply(guarded(|| body(&state.client)))
+}
+
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "the client panicked".into())
+}
+
+/// Describes why the last call to this library from the calling thread failed, or returns NULL if
+/// it succeeded. The string is valid until the next call to this library from the same thread.
+#[no_mangle]
+pub extern "C" fn last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(std::ptr::null(), |message| message.as_ptr())
+    })
+}
+
+/// What a client does when one of its functions fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FailurePolicy {
+    /// Return the status, and leave it to the caller to deal with the failure.
+    Return,
+    /// Print the error and abort the process, so that a failure cannot go unnoticed.
+    Abort,
+}
+
+impl FailurePolicy {
+    fn apply(self, status: OrdservStatus) -> OrdservStatus {
+        if self == FailurePolicy::Abort && status != OrdservStatus::Ok {
+            let message = LAST_ERROR.with(|last| {
+                last.borrow()
+                    .as_ref()
+                    .map(|message| message.to_string_lossy().into_owned())
+            });
+            eprintln!(
+                "ordering client: {:?}: {}",
+                status,
+                message.unwrap_or_default()
+            );
+            std::process::abort();
+        }
+        status
+    }
+}
+
+/// What the "client" field of a [`ClientAndJoinHandle`] points to.
+struct ClientState {
+    client: BlockingClient,
+    failure_policy: FailurePolicy,
 }
 
 #[repr(C)]
@@ -52,118 +252,615 @@ pub struct ClientAndJoinHandle {
     join_handle: *mut c_void,
 }
 
+/// Connect to a server that listens on the `host:port` in the `address` field.
+pub const ORDSERV_TRANSPORT_TCP: c_int = 0;
+/// Use the connection that the process inherited as the file descriptor in the `fd` field.
+pub const ORDSERV_TRANSPORT_INHERITED: c_int = 1;
+/// Use the shared-memory connection that the process inherited as the file descriptor in the `fd`
+/// field.
+pub const ORDSERV_TRANSPORT_SHARED_MEMORY: c_int = 2;
+
+pub const ORDSERV_LOG_LEVEL_OFF: c_int = 0;
+pub const ORDSERV_LOG_LEVEL_ERROR: c_int = 1;
+pub const ORDSERV_LOG_LEVEL_WARN: c_int = 2;
+pub const ORDSERV_LOG_LEVEL_INFO: c_int = 3;
+pub const ORDSERV_LOG_LEVEL_DEBUG: c_int = 4;
+pub const ORDSERV_LOG_LEVEL_TRACE: c_int = 5;
+
+/// Functions of the client return their status when they fail.
+pub const ORDSERV_FAILURE_POLICY_RETURN: c_int = 0;
+/// Functions of the client print the error and abort the process when they fail.
+pub const ORDSERV_FAILURE_POLICY_ABORT: c_int = 1;
+
+/// The configuration of a client that is started with `start_client_with_config`. The values that
+/// `start_client` takes from the environment correspond to the fields of this struct.
+#[repr(C)]
+pub struct OrdservClientConfig {
+    pub federate_id: c_int,
+    /// One of the `ORDSERV_TRANSPORT_` constants.
+    pub transport: c_int,
+    /// The `host:port` of the server, for `ORDSERV_TRANSPORT_TCP`.
+    pub address: *const c_char,
+    /// The file descriptor of the connection, for the other transports.
+    pub fd: c_int,
+    /// How long a tracepoint waits before it gives up on the hook invocations that it waits for.
+    pub wait_timeout_milliseconds: u64,
+    /// The contents of the precedence file of the run, or NULL to read it from `precedence_path`.
+    pub precedence: *const u8,
+    pub precedence_len: usize,
+    pub precedence_path: *const c_char,
+    /// The id of the precedence of the run, which the server gives the processes of a run in
+    /// `ORDSERV_PRECEDENCE_ID`.
+    pub precedence_id: u32,
+    /// One of the `ORDSERV_LOG_LEVEL_` constants.
+    pub log_level: c_int,
+    /// One of the `ORDSERV_FAILURE_POLICY_` constants.
+    pub failure_policy: c_int,
+}
+
+/// Starts the client of federate `fedid` and stores it in `client_and_join_handle`. The client is
+/// configured from the environment variables that the server sets for the processes of a run. It
+/// logs warnings and errors, and its functions return their status when they fail.
+///
 /// # Safety
 ///
-/// Only operate on the return value of this function by passing it as the first argument to other
-/// functions defined in this library. It is not necessary to hold a mutex before operating on the
-/// return value of this function; it is already protected by a mutex internally.
+/// `client_and_join_handle` must point to writable memory. Only operate on the value that is
+/// stored there by passing it, or its "client" field, as the first argument to other functions
+/// defined in this library. It is not necessary to hold a mutex before operating on it; it is
+/// already protected by a mutex internally.
+///
+/// A process that is forked from a process of the run must not use or finish the client of its
+/// parent. It calls this function again with its own federate id, which it may do at any point of
+/// the run.
 #[no_mangle]
-pub unsafe extern "C" fn start_client(fedid: c_int) -> ClientAndJoinHandle {
-    simple_logger::init_with_level(log::Level::Warn).unwrap();
+pub unsafe extern "C" fn start_client(
+    fedid: c_int,
+    client_and_join_handle: *mut ClientAndJoinHandle,
+) -> OrdservStatus {
+    guarded(|| {
+        let config = ClientConfig::from_env(FederateId(fedid)).map_err(ApiError::environment)?;
+        start(
+            config,
+            LevelFilter::Warn,
+            FailurePolicy::Return,
+            client_and_join_handle,
+        )
+    })
+}
+
+/// Like `start_client`, but the client is configured by `config` instead of the environment. The
+/// configuration is copied, so `config` and the memory that it points to may be freed once this
+/// function returns.
+///
+/// # Safety
+///
+/// The same as for `start_client`, and `config` must point to a valid `OrdservClientConfig`
+/// whose non-NULL strings are NUL-terminated and whose `precedence`, if it is not NULL, points to
+/// `precedence_len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn start_client_with_config(
+    config: *const OrdservClientConfig,
+    client_and_join_handle: *mut ClientAndJoinHandle,
+) -> OrdservStatus {
+    let mut failure_policy = FailurePolicy::Return;
+    let status = guarded(|| {
+        let config = config
+            .as_ref()
+            .ok_or_else(|| ApiError::invalid_argument("config is NULL"))?;
+        // A failure to read the rest of the configuration is subject to the failure policy.
+        failure_policy = match config.failure_policy {
+            ORDSERV_FAILURE_POLICY_RETURN => FailurePolicy::Return,
+            ORDSERV_FAILURE_POLICY_ABORT => FailurePolicy::Abort,
+            other => {
+                return Err(ApiError::invalid_argument(format!(
+                    "{} is not a failure policy",
+                    other
+                )))
+            }
+        };
+        let log_level = match config.log_level {
+            ORDSERV_LOG_LEVEL_OFF => LevelFilter::Off,
+            ORDSERV_LOG_LEVEL_ERROR => LevelFilter::Error,
+            ORDSERV_LOG_LEVEL_WARN => LevelFilter::Warn,
+            ORDSERV_LOG_LEVEL_INFO => LevelFilter::Info,
+            ORDSERV_LOG_LEVEL_DEBUG => LevelFilter::Debug,
+            ORDSERV_LOG_LEVEL_TRACE => LevelFilter::Trace,
+            other => {
+                return Err(ApiError::invalid_argument(format!(
+                    "{} is not a log level",
+                    other
+                )))
+            }
+        };
+        start(
+            client_config(config)?,
+            log_level,
+            failure_policy,
+            client_and_join_handle,
+        )
+    });
+    failure_policy.apply(status)
+}
+
+unsafe fn client_config(config: &OrdservClientConfig) -> Result<ClientConfig, ApiError> {
+    let transport = match config.transport {
+        ORDSERV_TRANSPORT_TCP => {
+            ClientTransport::Tcp(c_str(config.address, "address")?.to_string())
+        }
+        ORDSERV_TRANSPORT_INHERITED => ClientTransport::Inherited(config.fd),
+        ORDSERV_TRANSPORT_SHARED_MEMORY => ClientTransport::SharedMemory(config.fd),
+        other => {
+            return Err(ApiError::invalid_argument(format!(
+                "{} is not a transport",
+                other
+            )))
+        }
+    };
+    let precedence = if config.precedence.is_null() {
+        let path = c_str(config.precedence_path, "precedence_path")?;
+        let bytes = std::fs::read(path)
+            .map_err(|e| ApiError::invalid_argument(format!("failed to read {}: {}", path, e)))?;
+        Precedence::from_bytes(&bytes)
+    } else {
+        Precedence::from_bytes(std::slice::from_raw_parts(
+            config.precedence,
+            config.precedence_len,
+        ))
+    }
+    .map_err(|e| ApiError::invalid_argument(format!("the precedence is malformed: {}", e)))?;
+    Ok(ClientConfig {
+        federate_id: FederateId(config.federate_id),
+        wait_timeout: Duration::from_millis(config.wait_timeout_milliseconds),
+        transport,
+        precedence,
+        precid: PrecedenceId(config.precedence_id),
+    })
+}
+
+unsafe fn start(
+    config: ClientConfig,
+    log_level: LevelFilter,
+    failure_policy: FailurePolicy,
+    client_and_join_handle: *mut ClientAndJoinHandle,
+) -> Result<(), ApiError> {
+    if client_and_join_handle.is_null() {
+        return Err(ApiError::invalid_argument("client_and_join_handle is NULL"));
+    }
+    // A forked process inherits the logger of its parent, so only the level is ours to set.
+    let _ = simple_logger::init_with_level(log::Level::Trace);
+    log::set_max_level(log_level);
     info!("Starting client");
-    #[allow(clippy::unnecessary_cast)]
-    let (client, join_handle) = ordering_server::client::BlockingClient::start_reusing_connection(
-        FederateId(fedid as i32),
-        Duration::from_millis(
-            env::var(ORDSERV_WAIT_TIMEOUT_MILLISECONDS_ENV_VAR)
-                .unwrap()
-                .parse()
-                .unwrap(),
-        ),
-    );
+    let (client, join_handle) = BlockingClient::start_with_config(config);
     info!("Client started");
-    ClientAndJoinHandle {
-        client: Box::into_raw(Box::new(client)) as *mut c_void,
+    let state = ClientState {
+        client,
+        failure_policy,
+    };
+    *client_and_join_handle = ClientAndJoinHandle {
+        client: Box::into_raw(Box::new(state)) as *mut c_void,
         join_handle: Box::into_raw(Box::new(join_handle)) as *mut c_void,
-    }
+    };
+    Ok(())
 }
 
 /// Wait for the client thread to finish its final tasks, and GC its resources.
 ///
 /// # Safety
 ///
-/// This function invalidates its argument (by freeing it).
-#[no_mangle]
-pub unsafe extern "C" fn finish(client_and_join_handle: ClientAndJoinHandle) {
-    info!("Shutting down client");
-    let client = Box::from_raw(client_and_join_handle.client as *mut BlockingClient);
-    info!("Sending halt message");
-    client.halt.send(()).unwrap();
-    info!("Recovering join handle");
-    let join_handle =
-        Box::from_raw(client_and_join_handle.join_handle as *mut BlockingClientJoinHandle);
-    debug!("Joining client thread");
-    drop(client);
-    let (inner_client, read) = join_handle.join().unwrap();
-    debug!("Client thread joined");
-    (UNIX_CONNECTION_MANAGEMENT.unborrow)((
-        ReadConnection::new(read),
-        WriteConnection {
-            stream: inner_client.connection.stream,
-        },
-    ));
-    debug!("Exiting.");
+/// This function invalidates its argument (by freeing it), even if it fails.
+#[no_mangle]
+pub unsafe extern "C" fn finish(client_and_join_handle: ClientAndJoinHandle) -> OrdservStatus {
+    let mut failure_policy = FailurePolicy::Return;
+    let status = guarded(|| {
+        if client_and_join_handle.client.is_null() || client_and_join_handle.join_handle.is_null() {
+            return Err(ApiError::invalid_argument("the client is NULL"));
+        }
+        info!("Shutting down client");
+        let state = Box::from_raw(client_and_join_handle.client as *mut ClientState);
+        failure_policy = state.failure_policy;
+        info!("Sending halt message");
+        // The client thread is gone if it failed, which joining it reports.
+        let _ = state.client.halt.send(());
+        info!("Recovering join handle");
+        let join_handle =
+            Box::from_raw(client_and_join_handle.join_handle as *mut ClientJoinHandle);
+        debug!("Joining client thread");
+        drop(state);
+        join_handle.join().map_err(|payload| ApiError {
+            status: OrdservStatus::Internal,
+            message: format!("the client thread panicked: {}", panic_message(payload)),
+        })?;
+        debug!("Client thread joined");
+        debug!("Exiting.");
+        Ok(())
+    });
+    failure_policy.apply(status)
 }
 
 /// # Safety
 ///
-/// This function may block the current thread. Its argument must be the "client" field of the
-/// return value of `start_client`.
+/// This function may block the current thread. Its first argument must be the "client" field of
+/// the value stored by `start_client`, and `hook_id` must be a NUL-terminated string.
 #[no_mangle]
 pub unsafe extern "C" fn tracepoint_maybe_wait(
     client: *mut c_void,
     hook_id: *const c_char,
     federate_id: c_int,
     sequence_number: c_int,
-) {
-    let client = &*(client as *mut BlockingClient);
-    client.tracepoint_maybe_wait(make_hook_invocation(hook_id, federate_id, sequence_number));
+) -> OrdservStatus {
+    guarded_on(client, |client| {
+        client.tracepoint_maybe_wait(make_hook_invocation(
+            client,
+            hook_id,
+            federate_id,
+            sequence_number,
+        )?);
+        Ok(())
+    })
 }
 
 /// # Safety
 ///
-/// The argument of this function must be the "client" field of the return value of
-/// `start_client`.
+/// The first argument of this function must be the "client" field of the value stored by
+/// `start_client`, and `hook_id` must be a NUL-terminated string.
 #[no_mangle]
 pub unsafe extern "C" fn tracepoint_maybe_notify(
     client: *mut c_void,
     hook_id: *const c_char,
     federate_id: c_int,
     sequence_number: c_int,
-) {
-    let client = &*(client as *mut BlockingClient);
-    client.tracepoint_maybe_notify(make_hook_invocation(hook_id, federate_id, sequence_number));
+) -> OrdservStatus {
+    guarded_on(client, |client| {
+        client.tracepoint_maybe_notify(make_hook_invocation(
+            client,
+            hook_id,
+            federate_id,
+            sequence_number,
+        )?);
+        Ok(())
+    })
 }
 
 /// # Safety
 ///
-/// The argument of this function must be the "client" field of the return value of
-/// `start_client`.
+/// The same as for `tracepoint_maybe_wait`.
 #[no_mangle]
 pub unsafe extern "C" fn tracepoint_maybe_do(
     client: *mut c_void,
     hook_id: *const c_char,
     federate_id: c_int,
     sequence_number: c_int,
-) {
-    tracepoint_maybe_wait(client, hook_id, federate_id, sequence_number);
-    tracepoint_maybe_notify(client, hook_id, federate_id, sequence_number);
+) -> OrdservStatus {
+    guarded_on(client, |client| {
+        client.tracepoint_maybe_do(make_hook_invocation(
+            client,
+            hook_id,
+            federate_id,
+            sequence_number,
+        )?);
+        Ok(())
+    })
+}
+
+/// Puts the invocations that the calling thread makes through the `_next` tracepoints in
+/// `namespace`, so that they are counted apart from those of other threads and a precedence can
+/// refer to them as the invocations of the hook id `<hook_id>/<namespace>`. Takes them out of any
+/// namespace if `namespace` is NULL.
+///
+/// # Safety
+///
+/// `namespace` must be NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn set_thread_namespace(namespace: *const c_char) -> OrdservStatus {
+    guarded(|| {
+        let namespace = if namespace.is_null() {
+            None
+        } else {
+            Some(c_str(namespace, "namespace")?.to_string())
+        };
+        threads::set_thread_namespace(namespace);
+        Ok(())
+    })
+}
+
+/// Like `tracepoint_maybe_wait`, but the sequence number is assigned by the client, atomically and
+/// in the namespace of the calling thread if it has one, and stored in `sequence_number`.
+///
+/// # Safety
+///
+/// The same as for `tracepoint_maybe_wait`, and `sequence_number` must be NULL or point to
+/// writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn tracepoint_maybe_wait_next(
+    client: *mut c_void,
+    hook_id: *const c_char,
+    federate_id: c_int,
+    sequence_number: *mut c_int,
+) -> OrdservStatus {
+    guarded_on(client, |client| {
+        let hook_invocation = next_invocation(client, hook_id, federate_id, sequence_number)?;
+        client.tracepoint_maybe_wait(hook_invocation);
+        Ok(())
+    })
+}
+
+/// Like `tracepoint_maybe_wait_next`, for `tracepoint_maybe_notify`.
+///
+/// # Safety
+///
+/// The same as for `tracepoint_maybe_wait_next`.
+#[no_mangle]
+pub unsafe extern "C" fn tracepoint_maybe_notify_next(
+    client: *mut c_void,
+    hook_id: *const c_char,
+    federate_id: c_int,
+    sequence_number: *mut c_int,
+) -> OrdservStatus {
+    guarded_on(client, |client| {
+        let hook_invocation = next_invocation(client, hook_id, federate_id, sequence_number)?;
+        client.tracepoint_maybe_notify(hook_invocation);
+        Ok(())
+    })
+}
+
+/// Like `tracepoint_maybe_wait_next`, for `tracepoint_maybe_do`.
+///
+/// # Safety
+///
+/// The same as for `tracepoint_maybe_wait_next`.
+#[no_mangle]
+pub unsafe extern "C" fn tracepoint_maybe_do_next(
+    client: *mut c_void,
+    hook_id: *const c_char,
+    federate_id: c_int,
+    sequence_number: *mut c_int,
+) -> OrdservStatus {
+    guarded_on(client, |client| {
+        let hook_invocation = next_invocation(client, hook_id, federate_id, sequence_number)?;
+        client.tracepoint_maybe_do(hook_invocation);
+        Ok(())
+    })
+}
+
+/// Resolves a hook id to the number by which the `_numbered` tracepoints refer to it, so that the
+/// hook id is looked up only once instead of at every tracepoint, and stores it in `hook_number`.
+/// Stores -1 if the tracepoints of the hook never have anything to do; the `_numbered` tracepoints
+/// return immediately when given -1.
+///
+/// # Safety
+///
+/// The first argument of this function must be the "client" field of the value stored by
+/// `start_client`, `hook_id` must be a NUL-terminated string, and `hook_number` must point to
+/// writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn register_hook(
+    client: *mut c_void,
+    hook_id: *const c_char,
+    federate_id: c_int,
+    hook_number: *mut c_int,
+) -> OrdservStatus {
+    guarded_on(client, |client| {
+        if hook_number.is_null() {
+            return Err(ApiError::invalid_argument("hook_number is NULL"));
+        }
+        let hid = make_hook_invocation(client, hook_id, federate_id, 0)?.hid;
+        *hook_number = client
+            .hook_number(&hid)
+            .map_or(-1, |number| number.0 as c_int);
+        Ok(())
+    })
+}
+
+/// # Safety
+///
+/// This function may block the current thread. Its first argument must be the "client" field of
+/// the value stored by `start_client`.
+#[no_mangle]
+pub unsafe extern "C" fn tracepoint_maybe_wait_numbered(
+    client: *mut c_void,
+    hook_number: c_int,
+    sequence_number: c_int,
+) -> OrdservStatus {
+    guarded_on(client, |client| {
+        if let Some(numbered) = make_numbered_invocation(client, hook_number, sequence_number)? {
+            client.tracepoint_maybe_wait_numbered(numbered);
+        }
+        Ok(())
+    })
+}
+
+/// # Safety
+///
+/// The first argument of this function must be the "client" field of the value stored by
+/// `start_client`.
+#[no_mangle]
+pub unsafe extern "C" fn tracepoint_maybe_notify_numbered(
+    client: *mut c_void,
+    hook_number: c_int,
+    sequence_number: c_int,
+) -> OrdservStatus {
+    guarded_on(client, |client| {
+        if let Some(numbered) = make_numbered_invocation(client, hook_number, sequence_number)? {
+            client.tracepoint_maybe_notify_numbered(numbered);
+        }
+        Ok(())
+    })
+}
+
+/// # Safety
+///
+/// The same as for `tracepoint_maybe_wait_numbered`.
+#[no_mangle]
+pub unsafe extern "C" fn tracepoint_maybe_do_numbered(
+    client: *mut c_void,
+    hook_number: c_int,
+    sequence_number: c_int,
+) -> OrdservStatus {
+    guarded_on(client, |client| {
+        if let Some(numbered) = make_numbered_invocation(client, hook_number, sequence_number)? {
+            client.tracepoint_maybe_do_numbered(numbered);
+        }
+        Ok(())
+    })
+}
+
+/// Like `tracepoint_maybe_wait`, but the hook invocation also reports `n_attributes` attributes,
+/// the `i`th of which has key `keys[i]` and value `values[i]`, for the precedence to match on.
+///
+/// # Safety
+///
+/// This function may block the current thread. Its first argument must be the "client" field of
+/// the value stored by `start_client`, and `keys` and `values` must each point to `n_attributes`
+/// NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn tracepoint_maybe_wait_with_attributes(
+    client: *mut c_void,
+    hook_id: *const c_char,
+    federate_id: c_int,
+    sequence_number: c_int,
+    keys: *const *const c_char,
+    values: *const *const c_char,
+    n_attributes: c_int,
+) -> OrdservStatus {
+    guarded_on(client, |client| {
+        client.tracepoint_maybe_wait_with_attributes(
+            make_hook_invocation(client, hook_id, federate_id, sequence_number)?,
+            &make_attributes(keys, values, n_attributes)?,
+        );
+        Ok(())
+    })
+}
+
+/// Like `tracepoint_maybe_notify`, but the hook invocation also reports attributes, as for
+/// `tracepoint_maybe_wait_with_attributes`.
+///
+/// # Safety
+///
+/// The same as for `tracepoint_maybe_wait_with_attributes`.
+#[no_mangle]
+pub unsafe extern "C" fn tracepoint_maybe_notify_with_attributes(
+    client: *mut c_void,
+    hook_id: *const c_char,
+    federate_id: c_int,
+    sequence_number: c_int,
+    keys: *const *const c_char,
+    values: *const *const c_char,
+    n_attributes: c_int,
+) -> OrdservStatus {
+    guarded_on(client, |client| {
+        client.tracepoint_maybe_notify_with_attributes(
+            make_hook_invocation(client, hook_id, federate_id, sequence_number)?,
+            &make_attributes(keys, values, n_attributes)?,
+        );
+        Ok(())
+    })
+}
+
+/// Like `tracepoint_maybe_do`, but the hook invocation also reports attributes, as for
+/// `tracepoint_maybe_wait_with_attributes`.
+///
+/// # Safety
+///
+/// The same as for `tracepoint_maybe_wait_with_attributes`.
+#[no_mangle]
+pub unsafe extern "C" fn tracepoint_maybe_do_with_attributes(
+    client: *mut c_void,
+    hook_id: *const c_char,
+    federate_id: c_int,
+    sequence_number: c_int,
+    keys: *const *const c_char,
+    values: *const *const c_char,
+    n_attributes: c_int,
+) -> OrdservStatus {
+    guarded_on(client, |client| {
+        client.tracepoint_maybe_do_with_attributes(
+            make_hook_invocation(client, hook_id, federate_id, sequence_number)?,
+            &make_attributes(keys, values, n_attributes)?,
+        );
+        Ok(())
+    })
+}
+
+unsafe fn as_client_state<'a>(client: *mut c_void) -> Result<&'a ClientState, ApiError> {
+    (client as *const ClientState)
+        .as_ref()
+        .ok_or_else(|| ApiError::invalid_argument("the client is NULL"))
+}
+
+unsafe fn c_str<'a>(s: *const c_char, what: &str) -> Result<&'a str, ApiError> {
+    if s.is_null() {
+        return Err(ApiError::invalid_argument(format!("{} is NULL", what)));
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|e| ApiError::invalid_argument(format!("{} is not valid UTF-8: {}", what, e)))
+}
+
+unsafe fn make_numbered_invocation(
+    client: &BlockingClient,
+    hook_number: c_int,
+    sequence_number: c_int,
+) -> Result<Option<NumberedInvocation>, ApiError> {
+    if hook_number < 0 {
+        return Ok(None);
+    }
+    if !client.has_hook_number(HookNumber(hook_number as u32)) {
+        return Err(ApiError::invalid_argument(format!(
+            "hook number {} was not returned by register_hook",
+            hook_number
+        )));
+    }
+    Ok(Some(NumberedInvocation {
+        hook: HookNumber(hook_number as u32),
+        seqnum: SequenceNumberByFileAndLine(sequence_number as u32),
+    }))
+}
+
+unsafe fn make_attributes(
+    keys: *const *const c_char,
+    values: *const *const c_char,
+    n_attributes: c_int,
+) -> Result<Attributes, ApiError> {
+    if n_attributes > 0 && (keys.is_null() || values.is_null()) {
+        return Err(ApiError::invalid_argument("keys or values is NULL"));
+    }
+    (0..n_attributes.max(0) as usize)
+        .map(|i| Ok((c_str(*keys.add(i), "key")?, c_str(*values.add(i), "value")?)))
+        .collect()
+}
+
+unsafe fn next_invocation(
+    client: &BlockingClient,
+    hook_id: *const c_char,
+    federate_id: c_int,
+    sequence_number: *mut c_int,
+) -> Result<HookInvocation, ApiError> {
+    let hid = make_hook_invocation(client, hook_id, federate_id, 0)?.hid;
+    let hook_invocation = client.next_invocation(hid);
+    if !sequence_number.is_null() {
+        *sequence_number = hook_invocation.seqnum.0 as c_int;
+    }
+    Ok(hook_invocation)
 }
 
 unsafe fn make_hook_invocation(
+    client: &BlockingClient,
     hook_id: *const c_char,
     federate_id: c_int,
     sequence_number: c_int,
-) -> HookInvocation {
-    #[allow(clippy::unnecessary_cast)]
-    HookInvocation {
+) -> Result<HookInvocation, ApiError> {
+    if FederateId(federate_id) != client.federate_id() {
+        return Err(ApiError::invalid_argument(format!(
+            "the client belongs to federate {}, not {}",
+            client.federate_id().0,
+            federate_id
+        )));
+    }
+    Ok(HookInvocation {
         hid: HookId::new(
-            std::ffi::CStr::from_ptr(hook_id)
-                .to_str()
-                .unwrap()
-                .to_string(),
-            FederateId(federate_id as i32),
+            c_str(hook_id, "hook_id")?.to_string(),
+            FederateId(federate_id),
         ),
         seqnum: SequenceNumberByFileAndLine(sequence_number as u32),
-    }
+    })
 }
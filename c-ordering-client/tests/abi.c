@@ -0,0 +1,112 @@
+// Calls the library the way the runtime does: through the table that dlopen finds in it.
+
+#include <dlfcn.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "ordering_client.h"
+
+static const OrderingClientApi* api;
+
+#define CHECK(condition)                                                                \
+  do {                                                                                  \
+    if (!(condition)) {                                                                 \
+      fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__, #condition);     \
+      exit(1);                                                                          \
+    }                                                                                   \
+  } while (0)
+
+#define OK(call)                                                                        \
+  do {                                                                                  \
+    OrdservStatus status = (call);                                                      \
+    if (status != ORDSERV_STATUS_OK) {                                                  \
+      fprintf(stderr, "%s:%d: %s returned %d: %s\n", __FILE__, __LINE__, #call, status, \
+              api->last_error());                                                       \
+      exit(1);                                                                          \
+    }                                                                                   \
+  } while (0)
+
+int main(int argc, char** argv) {
+  CHECK(argc == 2);
+  void* handle = dlopen(argv[1], RTLD_NOW | RTLD_LOCAL);
+  if (!handle) {
+    fprintf(stderr, "%s\n", dlerror());
+    return 1;
+  }
+  api = dlsym(handle, "ORDERING_CLIENT_API");
+  CHECK(api);
+  CHECK(api->version == ORDERING_CLIENT_API_VERSION);
+  CHECK(api->size >= sizeof(OrderingClientApi));
+
+  // A process that was not started by the server is told so instead of aborting.
+  char* timeout = strdup(getenv("ORDSERV_WAIT_TIMEOUT"));
+  unsetenv("ORDSERV_WAIT_TIMEOUT");
+  ClientAndJoinHandle notifier;
+  CHECK(api->start_client(0, &notifier) == ORDSERV_STATUS_ENVIRONMENT);
+  CHECK(strstr(api->last_error(), "ORDSERV_WAIT_TIMEOUT"));
+  setenv("ORDSERV_WAIT_TIMEOUT", timeout, 1);
+  free(timeout);
+
+  // The notifier is configured without the environment: it is given the address of the server
+  // and the contents of the precedence file.
+  char address[64];
+  snprintf(address, sizeof(address), "%s:%s", getenv("ORDSERV_HOST"), getenv("ORDSERV_PORT"));
+  FILE* precedence_file = fopen(getenv("ORDSERV_PRECEDENCE_FILE"), "rb");
+  CHECK(precedence_file);
+  unsigned char precedence[1 << 16];
+  size_t precedence_len = fread(precedence, 1, sizeof(precedence), precedence_file);
+  CHECK(precedence_len > 0 && feof(precedence_file));
+  fclose(precedence_file);
+  OrdservClientConfig config = {
+    .federate_id = 0,
+    .transport = 7,
+    .address = address,
+    .fd = -1,
+    .wait_timeout_milliseconds = strtoull(getenv("ORDSERV_WAIT_TIMEOUT"), NULL, 10),
+    .precedence = precedence,
+    .precedence_len = precedence_len,
+    .precedence_path = NULL,
+    .precedence_id = strtoul(getenv("ORDSERV_PRECEDENCE_ID"), NULL, 10),
+    .log_level = ORDSERV_LOG_LEVEL_WARN,
+    .failure_policy = ORDSERV_FAILURE_POLICY_RETURN,
+  };
+  CHECK(api->start_client_with_config(&config, &notifier) == ORDSERV_STATUS_INVALID_ARGUMENT);
+  CHECK(strstr(api->last_error(), "transport"));
+  config.transport = ORDSERV_TRANSPORT_TCP;
+  config.precedence_len = 1;
+  CHECK(api->start_client_with_config(&config, &notifier) == ORDSERV_STATUS_INVALID_ARGUMENT);
+  CHECK(strstr(api->last_error(), "precedence"));
+  config.precedence_len = precedence_len;
+  OK(api->start_client_with_config(&config, &notifier));
+  CHECK(api->last_error() == NULL);
+
+  // The second client sets up the logger a second time.
+  ClientAndJoinHandle waiter;
+  OK(api->start_client(1, &waiter));
+
+  CHECK(api->tracepoint_maybe_do(notifier.client, "\xff", 0, 0) == ORDSERV_STATUS_INVALID_ARGUMENT);
+  CHECK(strstr(api->last_error(), "UTF-8"));
+  CHECK(api->tracepoint_maybe_do(NULL, "A", 0, 0) == ORDSERV_STATUS_INVALID_ARGUMENT);
+  CHECK(api->tracepoint_maybe_do(notifier.client, "A", 1, 0) == ORDSERV_STATUS_INVALID_ARGUMENT);
+  CHECK(api->tracepoint_maybe_do_numbered(notifier.client, 1000, 0) == ORDSERV_STATUS_INVALID_ARGUMENT);
+
+  // The waits below time out, which the server reports, unless the notifications get through.
+  OK(api->tracepoint_maybe_do(notifier.client, "A", 0, 0));
+  int hook_number;
+  OK(api->register_hook(waiter.client, "B", 1, &hook_number));
+  CHECK(hook_number >= 0);
+  OK(api->tracepoint_maybe_wait_numbered(waiter.client, hook_number, 0));
+
+  OK(api->set_thread_namespace("main"));
+  int sequence_number = -1;
+  OK(api->tracepoint_maybe_do_next(notifier.client, "A", 0, &sequence_number));
+  CHECK(sequence_number == 0);
+  sequence_number = -1;
+  OK(api->tracepoint_maybe_do_next(waiter.client, "B", 1, &sequence_number));
+  CHECK(sequence_number == 0);
+
+  OK(api->finish(notifier));
+  OK(api->finish(waiter));
+  return 0;
+}
@@ -0,0 +1,106 @@
+//! Calls the library from a C program that loads it with `dlopen`, the way the runtime does.
+
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use ordering_server::{
+    server::{self, Update},
+    Precedence, ORDSERV_WAIT_TIMEOUT_MILLISECONDS_ENV_VAR,
+};
+
+/// Builds the library with the profile of the tests, since `cargo test` does not build a crate
+/// that is only a `cdylib`, and returns the path that cargo reports for it.
+fn library_path() -> PathBuf {
+    let mut cargo = Command::new(env!("CARGO"));
+    cargo
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .args(["build", "--lib", "--message-format=json"])
+        .args(["-p", env!("CARGO_PKG_NAME")]);
+    if !cfg!(debug_assertions) {
+        cargo.arg("--release");
+    }
+    let output = cargo.output().unwrap();
+    assert!(
+        output.status.success(),
+        "failed to build the library: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .filter(|message| {
+            message["reason"] == "compiler-artifact"
+                && message["target"]["kind"]
+                    .as_array()
+                    .is_some_and(|kinds| kinds.iter().any(|kind| kind == "cdylib"))
+        })
+        .flat_map(|message| message["filenames"].as_array().cloned().unwrap_or_default())
+        .filter_map(|filename| filename.as_str().map(PathBuf::from))
+        .find(|filename| {
+            filename
+                .extension()
+                .is_some_and(|extension| extension == "so")
+        })
+        .expect("cargo did not report the library")
+}
+
+fn compile(source: &str, scratch_dir: &Path) -> PathBuf {
+    let crate_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let executable = scratch_dir.join(source.trim_end_matches(".c"));
+    let status = Command::new("cc")
+        .args(["-Wall", "-Werror", "-o"])
+        .arg(&executable)
+        .arg("-I")
+        .arg(crate_dir.join("include"))
+        .arg(crate_dir.join("tests").join(source))
+        .arg("-ldl")
+        .status()
+        .unwrap();
+    assert!(status.success(), "failed to compile {}", source);
+    executable
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_c_programs_use_the_library_through_its_api_table() {
+    let scratch_dir = std::env::temp_dir().join(format!("ordserv-abi-{}", std::process::id()));
+    std::fs::create_dir_all(&scratch_dir).unwrap();
+    let executable = compile("abi.c", &scratch_dir);
+    let mut server_handle = server::run(0, 1).await.unwrap();
+    let (updates, acks, reports) = &mut server_handle.updates_acks[0];
+    let precedence = Precedence::from_list(
+        2,
+        &[
+            (("A", 0, 0), &[("B", 1, 0)]),
+            (("A/main", 0, 0), &[("B/main", 1, 0)]),
+        ],
+        scratch_dir.clone(),
+        0,
+    );
+    updates.send(Update::Start(precedence)).await.unwrap();
+    let evars = acks.recv().await.unwrap().unwrap();
+    let output = tokio::task::spawn_blocking(move || {
+        Command::new(&executable)
+            .arg(library_path())
+            .envs(evars.0)
+            .env(ORDSERV_WAIT_TIMEOUT_MILLISECONDS_ENV_VAR, "5000")
+            .output()
+            .unwrap()
+    })
+    .await
+    .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    updates.send(Update::Finish).await.unwrap();
+    let report = reports.recv().await.unwrap();
+    assert!(report.is_clean(), "{}", report);
+    assert!(report.timeouts.is_empty(), "{}", report);
+    updates.send(Update::Halt).await.unwrap();
+    server_handle.join().await;
+    std::fs::remove_dir_all(scratch_dir).unwrap();
+}
@@ -0,0 +1,31 @@
+//! Checks that `include/ordering_client.h` declares what the library defines. The header is
+//! checked in so that C programs can be built without building the library; run
+//! `update-c-header.sh` after changing the API.
+
+use std::path::PathBuf;
+
+const UPDATE_HEADER_ENV_VAR: &str = "ORDSERV_UPDATE_C_HEADER";
+
+#[test]
+fn test_header_is_up_to_date() {
+    let crate_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let config = cbindgen::Config::from_file(crate_dir.join("cbindgen.toml")).unwrap();
+    let mut generated = vec![];
+    cbindgen::Builder::new()
+        .with_config(config)
+        .with_src(crate_dir.join("src/lib.rs"))
+        .generate()
+        .expect("Failed to generate the C header")
+        .write(&mut generated);
+    let path = crate_dir.join("include/ordering_client.h");
+    if std::env::var_os(UPDATE_HEADER_ENV_VAR).is_some() {
+        std::fs::write(&path, generated).unwrap();
+        return;
+    }
+    let checked_in = std::fs::read(&path).unwrap_or_default();
+    assert!(
+        checked_in == generated,
+        "{} is out of date; run update-c-header.sh",
+        path.display()
+    );
+}
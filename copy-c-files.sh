@@ -1,3 +1,4 @@
+cp c-ordering-client/include/ordering_client.h reactor-c-ordserv/include/core/
 cp c-ordering-client/examples/c-ordering-client.h reactor-c-ordserv/include/core/
 cp c-ordering-client/examples/c-ordering-client.c reactor-c-ordserv/core/
 cp c-ordering-client/examples/c-ordering-client.c reactor-c-ordserv/core/federated/RTI/
@@ -0,0 +1,127 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use ordering_server::{
+    connection::Connection,
+    memory::{MemoryReadHalf, MemoryWriteHalf},
+    pattern::{HookInvocationPattern, PatternEdge, SeqnumPattern},
+    server::{run_in_memory, ServerHandle, Update},
+    FederateId, Frame, HookId, HookInvocation, Precedence, PrecedenceId, RunId,
+};
+use tokio::{runtime::Runtime, sync::mpsc};
+
+const RUN_ID: u32 = 1;
+const FEDERATE_COUNTS: [usize; 3] = [2, 16, 128];
+const BURST: u32 = 100;
+
+/// A run in which the server forwards every notification of federate 0 to every other federate.
+struct FanOut {
+    server_handle: ServerHandle,
+    precid: PrecedenceId,
+    notifier: Connection<MemoryReadHalf, MemoryWriteHalf>,
+    /// Receives a message whenever one of the other federates reads a frame.
+    arrivals: mpsc::UnboundedReceiver<()>,
+    n_waiters: usize,
+    seqnum: u32,
+}
+
+impl FanOut {
+    async fn start(n_federates: usize) -> Self {
+        let (mut server_handle, connector) = run_in_memory(1).await;
+        let (updates, acks, _) = &mut server_handle.updates_acks[0];
+        let pattern = |hid: &str, fedid| {
+            HookInvocationPattern::new(
+                HookId::new(hid.into(), FederateId(fedid)),
+                SeqnumPattern::Any,
+            )
+        };
+        let mut precedence = Precedence::from_list(n_federates, &[], std::env::temp_dir(), RUN_ID);
+        precedence.pattern_edges = vec![PatternEdge {
+            notifier: pattern("notifier", 0),
+            waiters: (1..n_federates as i32)
+                .map(|fedid| pattern("waiter", fedid))
+                .collect(),
+        }];
+        updates.send(Update::Start(precedence)).await.unwrap();
+        let precid = acks.recv().await.unwrap().unwrap().precedence_id().unwrap();
+        let connect = |fedid| connector.connect_as(precid, FederateId(fedid), RunId(RUN_ID));
+        let notifier = connect(0).await.unwrap();
+        let (arrived, arrivals) = mpsc::unbounded_channel();
+        for fedid in 1..n_federates as i32 {
+            let mut waiter = connect(fedid).await.unwrap();
+            let arrived = arrived.clone();
+            tokio::spawn(async move {
+                while let Ok(Some(_)) = waiter.read_frame().await {
+                    if arrived.send(()).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        FanOut {
+            server_handle,
+            precid,
+            notifier,
+            arrivals,
+            n_waiters: n_federates - 1,
+            seqnum: 0,
+        }
+    }
+    /// Sends `n` notifications and waits until every other federate has read all of them.
+    async fn round(&mut self, n: u32) {
+        for _ in 0..n {
+            let notification = HookInvocation::from_short(("notifier", 0, self.seqnum));
+            self.seqnum += 1;
+            self.notifier
+                .write_frame(&Frame::notify(self.precid.0, &notification, RUN_ID))
+                .await
+                .unwrap();
+        }
+        for _ in 0..n as usize * self.n_waiters {
+            self.arrivals.recv().await.unwrap();
+        }
+    }
+    async fn finish(self) {
+        let (updates, _, _) = &self.server_handle.updates_acks[0];
+        updates.send(Update::Finish).await.unwrap();
+        updates.send(Update::Halt).await.unwrap();
+        self.server_handle.join().await;
+    }
+}
+
+fn bench_fan_out(c: &mut Criterion, rt: &Runtime, name: &str, burst: u32) {
+    let mut group = c.benchmark_group(name);
+    group.sample_size(10);
+    for n_federates in FEDERATE_COUNTS {
+        let mut fan_out = rt.block_on(FanOut::start(n_federates));
+        group.throughput(Throughput::Elements(
+            burst as u64 * (n_federates as u64 - 1),
+        ));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(n_federates),
+            &burst,
+            |b, burst| b.iter(|| rt.block_on(fan_out.round(*burst))),
+        );
+        rt.block_on(fan_out.finish());
+    }
+    group.finish();
+}
+
+/// How long it takes for one notification to reach every federate that waits for it.
+fn latency_benchmark(c: &mut Criterion) {
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    bench_fan_out(c, &rt, "forwarding latency", 1);
+}
+
+/// How many frames per second the server forwards when notifications arrive in bursts.
+fn throughput_benchmark(c: &mut Criterion) {
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    bench_fan_out(c, &rt, "forwarding throughput", BURST);
+}
+
+criterion_group!(benches, latency_benchmark, throughput_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,14 @@
+use std::time::Duration;
+
+use ordering_server::{client::AsyncClient, FederateId, HookInvocation};
+
+#[tokio::main]
+async fn main() {
+    let client =
+        AsyncClient::start(("127.0.0.1", 15045), FederateId(0), Duration::from_secs(5)).await;
+    println!("Hello");
+    client
+        .tracepoint_maybe_notify(HookInvocation::from_short(("A", 0, 0)))
+        .await;
+    client.finish().await;
+}
@@ -0,0 +1,15 @@
+use std::time::Duration;
+
+use ordering_server::{client::AsyncClient, FederateId, HookInvocation};
+
+#[tokio::main]
+async fn main() {
+    let client =
+        AsyncClient::start(("127.0.0.1", 15045), FederateId(1), Duration::from_secs(5)).await;
+    println!("B did startup");
+    client
+        .tracepoint_maybe_wait(HookInvocation::from_short(("B", 1, 0)))
+        .await;
+    println!("      world.");
+    client.finish().await;
+}
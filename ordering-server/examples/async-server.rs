@@ -0,0 +1,40 @@
+use std::process::Command;
+
+use ordering_server::{server, Precedence};
+
+#[tokio::main]
+async fn main() {
+    let mut server_handle = server::run(15045, 1).await.unwrap();
+    let precedence = Precedence::from_list(2, &[(("A", 0, 0), &[("B", 1, 0)])], "/tmp".into(), 0);
+    server_handle.updates_acks[0]
+        .0
+        .send(server::Update::Start(precedence))
+        .await
+        .unwrap();
+    let evars = server_handle.updates_acks[0]
+        .1
+        .recv()
+        .await
+        .unwrap()
+        .unwrap_or_else(|e| panic!("Precedence was rejected: {}", e));
+    println!("Received ack");
+    let mut child_a = Command::new("cargo")
+        .args(["run", "--example", "async-client-a"])
+        .envs(evars.0.clone())
+        .spawn()
+        .expect("failed to execute process");
+    let mut child_b = Command::new("cargo")
+        .args(["run", "--example", "async-client-b"])
+        .envs(evars.0)
+        .spawn()
+        .expect("failed to execute process");
+    child_a.wait().unwrap();
+    child_b.wait().unwrap();
+    server_handle.updates_acks[0]
+        .0
+        .send(server::Update::Halt)
+        .await
+        .unwrap();
+    server_handle.join().await;
+    println!("Server finished");
+}
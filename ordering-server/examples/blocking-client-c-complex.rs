@@ -3,26 +3,29 @@ use std::time::Duration;
 use ordering_server::{FederateId, HookInvocation};
 
 fn main() {
-    // simple_logger::SimpleLogger::new().init().unwrap();
-    // let (client, _jh) = ordering_server::client::BlockingClient::start(
-    //     ("127.0.0.1", 15045),
-    //     FederateId(2),
-    //     Duration::from_secs(5),
-    // );
-    // client.tracepoint_maybe_do(HookInvocation::from_short(("C99", 2, 0)));
-    // client.tracepoint_maybe_do(HookInvocation::from_short(("C0", 2, 0)));
-    // println!("            of");
-    // client.tracepoint_maybe_do(HookInvocation::from_short(("C0", 2, 1)));
-    // client.tracepoint_maybe_do(HookInvocation::from_short(("C1", 2, 0)));
-    // println!("            sentence");
-    // client.tracepoint_maybe_do(HookInvocation::from_short(("C1", 2, 1)));
-    // client.tracepoint_maybe_do(HookInvocation::from_short(("C1", 2, 2)));
-    // client.tracepoint_maybe_do(HookInvocation::from_short(("C1", 2, 3)));
-    // println!("            ordered");
-    // client.tracepoint_maybe_do(HookInvocation::from_short(("C1", 2, 4)));
-    // client.tracepoint_maybe_wait(HookInvocation::from_short(("C2", 2, 0)));
-    // println!("            the");
-    // client.tracepoint_maybe_notify(HookInvocation::from_short(("C2", 2, 0)));
-    // client.tracepoint_maybe_do(HookInvocation::from_short(("C2", 2, 1)));
-    // println!("            .");
+    simple_logger::SimpleLogger::new().init().unwrap();
+    let (client, jh) = ordering_server::client::BlockingClient::start(
+        ("127.0.0.1", 15045),
+        FederateId(2),
+        Duration::from_secs(5),
+    );
+    client.tracepoint_maybe_do(HookInvocation::from_short(("C99", 2, 0)));
+    client.tracepoint_maybe_do(HookInvocation::from_short(("C0", 2, 0)));
+    println!("            of");
+    client.tracepoint_maybe_do(HookInvocation::from_short(("C0", 2, 1)));
+    client.tracepoint_maybe_do(HookInvocation::from_short(("C1", 2, 0)));
+    println!("            sentence");
+    client.tracepoint_maybe_do(HookInvocation::from_short(("C1", 2, 1)));
+    client.tracepoint_maybe_do(HookInvocation::from_short(("C1", 2, 2)));
+    client.tracepoint_maybe_do(HookInvocation::from_short(("C1", 2, 3)));
+    println!("            ordered");
+    client.tracepoint_maybe_do(HookInvocation::from_short(("C1", 2, 4)));
+    client.tracepoint_maybe_wait(HookInvocation::from_short(("C2", 2, 0)));
+    println!("            the");
+    client.tracepoint_maybe_notify(HookInvocation::from_short(("C2", 2, 0)));
+    client.tracepoint_maybe_do(HookInvocation::from_short(("C2", 2, 1)));
+    println!("            .");
+    client.halt.send(()).unwrap();
+    drop(client);
+    jh.join().unwrap();
 }
@@ -0,0 +1,237 @@
+//! Static checks that are run on every [`Precedence`] before any client is allowed to act on it.
+
+use std::{collections::HashMap, fmt::Display};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{HookId, HookInvocation, Join, Precedence};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EdgeKind {
+    /// The source notifies the destination, so the destination cannot proceed until the source
+    /// has happened.
+    Notifies,
+    /// The source and the destination are invocations of the same hook, and the source has the
+    /// smaller sequence number, so the source happens first in the federate that executes them.
+    ProgramOrder,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Edge {
+    pub from: HookInvocation,
+    pub to: HookInvocation,
+    pub kind: EdgeKind,
+}
+
+/// A cycle of "must happen before" edges. The destination of each edge is the source of the next,
+/// and the destination of the last edge is the source of the first.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cycle(pub Vec<Edge>);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PrecedenceError {
+    /// Every hook invocation on the cycle would wait for its predecessor forever, so the run can
+    /// only end by timing out.
+    Cycle(Cycle),
+}
+
+impl Display for Cycle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(edge) = self.0.first() {
+            write!(f, "{}", edge.from)?;
+        }
+        for edge in &self.0 {
+            match edge.kind {
+                EdgeKind::Notifies => write!(f, " --notifies--> {}", edge.to)?,
+                EdgeKind::ProgramOrder => write!(f, " --program order--> {}", edge.to)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Display for PrecedenceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PrecedenceError::Cycle(cycle) => {
+                write!(
+                    f,
+                    "the precedence cannot be satisfied because it contains a cycle: {}",
+                    cycle
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for PrecedenceError {}
+
+impl Precedence {
+    /// Returns an error if no execution of the clients can satisfy this precedence. Only the exact
+    /// precedences in `sender2waiters` are checked; `pattern_edges` are taken on trust.
+    ///
+    /// A waiter that joins its notifiers with [`Join::Any`] may proceed after whichever of them
+    /// happens first, so the notifications of such a waiter are only taken into account if it has a
+    /// single notifier. This means that a precedence in which every notifier of such a waiter is
+    /// stuck is not rejected.
+    pub fn check(&self) -> Result<(), PrecedenceError> {
+        match self.find_cycle() {
+            Some(cycle) => Err(PrecedenceError::Cycle(cycle)),
+            None => Ok(()),
+        }
+    }
+
+    /// Finds a cycle in the graph whose edges are the notifications of this precedence together
+    /// with the program order between invocations of the same hook, if there is one.
+    pub fn find_cycle(&self) -> Option<Cycle> {
+        let graph = self.happens_before_graph();
+        // Iterative depth-first search. `visited[n]` is true iff `n` is on the current path.
+        let mut visited = HashMap::with_capacity(graph.len());
+        for &root in graph.keys() {
+            if visited.contains_key(root) {
+                continue;
+            }
+            visited.insert(root, true);
+            let mut path: Vec<(&HookInvocation, usize)> = vec![(root, 0)];
+            while let Some((node, next_edge)) = path.last_mut() {
+                let edges = &graph[*node];
+                if *next_edge == edges.len() {
+                    visited.insert(*node, false);
+                    path.pop();
+                    continue;
+                }
+                let (succ, _) = edges[*next_edge];
+                *next_edge += 1;
+                match visited.get(succ) {
+                    Some(true) => {
+                        let start = path.iter().position(|(n, _)| *n == succ).unwrap();
+                        let nodes: Vec<_> = path[start..].iter().map(|(n, _)| *n).collect();
+                        return Some(Cycle(
+                            (0..nodes.len())
+                                .map(|i| {
+                                    let from = nodes[i];
+                                    let to = nodes[(i + 1) % nodes.len()];
+                                    let (_, kind) =
+                                        *graph[from].iter().find(|(n, _)| *n == to).unwrap();
+                                    Edge {
+                                        from: from.clone(),
+                                        to: to.clone(),
+                                        kind,
+                                    }
+                                })
+                                .collect(),
+                        ));
+                    }
+                    Some(false) => {}
+                    None => {
+                        visited.insert(succ, true);
+                        path.push((succ, 0));
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    fn happens_before_graph(&self) -> HashMap<&HookInvocation, Vec<(&HookInvocation, EdgeKind)>> {
+        let mut graph: HashMap<&HookInvocation, Vec<_>> = HashMap::new();
+        let mut by_hook: HashMap<&HookId, Vec<&HookInvocation>> = HashMap::new();
+        let mut n_notifiers: HashMap<&HookInvocation, usize> = HashMap::new();
+        for waiter in self.sender2waiters.values().flatten() {
+            *n_notifiers.entry(waiter).or_default() += 1;
+        }
+        for (notifier, waiters) in &self.sender2waiters {
+            for waiter in waiters {
+                graph.entry(waiter).or_default();
+                if self.join(waiter) == Join::Any && n_notifiers[waiter] > 1 {
+                    continue;
+                }
+                graph
+                    .entry(notifier)
+                    .or_default()
+                    .push((waiter, EdgeKind::Notifies));
+            }
+            graph.entry(notifier).or_default();
+        }
+        for &hinvoc in graph.keys() {
+            by_hook.entry(&hinvoc.hid).or_default().push(hinvoc);
+        }
+        for mut invocations in by_hook.into_values() {
+            invocations.sort_by_key(|hinvoc| hinvoc.seqnum);
+            invocations.dedup();
+            for pair in invocations.windows(2) {
+                graph
+                    .get_mut(pair[0])
+                    .unwrap()
+                    .push((pair[1], EdgeKind::ProgramOrder));
+            }
+        }
+        graph
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn precedence(sender2waiters: &[crate::PrecedenceElement]) -> Precedence {
+        Precedence::from_list(3, sender2waiters, "/tmp".into(), 0)
+    }
+
+    #[test]
+    fn test_acyclic_precedence_is_accepted() {
+        let p = precedence(&[
+            (("A", 0, 0), &[("B", 1, 0), ("C", 2, 0)]),
+            (("B", 1, 0), &[("C", 2, 1)]),
+            (("A", 0, 1), &[("B", 1, 1)]),
+        ]);
+        assert_eq!(p.check(), Ok(()));
+    }
+
+    #[test]
+    fn test_cycle_through_program_order_is_rejected() {
+        // B:1 cannot notify A:0 because B:1 happens after B:0, which waits for A:1, which happens
+        // after A:0.
+        let p = precedence(&[(("A", 0, 1), &[("B", 1, 0)]), (("B", 1, 1), &[("A", 0, 0)])]);
+        let Err(PrecedenceError::Cycle(Cycle(edges))) = p.check() else {
+            panic!("expected a cycle");
+        };
+        assert_eq!(edges.len(), 4);
+        for (i, edge) in edges.iter().enumerate() {
+            assert_eq!(edge.to, edges[(i + 1) % edges.len()].from);
+            let expected_kind = if edge.from.hid == edge.to.hid {
+                EdgeKind::ProgramOrder
+            } else {
+                EdgeKind::Notifies
+            };
+            assert_eq!(edge.kind, expected_kind);
+        }
+    }
+
+    #[test]
+    fn test_self_notification_is_rejected() {
+        let p = precedence(&[(("A", 0, 0), &[("A", 0, 0)])]);
+        assert_eq!(
+            p.check(),
+            Err(PrecedenceError::Cycle(Cycle(vec![Edge {
+                from: HookInvocation::from_short(("A", 0, 0)),
+                to: HookInvocation::from_short(("A", 0, 0)),
+                kind: EdgeKind::Notifies,
+            }])))
+        );
+    }
+
+    #[test]
+    fn test_cycle_through_any_join_is_accepted_unless_all_is_required() {
+        // B:0 can proceed after C:0 without waiting for A:0, which happens after B:0.
+        let mut p = precedence(&[
+            (("A", 0, 0), &[("B", 1, 0)]),
+            (("C", 2, 0), &[("B", 1, 0)]),
+            (("B", 1, 0), &[("A", 0, 0)]),
+        ]);
+        assert_eq!(p.check(), Ok(()));
+        p.joins
+            .insert(HookInvocation::from_short(("B", 1, 0)), Join::All);
+        assert!(p.check().is_err());
+    }
+}
@@ -0,0 +1,112 @@
+//! Attributes that a tracepoint reports about the event at which it is invoked.
+//!
+//! A [`HookInvocation`](crate::HookInvocation) tells the invocations of a hook apart by how many
+//! times the hook has been invoked before. When timing changes how many times a line runs before
+//! the point of interest, a precedence that names the invocation by sequence number lands on the
+//! wrong one. A tracepoint may instead report what it is doing, such as the kind of event, its tag
+//! and microstep, and the federate on the other end, and a
+//! [`HookInvocationPattern`](crate::pattern::HookInvocationPattern) may require those attributes.
+//! For example, "the RTI sends TAG(10ms) to federate 1 after it receives LTC(10ms) from federate 0"
+//! is
+//!
+//! ```
+//! # use ordering_server::{attributes::*, pattern::*, FederateId, HookId};
+//! let rti = |hid: &str| HookId::new(hid.into(), FederateId(-1));
+//! PatternEdge {
+//!     notifier: HookInvocationPattern::new(rti("receive"), SeqnumPattern::Any).with_attributes(
+//!         Attributes::new().with(EVENT, "LTC").with(TAG, 10_000_000).with(PEER, 0),
+//!     ),
+//!     waiters: vec![HookInvocationPattern::new(rti("send"), SeqnumPattern::Any).with_attributes(
+//!         Attributes::new().with(EVENT, "TAG").with(TAG, 10_000_000).with(PEER, 1),
+//!     )],
+//! };
+//! ```
+//!
+//! Attributes are compared as strings, so the tracepoints and the precedence must agree on how
+//! values are formatted. The keys below are the ones that the tracepoints of the runtime report;
+//! any other key may be used as well.
+
+use std::{collections::BTreeMap, fmt::Display};
+
+use serde::{Deserialize, Serialize};
+
+/// The kind of event, such as `TAG` or `LTC`.
+pub const EVENT: &str = "event";
+/// The tag of the event, in nanoseconds.
+pub const TAG: &str = "tag";
+pub const MICROSTEP: &str = "microstep";
+/// The federate on the other end of the event, such as the receiver of a message.
+pub const PEER: &str = "peer";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Attributes(BTreeMap<String, String>);
+
+impl Attributes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn with(mut self, key: impl Into<String>, value: impl ToString) -> Self {
+        self.insert(key, value);
+        self
+    }
+    pub fn insert(&mut self, key: impl Into<String>, value: impl ToString) {
+        self.0.insert(key.into(), value.to_string());
+    }
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(String::as_str)
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+    /// Whether `reported` has every attribute of `self`, with the same value. Attributes that are
+    /// reported but not required are ignored.
+    pub fn are_satisfied_by(&self, reported: &Attributes) -> bool {
+        self.iter()
+            .all(|(key, value)| reported.get(key) == Some(value))
+    }
+}
+
+impl<K: Into<String>, V: ToString> FromIterator<(K, V)> for Attributes {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        let mut attributes = Attributes::new();
+        for (key, value) in iter {
+            attributes.insert(key, value);
+        }
+        attributes
+    }
+}
+
+impl Display for Attributes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{{")?;
+        for (i, (key, value)) in self.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}={}", key, value)?;
+        }
+        write!(f, "}}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_required_attributes_must_all_be_reported() {
+        let required = Attributes::new().with(EVENT, "TAG").with(PEER, 1);
+        assert!(required.are_satisfied_by(&required.clone().with(MICROSTEP, 0)));
+        assert!(!required.are_satisfied_by(&Attributes::new().with(EVENT, "TAG")));
+        assert!(!required.are_satisfied_by(&Attributes::new().with(EVENT, "TAG").with(PEER, 2)));
+        assert!(Attributes::new().are_satisfied_by(&required));
+        assert_eq!(required.to_string(), "{event=TAG, peer=1}");
+    }
+}
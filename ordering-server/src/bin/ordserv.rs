@@ -0,0 +1,69 @@
+use std::{path::PathBuf, time::Duration};
+
+use clap::Parser;
+
+use ordering_server::{control, server};
+
+/// Runs an ordering server that test drivers control through a Unix socket. See the documentation
+/// of `ordering_server::control` for the protocol.
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    /// Where to create the control socket.
+    control_socket: PathBuf,
+
+    /// The TCP port on which the processes of each run connect to the server. An ephemeral port
+    /// is used by default; either way, the port is part of the environment returned for each run.
+    #[arg(short, long, default_value_t = 0)]
+    port: u16,
+
+    /// The address on which to listen for the processes of the runs, such as 0.0.0.0 for
+    /// processes that run in containers.
+    #[arg(long, default_value = "127.0.0.1")]
+    bind: String,
+
+    /// The host that the processes of the runs connect to, as they see it. Defaults to the bind
+    /// address.
+    #[arg(long)]
+    advertise: Option<String>,
+
+    /// How long the processes of each run wait for a notification before giving up. If this is
+    /// not given, the test driver must set the wait timeout itself.
+    #[arg(short, long)]
+    wait_timeout_millis: Option<u64>,
+
+    /// Step through the runs in step mode from this terminal instead of through the control
+    /// socket.
+    #[arg(long)]
+    step: bool,
+}
+
+#[tokio::main]
+async fn main() {
+    simple_logger::init_with_level(log::Level::Info).unwrap();
+    let args = Cli::parse();
+    let advertised_host = args.advertise.as_deref().unwrap_or(&args.bind);
+    let server_handle = match server::run_on(&args.bind, args.port, advertised_host, 0).await {
+        Ok(server_handle) => server_handle,
+        Err(e) => {
+            eprintln!(
+                "ordserv: could not listen on {}:{}: {}",
+                args.bind, args.port, e
+            );
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = control::serve(
+        &args.control_socket,
+        server_handle,
+        args.wait_timeout_millis.map(Duration::from_millis),
+        args.step,
+    )
+    .await
+    {
+        eprintln!("ordserv: {}", e);
+        std::process::exit(1);
+    }
+    // The prompt may still be reading a line from stdin, which the runtime would wait for.
+    std::process::exit(0);
+}
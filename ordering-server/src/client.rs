@@ -1,23 +1,38 @@
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     env,
+    os::fd::RawFd,
+    path::{Path, PathBuf},
     sync::{Arc, Condvar, Mutex},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use log::{debug, info, warn};
+use log::{debug, error, info, warn};
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
-    net::{tcp::OwnedWriteHalf, unix, TcpStream, ToSocketAddrs},
+    net::{tcp, tcp::OwnedWriteHalf, unix, TcpStream, ToSocketAddrs},
     select,
-    sync::{mpsc, watch},
+    sync::{watch, Notify},
     task::JoinHandle,
 };
 
 use crate::{
-    connection::{Connection, WriteConnection, UNIX_CONNECTION_MANAGEMENT},
-    server::{PRECEDENCE_FILE_NAME, PRECEDENCE_ID_NAME},
-    FederateId, Frame, HookInvocation, Precedence, PrecedenceId,
+    attributes::Attributes,
+    connection::{
+        Connection, FrameError, ReadConnection, WriteConnection, UNIX_CONNECTION_MANAGEMENT,
+    },
+    delay::Delay,
+    intern::{HookNumber, HookTable, NumberedInvocation},
+    memory::{MemoryConnector, MemoryReadHalf, MemoryWriteHalf},
+    pattern::{PatternEdge, PatternIndex},
+    protocol::{FrameKind, PROTOCOL_VERSION},
+    server::{evar_name_for, PRECEDENCE_FILE_NAME, PRECEDENCE_ID_NAME},
+    shm::{ShmReadHalf, ShmWriteHalf, SHARED_MEMORY_TRANSPORT_NAME, SHM_CONNECTION_MANAGEMENT},
+    threads::SequenceNumbers,
+    timeline::{EventKind, Recorder},
+    EnvironmentVariables, FederateId, Frame, HookId, HookInvocation, Join, Precedence,
+    PrecedenceId, ORDSERV_HOST_ENV_VAR, ORDSERV_PORT_ENV_VAR, ORDSERV_TRANSPORT_ENV_VAR,
+    ORDSERV_WAIT_TIMEOUT_MILLISECONDS_ENV_VAR,
 };
 
 pub struct Client<W>
@@ -26,25 +41,22 @@ where
 {
     pub connection: WriteConnection<W>, // FIXME: should be private
 }
-pub struct ChannelClient<W>
-where
-    W: AsyncWriteExt + Unpin,
-{
-    #[allow(dead_code)] // FIXME: un-suppress this feature
-    client: Client<W>,
-    pub frames: mpsc::Receiver<Frame>,
-}
 
 pub struct BlockingClient {
-    requires_ok_to_proceed: HashSet<HookInvocation>,
-    requires_notify: HashSet<HookInvocation>,
-    ok_to_proceed: Arc<Mutex<HashSet<HookInvocation>>>,
+    requirements: Arc<Requirements>,
+    /// The hooks that [`BlockingClient::hook_number`] numbered although the precedence does not
+    /// mention them. They are numbered from the end of the hook table of the precedence on.
+    unlisted_hooks: Mutex<Vec<HookId>>,
+    sequence_numbers: SequenceNumbers,
+    ok_to_proceed: Arc<Mutex<Permissions>>,
     ok_cvar: Arc<Condvar>,
     notification_sender: tokio::sync::mpsc::UnboundedSender<Frame>,
     precid: PrecedenceId,
     fedid: FederateId,
     run_id: u32,
     wait_timeout: Duration,
+    recorder: Option<Arc<Recorder>>,
+    scratch_dir: PathBuf,
     pub halt: watch::Sender<()>, // FIXME: should be private
 }
 
@@ -78,14 +90,20 @@ where
                     select! {
                         frame = read.read_frame() => {
                             match frame {
-                                Some(frame) => {
+                                Ok(Some(frame)) if frame.kind == FrameKind::Hello => {
+                                    info!(target: "client", "Handshake acknowledged by the server (protocol version {})", PROTOCOL_VERSION);
+                                }
+                                Ok(Some(frame)) => {
                                     debug!("Invoking callback on frame: {:?}", frame);
                                     callback(frame);
                                 }
-                                None => {
+                                Ok(None) => {
                                     info!(target: "client", "Connection closed");
                                     panic!("The client is supposed to be the one to close the connection, not the server. The server can close the connection if this client is a straggler from a previous run.");
                                 }
+                                Err(e) => {
+                                    panic!("Failed to read frame from the ordering server: {}", e);
+                                }
                             }
                         }
                         _ = halt.changed() => {
@@ -99,65 +117,53 @@ where
             }),
         )
     }
-    pub async fn write(&mut self, frame: Frame) {
-        self.connection.write_frame(frame).await;
-    }
-}
-
-// impl ChannelClient<tcp::OwnedWriteHalf> {
-//     pub async fn start<T: ToSocketAddrs + std::fmt::Debug>(
-//         addr: T,
-//     ) -> (ChannelClient<tcp::OwnedWriteHalf>, JoinHandle<()>) {
-//         ChannelClient::start_from_socket(socket_from_addr(addr).await.into_split()).await
-//     }
-// }
-
-// impl<W> ChannelClient<W>
-// where
-//     W: AsyncWriteExt + Unpin,
-// {
-//     pub async fn start_from_socket<R: AsyncReadExt + Unpin + Send + 'static>(
-//         socket: (R, W),
-//     ) -> (ChannelClient<W>, JoinHandle<()>) {
-//         let (frames_sender, frames_receiver) = mpsc::channel(1);
-//         let (client, join_handle) = Client::start_from_socket(
-//             socket,
-//             Box::new(move |frame| {
-//                 debug!("Forwarding frame to parent: {:?}", frame);
-//                 frames_sender.clone().try_send(frame).unwrap();
-//             }),
-//         )
-//         .await;
-//         (
-//             ChannelClient {
-//                 client,
-//                 frames: frames_receiver,
-//             },
-//             join_handle,
-//         )
-//     }
-//     pub async fn write(&mut self, frame: Frame) {
-//         self.client.write(frame).await;
-//     }
-// }
-
-// impl BlockingClient {
-//     pub fn start<T: ToSocketAddrs + std::fmt::Debug>(
-//         addr: T,
-//         federate_id: FederateId,
-//         wait_timeout: Duration,
-//     ) -> (
-//         BlockingClient,
-//         std::thread::JoinHandle<Client<tokio::io::BufWriter<tokio::net::unix::OwnedWriteHalf>>>,
-//     ) {
-//         let rt = tokio::runtime::Builder::new_multi_thread()
-//             .enable_io()
-//             .build()
-//             .unwrap();
-//         let socket = rt.block_on(socket_from_addr(addr));
-//         Self::start_from_socket(rt, federate_id, wait_timeout, socket.into_split())
-//     }
-// }
+    pub async fn write(&mut self, frame: &Frame) -> Result<(), FrameError> {
+        self.connection.write_frame(frame).await
+    }
+}
+
+pub type TcpBlockingClientJoinHandle =
+    std::thread::JoinHandle<(Client<tcp::OwnedWriteHalf>, tcp::OwnedReadHalf)>;
+
+impl BlockingClient {
+    /// Connects to an ordering server that was started with [`crate::server::run`]. Unlike
+    /// [`BlockingClient::start_reusing_connection`], this does not require the process to inherit
+    /// any file descriptors from the process that started the server.
+    pub fn start<T: ToSocketAddrs + std::fmt::Debug>(
+        addr: T,
+        federate_id: FederateId,
+        wait_timeout: Duration,
+    ) -> (BlockingClient, TcpBlockingClientJoinHandle) {
+        Self::start_tcp(
+            addr,
+            load_precedence(),
+            load_precid(),
+            federate_id,
+            wait_timeout,
+        )
+    }
+    fn start_tcp<T: ToSocketAddrs + std::fmt::Debug>(
+        addr: T,
+        precedence: Precedence,
+        precid: PrecedenceId,
+        federate_id: FederateId,
+        wait_timeout: Duration,
+    ) -> (BlockingClient, TcpBlockingClientJoinHandle) {
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .enable_io()
+            .build()
+            .unwrap();
+        let socket = rt.block_on(socket_from_addr(addr));
+        Self::start_from_socket(
+            rt,
+            precedence,
+            precid,
+            federate_id,
+            wait_timeout,
+            socket.into_split(),
+        )
+    }
+}
 
 pub type BlockingClientJoinHandle =
     std::thread::JoinHandle<(Client<unix::OwnedWriteHalf>, unix::OwnedReadHalf)>;
@@ -167,7 +173,21 @@ impl BlockingClient {
         federate_id: FederateId,
         wait_timeout: Duration,
     ) -> (BlockingClient, BlockingClientJoinHandle) {
-        let raw_fd = crate::server::connection_raw_fd_for(federate_id);
+        Self::start_inherited(
+            crate::server::connection_raw_fd_for(federate_id),
+            load_precedence(),
+            load_precid(),
+            federate_id,
+            wait_timeout,
+        )
+    }
+    fn start_inherited(
+        raw_fd: RawFd,
+        precedence: Precedence,
+        precid: PrecedenceId,
+        federate_id: FederateId,
+        wait_timeout: Duration,
+    ) -> (BlockingClient, BlockingClientJoinHandle) {
         let rt = tokio::runtime::Builder::new_multi_thread()
             .enable_io()
             .build()
@@ -183,6 +203,58 @@ impl BlockingClient {
             .into_split();
         Self::start_from_socket::<unix::OwnedReadHalf, unix::OwnedWriteHalf>(
             rt,
+            precedence,
+            precid,
+            federate_id,
+            wait_timeout,
+            (r.stream, w.stream.into_inner()),
+        )
+    }
+}
+
+pub type SharedMemoryBlockingClientJoinHandle =
+    std::thread::JoinHandle<(Client<ShmWriteHalf>, ShmReadHalf)>;
+
+impl BlockingClient {
+    /// Uses the connection that this process inherited from an ordering server that was started
+    /// with [`crate::server::run_shared_memory`]. The connection must be given back to the server
+    /// when the client is finished, as for [`BlockingClient::start_reusing_connection`].
+    pub fn start_shared_memory(
+        federate_id: FederateId,
+        wait_timeout: Duration,
+    ) -> (BlockingClient, SharedMemoryBlockingClientJoinHandle) {
+        Self::start_shared_memory_from_fd(
+            crate::server::connection_raw_fd_for(federate_id),
+            load_precedence(),
+            load_precid(),
+            federate_id,
+            wait_timeout,
+        )
+    }
+    fn start_shared_memory_from_fd(
+        raw_fd: RawFd,
+        precedence: Precedence,
+        precid: PrecedenceId,
+        federate_id: FederateId,
+        wait_timeout: Duration,
+    ) -> (BlockingClient, SharedMemoryBlockingClientJoinHandle) {
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .enable_io()
+            .build()
+            .unwrap();
+        let (r, w) = rt
+            .block_on(async { unsafe { (SHM_CONNECTION_MANAGEMENT.borrow)(raw_fd) } })
+            .unwrap_or_else(|err| {
+                panic!(
+                    "Failed to borrow connection for federate id {}: {}",
+                    federate_id.0, err
+                )
+            })
+            .into_split();
+        Self::start_from_socket(
+            rt,
+            precedence,
+            precid,
             federate_id,
             wait_timeout,
             (r.stream, w.stream.into_inner()),
@@ -190,47 +262,242 @@ impl BlockingClient {
     }
 }
 
+/// How a [`BlockingClient`] reaches the ordering server.
+#[derive(Debug, Clone)]
+pub enum ClientTransport {
+    /// The `host:port` of a server that was started with [`crate::server::run`].
+    Tcp(String),
+    /// A connection that the process inherited from a server that was started with
+    /// [`crate::server::run_reusing_connections`].
+    Inherited(RawFd),
+    /// A connection that the process inherited from a server that was started with
+    /// [`crate::server::run_shared_memory`].
+    SharedMemory(RawFd),
+}
+
+/// Everything that a [`BlockingClient`] needs to know to take part in a run. The processes that the
+/// server starts find it in their environment (see [`ClientConfig::from_env`]), but a program that
+/// embeds clients may make it up itself, such as to run clients of several runs at once.
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    pub federate_id: FederateId,
+    pub wait_timeout: Duration,
+    pub transport: ClientTransport,
+    pub precedence: Precedence,
+    pub precid: PrecedenceId,
+}
+
+impl ClientConfig {
+    /// Reads the configuration of federate `federate_id` from the environment variables that the
+    /// server gives the processes of a run, together with [`ORDSERV_WAIT_TIMEOUT_MILLISECONDS_ENV_VAR`].
+    pub fn from_env(federate_id: FederateId) -> Result<Self, String> {
+        fn var(name: &str) -> Result<String, String> {
+            env::var(name).map_err(|_| format!("{} is not set", name))
+        }
+        fn parse<T: std::str::FromStr>(name: &str, what: &str) -> Result<T, String> {
+            let value = var(name)?;
+            value
+                .parse()
+                .map_err(|_| format!("{} is not {}: {:?}", name, what, value))
+        }
+        let wait_timeout = Duration::from_millis(parse(
+            ORDSERV_WAIT_TIMEOUT_MILLISECONDS_ENV_VAR,
+            "the wait timeout in milliseconds",
+        )?);
+        // A server running in TCP mode advertises its port; otherwise the connection is
+        // inherited, and the server says if it is a shared-memory connection.
+        let transport = if env::var_os(ORDSERV_PORT_ENV_VAR).is_some() {
+            let port: u16 = parse(ORDSERV_PORT_ENV_VAR, "a port")?;
+            let host = env::var(ORDSERV_HOST_ENV_VAR).unwrap_or_else(|_| "127.0.0.1".into());
+            ClientTransport::Tcp(tcp_address(&host, port))
+        } else {
+            let fd = parse(&evar_name_for(federate_id), "a file descriptor")?;
+            if env::var(ORDSERV_TRANSPORT_ENV_VAR).is_ok_and(|t| t == SHARED_MEMORY_TRANSPORT_NAME)
+            {
+                ClientTransport::SharedMemory(fd)
+            } else {
+                ClientTransport::Inherited(fd)
+            }
+        };
+        let precedence_file = var(PRECEDENCE_FILE_NAME)?;
+        let precedence = std::fs::read(&precedence_file)
+            .map_err(|e| format!("Failed to read {}: {}", precedence_file, e))
+            .and_then(|bytes| Precedence::from_bytes(&bytes))?;
+        let precid = PrecedenceId(parse(PRECEDENCE_ID_NAME, "a precedence id")?);
+        Ok(Self {
+            federate_id,
+            wait_timeout,
+            transport,
+            precedence,
+            precid,
+        })
+    }
+}
+
+/// The thread of a client that was started with [`BlockingClient::start_with_config`].
+pub enum ClientJoinHandle {
+    Tcp(TcpBlockingClientJoinHandle),
+    Inherited(BlockingClientJoinHandle),
+    SharedMemory(SharedMemoryBlockingClientJoinHandle),
+}
+
+impl BlockingClient {
+    pub fn start_with_config(config: ClientConfig) -> (BlockingClient, ClientJoinHandle) {
+        let ClientConfig {
+            federate_id,
+            wait_timeout,
+            transport,
+            precedence,
+            precid,
+        } = config;
+        match transport {
+            ClientTransport::Tcp(addr) => {
+                let (client, join_handle) =
+                    Self::start_tcp(addr, precedence, precid, federate_id, wait_timeout);
+                (client, ClientJoinHandle::Tcp(join_handle))
+            }
+            ClientTransport::Inherited(raw_fd) => {
+                let (client, join_handle) =
+                    Self::start_inherited(raw_fd, precedence, precid, federate_id, wait_timeout);
+                (client, ClientJoinHandle::Inherited(join_handle))
+            }
+            ClientTransport::SharedMemory(raw_fd) => {
+                let (client, join_handle) = Self::start_shared_memory_from_fd(
+                    raw_fd,
+                    precedence,
+                    precid,
+                    federate_id,
+                    wait_timeout,
+                );
+                (client, ClientJoinHandle::SharedMemory(join_handle))
+            }
+        }
+    }
+}
+
+impl ClientJoinHandle {
+    /// Waits for the thread of the client, which ends once the client is halted and dropped. An
+    /// inherited connection is given back, so that the server can use it for the next run.
+    pub fn join(self) -> std::thread::Result<()> {
+        match self {
+            ClientJoinHandle::Tcp(join_handle) => {
+                // The connection is not shared with anyone else, so it is simply closed.
+                drop(join_handle.join()?);
+            }
+            ClientJoinHandle::Inherited(join_handle) => {
+                let (client, read) = join_handle.join()?;
+                unsafe {
+                    (UNIX_CONNECTION_MANAGEMENT.unborrow)((
+                        ReadConnection::new(read),
+                        WriteConnection {
+                            stream: client.connection.stream,
+                        },
+                    ))
+                };
+            }
+            ClientJoinHandle::SharedMemory(join_handle) => {
+                let (client, read) = join_handle.join()?;
+                unsafe {
+                    (SHM_CONNECTION_MANAGEMENT.unborrow)((
+                        ReadConnection::new(read),
+                        WriteConnection {
+                            stream: client.connection.stream,
+                        },
+                    ))
+                };
+            }
+        }
+        Ok(())
+    }
+}
+
+pub type MemoryBlockingClientJoinHandle =
+    std::thread::JoinHandle<(Client<MemoryWriteHalf>, MemoryReadHalf)>;
+
+impl BlockingClient {
+    /// Connects to an ordering server that was started with [`crate::server::run_in_memory`] in the
+    /// same process. The precedence is found through `evars`, the environment variables that the
+    /// server acked the run with, instead of through the environment of the process, so several
+    /// clients of different runs can share a process.
+    pub fn start_in_memory(
+        connector: &MemoryConnector,
+        evars: &EnvironmentVariables,
+        federate_id: FederateId,
+        wait_timeout: Duration,
+    ) -> (BlockingClient, MemoryBlockingClientJoinHandle) {
+        let evar = |name| {
+            evars
+                .get(name)
+                .unwrap_or_else(|| panic!("{} is not among the acked environment variables", name))
+        };
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        Self::start_from_socket(
+            rt,
+            read_precedence(evar(PRECEDENCE_FILE_NAME)),
+            PrecedenceId(evar(PRECEDENCE_ID_NAME).to_str().unwrap().parse().unwrap()),
+            federate_id,
+            wait_timeout,
+            connector.connect(),
+        )
+    }
+}
+
 impl BlockingClient {
     fn start_from_socket<
         R: AsyncReadExt + Unpin + Send + 'static,
         W: AsyncWriteExt + Unpin + Send + 'static,
     >(
         rt: tokio::runtime::Runtime,
+        mut precedence: Precedence,
+        precid: PrecedenceId,
         federate_id: FederateId,
         wait_timeout: Duration,
         socket: (R, W),
     ) -> (BlockingClient, std::thread::JoinHandle<(Client<W>, R)>) {
-        let ok_to_proceed = Arc::new(Mutex::new(HashSet::new()));
+        let ok_to_proceed = Arc::new(Mutex::new(Permissions::default()));
         let ok_cvar = Arc::new(Condvar::new());
-        let precedence = load_precedence();
         let run_id = precedence.run_id;
-        let requires_ok_to_proceed = Self::get_requires_ok_to_proceed(&precedence);
-        let requires_notify = Self::get_requires_notify(&precedence);
+        precedence.number_hooks();
+        let requirements = Arc::new(Requirements::new(&precedence));
+        let recorder = precedence
+            .record_timeline
+            .then(|| Arc::new(Recorder::new(Some(federate_id))));
+        let scratch_dir = precedence.scratch_dir.clone();
+        let recorder_clone = recorder.clone();
         let (notification_sender2async, notification_receiver2async) =
             tokio::sync::mpsc::unbounded_channel();
         let ok_to_proceed_clone = Arc::clone(&ok_to_proceed);
         let ok_cvar_clone = Arc::clone(&ok_cvar);
+        let requirements_clone = Arc::clone(&requirements);
         let (halt_send, halt_recv) = watch::channel(());
         let join_handle = std::thread::spawn(move || {
             rt.block_on(Self::run_client(
                 ok_to_proceed_clone,
                 ok_cvar_clone,
                 precedence,
+                requirements_clone,
                 socket,
                 notification_receiver2async,
                 halt_recv,
+                recorder_clone,
             ))
         });
         let client = BlockingClient {
             ok_to_proceed,
             ok_cvar,
-            requires_ok_to_proceed,
-            requires_notify,
+            requirements,
+            unlisted_hooks: Mutex::new(vec![]),
+            sequence_numbers: SequenceNumbers::default(),
             notification_sender: notification_sender2async,
-            precid: Self::load_precid(),
+            precid,
             fedid: federate_id,
             wait_timeout,
             run_id: run_id.0,
+            recorder,
+            scratch_dir,
             halt: halt_send,
         };
         info!("BlockingClient sending initial frame");
@@ -238,12 +505,129 @@ impl BlockingClient {
         info!("BlockingClient sent initial frame");
         (client, join_handle)
     }
+    /// The number by which the hook `hid` of this client's federate can be passed to the
+    /// `_numbered` tracepoints, so that its hook id is looked up only once. Returns `None` if the
+    /// tracepoints of `hid` never have anything to do.
+    pub fn hook_number(&self, hid: &HookId) -> Option<HookNumber> {
+        assert!(hid.1 == self.fedid);
+        if let Some(number) = self.requirements.hooks.number(hid) {
+            return Some(number);
+        }
+        if !self.requirements.record {
+            return None;
+        }
+        // Every hook invocation is reported when the interleaving is recorded, even those of
+        // hooks that the precedence does not mention. The server does not know the numbers of
+        // such hooks, so the frames about them carry the hook id instead.
+        let mut unlisted_hooks = self.unlisted_hooks.lock().unwrap();
+        let position = unlisted_hooks
+            .iter()
+            .position(|unlisted| unlisted == hid)
+            .unwrap_or_else(|| {
+                unlisted_hooks.push(hid.clone());
+                unlisted_hooks.len() - 1
+            });
+        Some(HookNumber(
+            (self.requirements.hooks.len() + position) as u32,
+        ))
+    }
+    /// The next invocation of `hid` by the calling thread, counted in the namespace of the thread
+    /// if it has one. See [`crate::threads`].
+    pub fn next_invocation(&self, hid: HookId) -> HookInvocation {
+        assert!(hid.1 == self.fedid);
+        self.sequence_numbers.next(hid)
+    }
+    pub fn federate_id(&self) -> FederateId {
+        self.fedid
+    }
+    /// Whether `number` was returned by [`BlockingClient::hook_number`].
+    pub fn has_hook_number(&self, number: HookNumber) -> bool {
+        (number.0 as usize)
+            < self.requirements.hooks.len() + self.unlisted_hooks.lock().unwrap().len()
+    }
+    /// Whether `numbered` is not one of the hooks that the precedence mentions.
+    fn is_unlisted(&self, numbered: NumberedInvocation) -> bool {
+        (numbered.hook.0 as usize) >= self.requirements.hooks.len()
+    }
+    /// The hook of `numbered` if [`BlockingClient::hook_number`] numbered it although the precedence
+    /// does not mention it, or `None` if the number was never handed out.
+    fn unlisted_hook(&self, numbered: NumberedInvocation) -> Option<HookInvocation> {
+        let position = (numbered.hook.0 as usize).checked_sub(self.requirements.hooks.len())?;
+        let hid = self.unlisted_hooks.lock().unwrap().get(position)?.clone();
+        Some(HookInvocation {
+            hid,
+            seqnum: numbered.seqnum,
+        })
+    }
     pub fn tracepoint_maybe_wait(&self, hook_invocation: HookInvocation) {
+        self.tracepoint_maybe_wait_with_attributes(hook_invocation, &Attributes::new());
+    }
+    /// Like [`BlockingClient::tracepoint_maybe_wait`], for a hook invocation that reports
+    /// `attributes` for the pattern edges of the precedence to match on.
+    pub fn tracepoint_maybe_wait_with_attributes(
+        &self,
+        hook_invocation: HookInvocation,
+        attributes: &Attributes,
+    ) {
         assert!(hook_invocation.hid.1 == self.fedid);
-        if self.requires_ok_to_proceed.contains(&hook_invocation) {
-            debug!("{:?} requires wait", hook_invocation);
+        match self.requirements.hooks.numbered(&hook_invocation) {
+            Some(numbered) => {
+                self.tracepoint_maybe_wait_numbered_with_attributes(numbered, attributes)
+            }
+            None => self.record_unlisted(&hook_invocation),
+        }
+    }
+    /// Like [`BlockingClient::tracepoint_maybe_wait`], for a hook that was numbered by
+    /// [`BlockingClient::hook_number`].
+    pub fn tracepoint_maybe_wait_numbered(&self, numbered: NumberedInvocation) {
+        self.tracepoint_maybe_wait_numbered_with_attributes(numbered, &Attributes::new());
+    }
+    pub fn tracepoint_maybe_wait_numbered_with_attributes(
+        &self,
+        numbered: NumberedInvocation,
+        attributes: &Attributes,
+    ) {
+        if self.is_unlisted(numbered) {
+            if let Some(hook_invocation) = self.unlisted_hook(numbered) {
+                self.record_unlisted(&hook_invocation);
+            }
+            return;
+        }
+        self.wait_for_notifications(numbered, attributes);
+        if let Some(delay) = self.requirements.delay(numbered, self.recorder.as_deref()) {
+            std::thread::sleep(delay);
+        }
+        if self.requirements.record {
+            self.notification_sender
+                .send(self.numbered_frame(FrameKind::Record, numbered))
+                .unwrap();
+        }
+    }
+    /// Reports a hook invocation that the precedence does not mention if the interleaving is being
+    /// recorded.
+    fn record_unlisted(&self, hook_invocation: &HookInvocation) {
+        if self.requirements.record {
+            self.notification_sender
+                .send(Frame::record(self.precid.0, hook_invocation, self.run_id))
+                .unwrap();
+        }
+    }
+    fn numbered_frame(&self, kind: FrameKind, numbered: NumberedInvocation) -> Frame {
+        Frame::numbered(kind, self.precid.0, self.fedid, numbered, self.run_id)
+    }
+    fn wait_for_notifications(&self, numbered: NumberedInvocation, attributes: &Attributes) {
+        if self.requirements.requires_wait(numbered, attributes) {
+            debug!("{:?} requires wait", numbered);
+            let start = Instant::now();
             let mut ok_to_proceed = self.ok_to_proceed.lock().unwrap();
-            while !ok_to_proceed.contains(&hook_invocation) {
+            let mut blocked = false;
+            while !ok_to_proceed.allows(numbered, attributes, &self.requirements) {
+                if self.requirements.step && !blocked {
+                    self.notification_sender
+                        .send(self.numbered_frame(FrameKind::Blocked, numbered))
+                        .unwrap();
+                    blocked = true;
+                }
                 let result = self
                     .ok_cvar
                     .wait_timeout(ok_to_proceed, self.wait_timeout)
@@ -251,75 +635,130 @@ impl BlockingClient {
                 debug!("Got notification on cvar: {:?}", result);
                 ok_to_proceed = result.0;
                 if result.1.timed_out() {
-                    eprintln!("Timed out waiting for {:?}", hook_invocation);
-                    break;
+                    let hook_invocation = self.requirements.invocation(numbered);
+                    warn!("Timed out waiting for {:?}", hook_invocation);
+                    // Let the server know that this ordering could not be enforced.
+                    self.notification_sender
+                        .send(
+                            self.numbered_frame(FrameKind::Timeout, numbered)
+                                .with_attributes(attributes),
+                        )
+                        .unwrap();
+                    if let Some(recorder) = &self.recorder {
+                        recorder.timeout(hook_invocation, attributes.clone(), start.elapsed());
+                    }
+                    return;
                 }
             }
+            if blocked {
+                self.notification_sender
+                    .send(self.numbered_frame(FrameKind::Unblocked, numbered))
+                    .unwrap();
+            }
+            if let Some(recorder) = &self.recorder {
+                recorder.wake_up(self.requirements.invocation(numbered), start.elapsed());
+            }
         }
     }
     pub fn tracepoint_maybe_notify(&self, hook_invocation: HookInvocation) {
-        if self.requires_notify.contains(&hook_invocation) {
-            let mut hook_id = [0; 32];
-            for (idx, byte) in hook_invocation.hid.0.as_bytes().iter().enumerate() {
-                hook_id[idx] = *byte;
+        self.tracepoint_maybe_notify_with_attributes(hook_invocation, &Attributes::new());
+    }
+    /// Like [`BlockingClient::tracepoint_maybe_notify`], for a hook invocation that reports
+    /// `attributes` for the pattern edges of the precedence to match on.
+    pub fn tracepoint_maybe_notify_with_attributes(
+        &self,
+        hook_invocation: HookInvocation,
+        attributes: &Attributes,
+    ) {
+        assert!(hook_invocation.hid.1 == self.fedid);
+        if let Some(numbered) = self.requirements.hooks.numbered(&hook_invocation) {
+            self.tracepoint_maybe_notify_numbered_with_attributes(numbered, attributes);
+        }
+    }
+    /// Like [`BlockingClient::tracepoint_maybe_notify`], for a hook that was numbered by
+    /// [`BlockingClient::hook_number`].
+    pub fn tracepoint_maybe_notify_numbered(&self, numbered: NumberedInvocation) {
+        self.tracepoint_maybe_notify_numbered_with_attributes(numbered, &Attributes::new());
+    }
+    pub fn tracepoint_maybe_notify_numbered_with_attributes(
+        &self,
+        numbered: NumberedInvocation,
+        attributes: &Attributes,
+    ) {
+        if !self.is_unlisted(numbered) && self.requirements.notify(numbered, attributes) {
+            debug!("Notifying {:?}", numbered);
+            if let Some(recorder) = &self.recorder {
+                recorder.record(EventKind::Notify(self.requirements.invocation(numbered)));
             }
-            debug!("Notifying {:?}", hook_invocation);
             self.notification_sender
-                .send(Frame {
-                    precedence_id: self.precid.0,
-                    federate_id: self.fedid.0,
-                    hook_id,
-                    sequence_number: hook_invocation.seqnum.0,
-                    run_id: self.run_id,
-                })
+                .send(
+                    self.numbered_frame(FrameKind::Notify, numbered)
+                        .with_attributes(attributes),
+                )
                 .unwrap();
-            debug!("Notified {:?}", hook_invocation);
+            debug!("Notified {:?}", numbered);
         }
     }
     pub fn tracepoint_maybe_do(&self, hook_invocation: HookInvocation) {
-        self.tracepoint_maybe_wait(hook_invocation.clone());
-        self.tracepoint_maybe_notify(hook_invocation);
+        self.tracepoint_maybe_do_with_attributes(hook_invocation, &Attributes::new());
+    }
+    /// Like [`BlockingClient::tracepoint_maybe_do`], for a hook invocation that reports
+    /// `attributes` for the pattern edges of the precedence to match on.
+    pub fn tracepoint_maybe_do_with_attributes(
+        &self,
+        hook_invocation: HookInvocation,
+        attributes: &Attributes,
+    ) {
+        assert!(hook_invocation.hid.1 == self.fedid);
+        match self.requirements.hooks.numbered(&hook_invocation) {
+            Some(numbered) => {
+                self.tracepoint_maybe_do_numbered_with_attributes(numbered, attributes)
+            }
+            None => self.record_unlisted(&hook_invocation),
+        }
+    }
+    /// Like [`BlockingClient::tracepoint_maybe_do`], for a hook that was numbered by
+    /// [`BlockingClient::hook_number`].
+    pub fn tracepoint_maybe_do_numbered(&self, numbered: NumberedInvocation) {
+        self.tracepoint_maybe_do_numbered_with_attributes(numbered, &Attributes::new());
     }
+    pub fn tracepoint_maybe_do_numbered_with_attributes(
+        &self,
+        numbered: NumberedInvocation,
+        attributes: &Attributes,
+    ) {
+        self.tracepoint_maybe_wait_numbered_with_attributes(numbered, attributes);
+        self.tracepoint_maybe_notify_numbered_with_attributes(numbered, attributes);
+    }
+    #[allow(clippy::too_many_arguments)]
     async fn run_client<
         R: AsyncReadExt + Unpin + Send + 'static,
         W: AsyncWriteExt + Unpin + Send + 'static,
     >(
-        ok_to_proceed: Arc<Mutex<HashSet<HookInvocation>>>,
+        ok_to_proceed: Arc<Mutex<Permissions>>,
         ok_cvar: Arc<Condvar>,
         precedence: Precedence,
+        requirements: Arc<Requirements>,
         socket: (R, W),
         mut notification_receiver: tokio::sync::mpsc::UnboundedReceiver<Frame>,
         halt: watch::Receiver<()>,
+        recorder: Option<Arc<Recorder>>,
     ) -> (Client<W>, R) {
         info!("Client starting");
         let (mut client, jh) = Client::start_from_socket(
             socket,
             Box::new(move |frame| {
-                if frame.run_id != precedence.run_id.0 {
-                    warn!(
-                        "Received notification for run id {} but expected {}. Ignoring it. The server sometimes forwards messages from stragglers from previous runs, which is probably not the ideal behavior but is not currently considered an error condition.",
-                        frame.run_id, precedence.run_id.0);
-                    return;
-                }
-                debug!("Inside callback on frame: {:?}", frame);
                 let mut ok_to_proceed = ok_to_proceed.lock().unwrap();
                 debug!("Got lock on ok_to_proceed");
-                for hook_invocation in precedence
-                    .sender2waiters
-                    .get(&frame.hook_invocation())
-                    .unwrap_or_else(|| {
-                        panic!(
-                            "Received notification for {:?} (run id {}) but no one is waiting for it. The sender2waiters map is {:?} (run id {})",
-                            frame.hook_invocation(),
-                            frame.run_id,
-                            precedence.sender2waiters,
-                            precedence.run_id.0,
-                        )
-                    })
-                {
-                    ok_to_proceed.insert(hook_invocation.clone());
+                if accept_notification(
+                    &precedence,
+                    &requirements,
+                    recorder.as_deref(),
+                    &frame,
+                    &mut ok_to_proceed,
+                ) {
+                    ok_cvar.notify_all();
                 }
-                ok_cvar.notify_all();
             }),
             halt,
         )
@@ -327,40 +766,321 @@ impl BlockingClient {
         info!("Client started and entering steady state");
         while let Some(frame) = notification_receiver.recv().await {
             debug!("Received notification: {:?}", frame);
-            client.write(frame).await;
+            if let Err(e) = client.write(&frame).await {
+                error!("Failed to send notification {:?}: {}", frame, e);
+                continue;
+            }
             debug!("Wrote notification: {:?}", frame);
         }
         debug!("Client exiting");
         (client, jh.await.unwrap())
     }
-    fn get_requires_ok_to_proceed(precedence: &Precedence) -> HashSet<HookInvocation> {
-        let mut ret = HashSet::new();
-        for waiter in precedence.sender2waiters.values().flatten() {
-            ret.insert(waiter.clone());
-        }
-        ret
+    fn send_initial_frame(&self) {
+        self.notification_sender
+            .send(Frame::hello(self.precid.0, self.fedid, self.run_id))
+            .unwrap();
     }
-    fn get_requires_notify(precedence: &Precedence) -> HashSet<HookInvocation> {
-        let mut ret = HashSet::new();
-        for notifier in precedence.sender2waiters.keys() {
-            ret.insert(notifier.clone());
+}
+
+impl Drop for BlockingClient {
+    fn drop(&mut self) {
+        if let Some(recorder) = &self.recorder {
+            recorder.save(&self.scratch_dir);
         }
-        ret
     }
-    fn load_precid() -> PrecedenceId {
-        let id = env::var(PRECEDENCE_ID_NAME).unwrap();
-        PrecedenceId(id.parse().unwrap())
+}
+
+/// A client for programs that already run on tokio. Unlike [`BlockingClient`], it does not start a
+/// runtime of its own; it runs on the runtime of the caller, and waiting does not block a thread.
+///
+/// [`AsyncClient::finish`] must be called once the program is done with the client.
+pub struct AsyncClient<W, R>
+where
+    W: AsyncWriteExt + Unpin,
+{
+    requirements: Arc<Requirements>,
+    ok_to_proceed: Arc<Mutex<Permissions>>,
+    ok_notify: Arc<Notify>,
+    client: tokio::sync::Mutex<Client<W>>,
+    reader: JoinHandle<R>,
+    precid: PrecedenceId,
+    fedid: FederateId,
+    run_id: u32,
+    wait_timeout: Duration,
+    recorder: Option<Arc<Recorder>>,
+    scratch_dir: PathBuf,
+    halt: watch::Sender<()>,
+}
+
+impl AsyncClient<tcp::OwnedWriteHalf, tcp::OwnedReadHalf> {
+    /// Connects to an ordering server that was started with [`crate::server::run`].
+    pub async fn start<T: ToSocketAddrs + std::fmt::Debug>(
+        addr: T,
+        federate_id: FederateId,
+        wait_timeout: Duration,
+    ) -> Self {
+        let socket = socket_from_addr(addr).await;
+        Self::start_from_socket(
+            load_precedence(),
+            load_precid(),
+            federate_id,
+            wait_timeout,
+            socket.into_split(),
+        )
+        .await
     }
-    fn send_initial_frame(&self) {
-        self.notification_sender
-            .send(Frame {
-                precedence_id: self.precid.0,
-                federate_id: self.fedid.0,
-                hook_id: [b'S'; 32],
-                sequence_number: 0,
-                run_id: self.run_id,
+}
+
+impl AsyncClient<unix::OwnedWriteHalf, unix::OwnedReadHalf> {
+    /// Uses the connection that this process inherited from an ordering server that was started
+    /// with [`crate::server::run_reusing_connections`]. The connection must be given back to the
+    /// server when the client is finished, as is done for [`BlockingClient`].
+    pub async fn start_reusing_connection(federate_id: FederateId, wait_timeout: Duration) -> Self {
+        let raw_fd = crate::server::connection_raw_fd_for(federate_id);
+        let (r, w) = unsafe { (UNIX_CONNECTION_MANAGEMENT.borrow)(raw_fd) }
+            .unwrap_or_else(|err| {
+                panic!(
+                    "Failed to borrow connection for federate id {}: {}",
+                    federate_id.0, err
+                )
             })
-            .unwrap();
+            .into_split();
+        Self::start_from_socket(
+            load_precedence(),
+            load_precid(),
+            federate_id,
+            wait_timeout,
+            (r.stream, w.stream.into_inner()),
+        )
+        .await
+    }
+}
+
+impl AsyncClient<ShmWriteHalf, ShmReadHalf> {
+    /// Uses the connection that this process inherited from an ordering server that was started
+    /// with [`crate::server::run_shared_memory`].
+    pub async fn start_shared_memory(federate_id: FederateId, wait_timeout: Duration) -> Self {
+        let raw_fd = crate::server::connection_raw_fd_for(federate_id);
+        let (r, w) = unsafe { (SHM_CONNECTION_MANAGEMENT.borrow)(raw_fd) }
+            .unwrap_or_else(|err| {
+                panic!(
+                    "Failed to borrow connection for federate id {}: {}",
+                    federate_id.0, err
+                )
+            })
+            .into_split();
+        Self::start_from_socket(
+            load_precedence(),
+            load_precid(),
+            federate_id,
+            wait_timeout,
+            (r.stream, w.stream.into_inner()),
+        )
+        .await
+    }
+}
+
+impl<W, R> AsyncClient<W, R>
+where
+    W: AsyncWriteExt + Unpin + Send + 'static,
+    R: AsyncReadExt + Unpin + Send + 'static,
+{
+    async fn start_from_socket(
+        mut precedence: Precedence,
+        precid: PrecedenceId,
+        federate_id: FederateId,
+        wait_timeout: Duration,
+        socket: (R, W),
+    ) -> Self {
+        let ok_to_proceed = Arc::new(Mutex::new(Permissions::default()));
+        let ok_notify = Arc::new(Notify::new());
+        let run_id = precedence.run_id;
+        precedence.number_hooks();
+        let requirements = Arc::new(Requirements::new(&precedence));
+        let recorder = precedence
+            .record_timeline
+            .then(|| Arc::new(Recorder::new(Some(federate_id))));
+        let scratch_dir = precedence.scratch_dir.clone();
+        let (halt_send, halt_recv) = watch::channel(());
+        let (client, reader) = {
+            let ok_to_proceed = Arc::clone(&ok_to_proceed);
+            let ok_notify = Arc::clone(&ok_notify);
+            let recorder = recorder.clone();
+            let requirements = Arc::clone(&requirements);
+            Client::start_from_socket(
+                socket,
+                Box::new(move |frame| {
+                    let mut ok_to_proceed = ok_to_proceed.lock().unwrap();
+                    if accept_notification(
+                        &precedence,
+                        &requirements,
+                        recorder.as_deref(),
+                        &frame,
+                        &mut ok_to_proceed,
+                    ) {
+                        ok_notify.notify_waiters();
+                    }
+                }),
+                halt_recv,
+            )
+            .await
+        };
+        let client = AsyncClient {
+            requirements,
+            ok_to_proceed,
+            ok_notify,
+            client: tokio::sync::Mutex::new(client),
+            reader,
+            precid,
+            fedid: federate_id,
+            run_id: run_id.0,
+            wait_timeout,
+            recorder,
+            scratch_dir,
+            halt: halt_send,
+        };
+        info!("AsyncClient sending initial frame");
+        client
+            .write(&Frame::hello(client.precid.0, client.fedid, client.run_id))
+            .await;
+        client
+    }
+    pub async fn tracepoint_maybe_wait(&self, hook_invocation: HookInvocation) {
+        self.tracepoint_maybe_wait_with_attributes(hook_invocation, &Attributes::new())
+            .await;
+    }
+    /// Like [`AsyncClient::tracepoint_maybe_wait`], for a hook invocation that reports
+    /// `attributes` for the pattern edges of the precedence to match on.
+    pub async fn tracepoint_maybe_wait_with_attributes(
+        &self,
+        hook_invocation: HookInvocation,
+        attributes: &Attributes,
+    ) {
+        assert!(hook_invocation.hid.1 == self.fedid);
+        let Some(numbered) = self.requirements.hooks.numbered(&hook_invocation) else {
+            if self.requirements.record {
+                self.write(&Frame::record(self.precid.0, &hook_invocation, self.run_id))
+                    .await;
+            }
+            return;
+        };
+        self.wait_for_notifications(numbered, attributes).await;
+        if let Some(delay) = self.requirements.delay(numbered, self.recorder.as_deref()) {
+            tokio::time::sleep(delay).await;
+        }
+        if self.requirements.record {
+            self.write(&self.numbered_frame(FrameKind::Record, numbered))
+                .await;
+        }
+    }
+    fn numbered_frame(&self, kind: FrameKind, numbered: NumberedInvocation) -> Frame {
+        Frame::numbered(kind, self.precid.0, self.fedid, numbered, self.run_id)
+    }
+    async fn wait_for_notifications(&self, numbered: NumberedInvocation, attributes: &Attributes) {
+        if !self.requirements.requires_wait(numbered, attributes) {
+            return;
+        }
+        debug!("{:?} requires wait", numbered);
+        let start = Instant::now();
+        let deadline = tokio::time::Instant::from_std(start + self.wait_timeout);
+        let mut blocked = false;
+        loop {
+            // Registered before checking so that a notification that arrives in between is not
+            // missed.
+            let notified = self.ok_notify.notified();
+            if self
+                .ok_to_proceed
+                .lock()
+                .unwrap()
+                .allows(numbered, attributes, &self.requirements)
+            {
+                break;
+            }
+            if self.requirements.step && !blocked {
+                self.write(&self.numbered_frame(FrameKind::Blocked, numbered))
+                    .await;
+                blocked = true;
+            }
+            if tokio::time::timeout_at(deadline, notified).await.is_err() {
+                let hook_invocation = self.requirements.invocation(numbered);
+                warn!("Timed out waiting for {:?}", hook_invocation);
+                // Let the server know that this ordering could not be enforced.
+                self.write(
+                    &self
+                        .numbered_frame(FrameKind::Timeout, numbered)
+                        .with_attributes(attributes),
+                )
+                .await;
+                if let Some(recorder) = &self.recorder {
+                    recorder.timeout(hook_invocation, attributes.clone(), start.elapsed());
+                }
+                return;
+            }
+        }
+        if blocked {
+            self.write(&self.numbered_frame(FrameKind::Unblocked, numbered))
+                .await;
+        }
+        if let Some(recorder) = &self.recorder {
+            recorder.wake_up(self.requirements.invocation(numbered), start.elapsed());
+        }
+    }
+    pub async fn tracepoint_maybe_notify(&self, hook_invocation: HookInvocation) {
+        self.tracepoint_maybe_notify_with_attributes(hook_invocation, &Attributes::new())
+            .await;
+    }
+    /// Like [`AsyncClient::tracepoint_maybe_notify`], for a hook invocation that reports
+    /// `attributes` for the pattern edges of the precedence to match on.
+    pub async fn tracepoint_maybe_notify_with_attributes(
+        &self,
+        hook_invocation: HookInvocation,
+        attributes: &Attributes,
+    ) {
+        assert!(hook_invocation.hid.1 == self.fedid);
+        let Some(numbered) = self.requirements.hooks.numbered(&hook_invocation) else {
+            return;
+        };
+        if self.requirements.notify(numbered, attributes) {
+            debug!("Notifying {:?}", hook_invocation);
+            if let Some(recorder) = &self.recorder {
+                recorder.record(EventKind::Notify(hook_invocation));
+            }
+            self.write(
+                &self
+                    .numbered_frame(FrameKind::Notify, numbered)
+                    .with_attributes(attributes),
+            )
+            .await;
+        }
+    }
+    pub async fn tracepoint_maybe_do(&self, hook_invocation: HookInvocation) {
+        self.tracepoint_maybe_do_with_attributes(hook_invocation, &Attributes::new())
+            .await;
+    }
+    pub async fn tracepoint_maybe_do_with_attributes(
+        &self,
+        hook_invocation: HookInvocation,
+        attributes: &Attributes,
+    ) {
+        self.tracepoint_maybe_wait_with_attributes(hook_invocation.clone(), attributes)
+            .await;
+        self.tracepoint_maybe_notify_with_attributes(hook_invocation, attributes)
+            .await;
+    }
+    async fn write(&self, frame: &Frame) {
+        if let Err(e) = self.client.lock().await.write(frame).await {
+            error!("Failed to send {:?}: {}", frame, e);
+        }
+    }
+    /// Stops listening for notifications, saves the timeline of the run if one is being recorded,
+    /// and returns both halves of the connection.
+    pub async fn finish(self) -> (Client<W>, R) {
+        if let Some(recorder) = &self.recorder {
+            recorder.save(&self.scratch_dir);
+        }
+        // The reader may already have stopped if the server went away.
+        let _ = self.halt.send(());
+        (self.client.into_inner(), self.reader.await.unwrap())
     }
 }
 
@@ -373,8 +1093,666 @@ async fn socket_from_addr<T: ToSocketAddrs + std::fmt::Debug>(addr: T) -> TcpStr
     socket
 }
 
+/// The hook invocations of a precedence at which a client must wait or notify.
+///
+/// Hook invocations are looked up by number, so that the tracepoints of hooks that the precedence
+/// does not mention find out that they have nothing to do without hashing a hook id.
+struct Requirements {
+    hooks: HookTable,
+    /// The waiters of each exact notifier.
+    waiters: HashMap<NumberedInvocation, Vec<NumberedInvocation>>,
+    /// The number of exact notifiers of each hook invocation that must wait.
+    n_notifiers: HashMap<NumberedInvocation, usize>,
+    patterns: PatternIndex<HookNumber>,
+    joins: HashMap<NumberedInvocation, Join>,
+    delays: HashMap<NumberedInvocation, Delay>,
+    /// Whether to tell the server about every hook invocation as it happens.
+    record: bool,
+    /// Whether to tell the server whenever a hook invocation starts or stops waiting.
+    step: bool,
+}
+
+impl Requirements {
+    /// `precedence` must have been numbered with [`Precedence::number_hooks`].
+    fn new(precedence: &Precedence) -> Self {
+        let hooks = precedence.hook_table();
+        let waiters = precedence.numbered_sender2waiters(&hooks);
+        let mut n_notifiers = HashMap::new();
+        for waiter in waiters.values().flatten() {
+            *n_notifiers.entry(*waiter).or_default() += 1;
+        }
+        let numbered = |hook_invocation| hooks.numbered(hook_invocation).unwrap();
+        Requirements {
+            n_notifiers,
+            patterns: PatternIndex::numbered(precedence, &hooks),
+            joins: precedence
+                .joins
+                .iter()
+                .map(|(waiter, join)| (numbered(waiter), *join))
+                .collect(),
+            delays: precedence
+                .delays
+                .iter()
+                .map(|(hook_invocation, delay)| (numbered(hook_invocation), *delay))
+                .collect(),
+            record: precedence.record_interleaving,
+            step: precedence.step,
+            waiters,
+            hooks,
+        }
+    }
+    /// The hook invocation that `numbered` stands for, for logging and for the timeline.
+    fn invocation(&self, numbered: NumberedInvocation) -> HookInvocation {
+        self.hooks.invocation(numbered).unwrap()
+    }
+    /// Whether `numbered` has to wait for a notification before it may proceed.
+    fn requires_wait(&self, numbered: NumberedInvocation, attributes: &Attributes) -> bool {
+        self.n_notifiers.contains_key(&numbered)
+            || self
+                .patterns
+                .numbered_waiting(numbered, attributes)
+                .next()
+                .is_some()
+    }
+    fn notify(&self, numbered: NumberedInvocation, attributes: &Attributes) -> bool {
+        self.waiters.contains_key(&numbered)
+            || self
+                .patterns
+                .numbered_notified_by(numbered, attributes)
+                .next()
+                .is_some()
+    }
+    /// How long to sleep before `numbered` proceeds, if at all.
+    fn delay(&self, numbered: NumberedInvocation, recorder: Option<&Recorder>) -> Option<Duration> {
+        let delay = self.delays.get(&numbered)?.duration();
+        debug!("Delaying {:?} by {:?}", numbered, delay);
+        if let Some(recorder) = recorder {
+            recorder.record(EventKind::Delayed {
+                hook_invocation: self.invocation(numbered),
+                delay,
+            });
+        }
+        Some(delay)
+    }
+}
+
+/// The notifications that a client has received so far.
+#[derive(Debug, Default)]
+struct Permissions {
+    /// The notifiers that each waiter has heard from.
+    notified: HashMap<NumberedInvocation, HashSet<NumberedInvocation>>,
+    /// The pattern edges at least one of whose notifiers has happened.
+    pattern_edges: HashSet<usize>,
+}
+
+impl Permissions {
+    fn allows(
+        &self,
+        numbered: NumberedInvocation,
+        attributes: &Attributes,
+        requirements: &Requirements,
+    ) -> bool {
+        let n_heard = self.notified.get(&numbered).map_or(0, HashSet::len);
+        let mut edges = requirements
+            .patterns
+            .numbered_waiting(numbered, attributes)
+            .map(|(edge, _)| self.pattern_edges.contains(&edge));
+        match requirements
+            .joins
+            .get(&numbered)
+            .copied()
+            .unwrap_or_default()
+        {
+            Join::Any => n_heard > 0 || edges.any(|satisfied| satisfied),
+            Join::All => {
+                n_heard
+                    == requirements
+                        .n_notifiers
+                        .get(&numbered)
+                        .copied()
+                        .unwrap_or(0)
+                    && edges.all(|satisfied| satisfied)
+            }
+        }
+    }
+}
+
+/// Marks the waiters of the hook invocation in `frame` as ok to proceed. Returns false if the frame
+/// belongs to a different run.
+fn accept_notification(
+    precedence: &Precedence,
+    requirements: &Requirements,
+    recorder: Option<&Recorder>,
+    frame: &Frame,
+    ok_to_proceed: &mut Permissions,
+) -> bool {
+    if frame.run_id != precedence.run_id.0 {
+        warn!(
+            "Received notification for run id {} but expected {}. Ignoring it. The server sometimes forwards messages from stragglers from previous runs, which is probably not the ideal behavior but is not currently considered an error condition.",
+            frame.run_id, precedence.run_id.0);
+        return false;
+    }
+    debug!("Inside callback on frame: {:?}", frame);
+    if frame.kind == FrameKind::NotifierGone {
+        release_waiters_of(
+            precedence,
+            requirements,
+            FederateId(frame.federate_id),
+            ok_to_proceed,
+        );
+        return true;
+    }
+    let Some(notification) = requirements.hooks.frame_numbered(frame) else {
+        panic!(
+            "Received notification {:?} about a hook that is not among the hooks of the precedence, {:?}",
+            frame, precedence.hooks
+        )
+    };
+    let waiters = requirements.waiters.get(&notification);
+    let edges: Vec<_> = requirements
+        .patterns
+        .numbered_notified_by(notification, &frame.attributes)
+        .collect();
+    if waiters.is_none() && edges.is_empty() {
+        panic!(
+            "Received notification for {:?} (run id {}) but no one is waiting for it. The sender2waiters map is {:?} and the pattern edges are {:?} (run id {})",
+            requirements.invocation(notification),
+            frame.run_id,
+            precedence.sender2waiters,
+            precedence.pattern_edges,
+            precedence.run_id.0,
+        )
+    }
+    for waiter in waiters.into_iter().flatten() {
+        if let Some(recorder) = recorder {
+            recorder.arrived(
+                &requirements.invocation(notification),
+                &requirements.invocation(*waiter),
+            );
+        }
+        ok_to_proceed
+            .notified
+            .entry(*waiter)
+            .or_default()
+            .insert(notification);
+    }
+    for edge in edges {
+        if let Some(recorder) = recorder {
+            for waiter in &precedence.pattern_edges[edge].waiters {
+                recorder.arrived_for_pattern(&requirements.invocation(notification), waiter);
+            }
+        }
+        ok_to_proceed.pattern_edges.insert(edge);
+    }
+    true
+}
+
+/// Treats every notification that `gone` was supposed to send as if it had arrived, because it never
+/// will.
+fn release_waiters_of(
+    precedence: &Precedence,
+    requirements: &Requirements,
+    gone: FederateId,
+    ok_to_proceed: &mut Permissions,
+) {
+    warn!(
+        "Federate {} went away early; releasing the hook invocations that wait for it",
+        gone.0
+    );
+    for (notifier, waiters) in &requirements.waiters {
+        if requirements.hooks.hook(notifier.hook).unwrap().1 == gone {
+            for waiter in waiters {
+                ok_to_proceed
+                    .notified
+                    .entry(*waiter)
+                    .or_default()
+                    .insert(*notifier);
+            }
+        }
+    }
+    for (edge, PatternEdge { notifier, .. }) in precedence.pattern_edges.iter().enumerate() {
+        if notifier.hid.1 == gone {
+            ok_to_proceed.pattern_edges.insert(edge);
+        }
+    }
+}
+
+/// Formats `host` and `port` as an address that can be resolved, bracketing IPv6 addresses.
+fn tcp_address(host: &str, port: u16) -> String {
+    if host.contains(':') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+fn load_precid() -> PrecedenceId {
+    let id = env::var(PRECEDENCE_ID_NAME).unwrap();
+    PrecedenceId(id.parse().unwrap())
+}
+
 fn load_precedence() -> Precedence {
-    let f = env::var(PRECEDENCE_FILE_NAME).unwrap();
-    let f = std::fs::File::open(f).unwrap();
+    read_precedence(env::var(PRECEDENCE_FILE_NAME).unwrap())
+}
+
+fn read_precedence(path: impl AsRef<Path>) -> Precedence {
+    let f = std::fs::File::open(path).unwrap();
     rmp_serde::from_read(f).unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        pattern::{HookInvocationPattern, SeqnumPattern},
+        server::{self, Update},
+        HookId, ORDSERV_PORT_ENV_VAR,
+    };
+
+    fn port_and_precid(evars: EnvironmentVariables) -> (u16, PrecedenceId) {
+        let evar = |name: &str| evars.get(name).unwrap().to_str().unwrap().to_string();
+        (
+            evar(ORDSERV_PORT_ENV_VAR).parse().unwrap(),
+            PrecedenceId(evar(PRECEDENCE_ID_NAME).parse().unwrap()),
+        )
+    }
+
+    async fn start_client(
+        port: u16,
+        precid: PrecedenceId,
+        precedence: &Precedence,
+        fedid: i32,
+    ) -> AsyncClient<tcp::OwnedWriteHalf, tcp::OwnedReadHalf> {
+        let socket = socket_from_addr(("127.0.0.1", port)).await;
+        AsyncClient::start_from_socket(
+            precedence.clone(),
+            precid,
+            FederateId(fedid),
+            Duration::from_millis(300),
+            socket.into_split(),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_async_clients_order_hook_invocations() {
+        let scratch_dir = crate::test_scratch_dir("async");
+        let mut server_handle = server::run(0, 1).await.unwrap();
+        let (updates, acks, reports) = &mut server_handle.updates_acks[0];
+        let precedence = Precedence::from_list(
+            2,
+            &[
+                (("notifier", 0, 0), &[("waiter", 1, 0)]),
+                (("never", 0, 0), &[("gives up", 1, 0)]),
+            ],
+            scratch_dir.clone(),
+            3,
+        );
+        updates
+            .send(Update::Start(precedence.clone()))
+            .await
+            .unwrap();
+        let (port, precid) = port_and_precid(acks.recv().await.unwrap().unwrap());
+        let start_client = |fedid| start_client(port, precid, &precedence, fedid);
+        let notifier = start_client(0).await;
+        let waiter = start_client(1).await;
+        let start = Instant::now();
+        let waiting = tokio::spawn(async move {
+            waiter
+                .tracepoint_maybe_wait(HookInvocation::from_short(("waiter", 1, 0)))
+                .await;
+            let waited = start.elapsed();
+            waiter
+                .tracepoint_maybe_wait(HookInvocation::from_short(("gives up", 1, 0)))
+                .await;
+            waiter.finish().await;
+            waited
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        notifier
+            .tracepoint_maybe_notify(HookInvocation::from_short(("notifier", 0, 0)))
+            .await;
+        let waited = waiting.await.unwrap();
+        assert!(waited >= Duration::from_millis(100));
+        assert!(waited < Duration::from_millis(300));
+        notifier.finish().await;
+        updates.send(Update::Finish).await.unwrap();
+        let report = reports.recv().await.unwrap();
+        assert!(report.is_clean(), "{}", report);
+        assert_eq!(report.timeouts.len(), 1);
+        assert_eq!(
+            report.timeouts[0].waiter,
+            HookInvocation::from_short(("gives up", 1, 0))
+        );
+        server_handle.join().await;
+        std::fs::remove_dir_all(scratch_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_pattern_edges_order_every_matching_invocation() {
+        let scratch_dir = crate::test_scratch_dir("pattern");
+        let mut server_handle = server::run(0, 1).await.unwrap();
+        let (updates, acks, reports) = &mut server_handle.updates_acks[0];
+        let pattern = |hid: &str, fedid, seqnums| {
+            HookInvocationPattern::new(HookId::new(hid.into(), FederateId(fedid)), seqnums)
+        };
+        let never = pattern("never", 0, SeqnumPattern::Any);
+        let mut precedence = Precedence::from_list(2, &[], scratch_dir.clone(), 5);
+        precedence.pattern_edges = vec![
+            PatternEdge {
+                notifier: pattern("A", 0, SeqnumPattern::Exact(1)),
+                waiters: vec![pattern("B", 1, SeqnumPattern::Range { start: 2, end: 4 })],
+            },
+            PatternEdge {
+                notifier: never.clone(),
+                waiters: vec![pattern("C", 1, SeqnumPattern::Any)],
+            },
+        ];
+        updates
+            .send(Update::Start(precedence.clone()))
+            .await
+            .unwrap();
+        let (port, precid) = port_and_precid(acks.recv().await.unwrap().unwrap());
+        let notifier = start_client(port, precid, &precedence, 0).await;
+        let waiter = start_client(port, precid, &precedence, 1).await;
+        let start = Instant::now();
+        let waiting = tokio::spawn(async move {
+            let mut waited = vec![];
+            for seqnum in 0..5 {
+                waiter
+                    .tracepoint_maybe_wait(HookInvocation::from_short(("B", 1, seqnum)))
+                    .await;
+                waited.push(start.elapsed());
+            }
+            waiter
+                .tracepoint_maybe_wait(HookInvocation::from_short(("C", 1, 7)))
+                .await;
+            waiter.finish().await;
+            waited
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        for seqnum in 0..2 {
+            notifier
+                .tracepoint_maybe_do(HookInvocation::from_short(("A", 0, seqnum)))
+                .await;
+        }
+        let waited = waiting.await.unwrap();
+        assert!(waited[1] < Duration::from_millis(100));
+        assert!(waited[2] >= Duration::from_millis(100));
+        assert!(waited[4] < Duration::from_millis(300));
+        notifier.finish().await;
+        updates.send(Update::Finish).await.unwrap();
+        let report = reports.recv().await.unwrap();
+        assert!(report.is_clean(), "{}", report);
+        assert_eq!(report.timeouts.len(), 1);
+        assert_eq!(report.timeouts[0].unsatisfied_by_patterns, vec![never]);
+        server_handle.join().await;
+        std::fs::remove_dir_all(scratch_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_pattern_edges_match_reported_attributes() {
+        use crate::attributes::{EVENT, TAG};
+        let scratch_dir = crate::test_scratch_dir("attributes");
+        let mut server_handle = server::run(0, 1).await.unwrap();
+        let (updates, acks, reports) = &mut server_handle.updates_acks[0];
+        let event = |kind, tag| Attributes::new().with(EVENT, kind).with(TAG, tag);
+        let pattern = |hid: &str, fedid, kind| {
+            HookInvocationPattern::new(
+                HookId::new(hid.into(), FederateId(fedid)),
+                SeqnumPattern::Any,
+            )
+            .with_attributes(event(kind, 10))
+        };
+        let mut precedence = Precedence::from_list(2, &[], scratch_dir.clone(), 6);
+        precedence.pattern_edges = vec![PatternEdge {
+            notifier: pattern("receive", 0, "LTC"),
+            waiters: vec![pattern("send", 1, "TAG")],
+        }];
+        updates
+            .send(Update::Start(precedence.clone()))
+            .await
+            .unwrap();
+        let (port, precid) = port_and_precid(acks.recv().await.unwrap().unwrap());
+        let notifier = start_client(port, precid, &precedence, 0).await;
+        let waiter = start_client(port, precid, &precedence, 1).await;
+        let start = Instant::now();
+        let waiting = tokio::spawn(async move {
+            let mut waited = vec![];
+            // The sequence numbers of the invocations do not matter, only what they report.
+            for (seqnum, tag) in [(0, 5), (1, 10)] {
+                waiter
+                    .tracepoint_maybe_wait_with_attributes(
+                        HookInvocation::from_short(("send", 1, seqnum)),
+                        &event("TAG", tag),
+                    )
+                    .await;
+                waited.push(start.elapsed());
+            }
+            waiter.finish().await;
+            waited
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        for (seqnum, (kind, tag)) in [("LTC", 5), ("TAG", 10), ("LTC", 10)]
+            .into_iter()
+            .enumerate()
+        {
+            notifier
+                .tracepoint_maybe_notify_with_attributes(
+                    HookInvocation::from_short(("receive", 0, seqnum as u32)),
+                    &event(kind, tag),
+                )
+                .await;
+        }
+        let waited = waiting.await.unwrap();
+        assert!(waited[0] < Duration::from_millis(100));
+        assert!(waited[1] >= Duration::from_millis(100));
+        assert!(waited[1] < Duration::from_millis(300));
+        notifier.finish().await;
+        updates.send(Update::Finish).await.unwrap();
+        let report = reports.recv().await.unwrap();
+        assert!(report.is_clean(), "{}", report);
+        assert!(report.timeouts.is_empty(), "{}", report);
+        server_handle.join().await;
+        std::fs::remove_dir_all(scratch_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_waiters_join_all_or_any_of_their_notifiers() {
+        let scratch_dir = crate::test_scratch_dir("join");
+        let mut server_handle = server::run(0, 1).await.unwrap();
+        let (updates, acks, reports) = &mut server_handle.updates_acks[0];
+        let waiters: &[_] = &[("any", 1, 0), ("all", 1, 0)];
+        let mut precedence = Precedence::from_list(
+            2,
+            &[(("first", 0, 0), waiters), (("second", 0, 0), waiters)],
+            scratch_dir.clone(),
+            7,
+        );
+        precedence
+            .joins
+            .insert(HookInvocation::from_short(("all", 1, 0)), Join::All);
+        updates
+            .send(Update::Start(precedence.clone()))
+            .await
+            .unwrap();
+        let (port, precid) = port_and_precid(acks.recv().await.unwrap().unwrap());
+        let notifier = start_client(port, precid, &precedence, 0).await;
+        let waiter = start_client(port, precid, &precedence, 1).await;
+        let start = Instant::now();
+        let waiting = tokio::spawn(async move {
+            let mut waited = vec![];
+            for hid in ["any", "all"] {
+                waiter
+                    .tracepoint_maybe_wait(HookInvocation::from_short((hid, 1, 0)))
+                    .await;
+                waited.push(start.elapsed());
+            }
+            waiter.finish().await;
+            waited
+        });
+        for hid in ["first", "second"] {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            notifier
+                .tracepoint_maybe_notify(HookInvocation::from_short((hid, 0, 0)))
+                .await;
+        }
+        let waited = waiting.await.unwrap();
+        assert!(waited[0] >= Duration::from_millis(100));
+        assert!(waited[0] < Duration::from_millis(200));
+        assert!(waited[1] >= Duration::from_millis(200));
+        notifier.finish().await;
+        updates.send(Update::Finish).await.unwrap();
+        let report = reports.recv().await.unwrap();
+        assert!(report.is_clean(), "{}", report);
+        assert!(report.timeouts.is_empty());
+        server_handle.join().await;
+        std::fs::remove_dir_all(scratch_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_delays_hold_back_hook_invocations() {
+        let scratch_dir = crate::test_scratch_dir("delay");
+        let mut server_handle = server::run(0, 1).await.unwrap();
+        let (updates, acks, reports) = &mut server_handle.updates_acks[0];
+        let slow = HookInvocation::from_short(("slow", 0, 0));
+        let mut precedence = Precedence::from_list(1, &[], scratch_dir.clone(), 11);
+        precedence
+            .delays
+            .insert(slow.clone(), Delay::Fixed(Duration::from_millis(100)));
+        updates
+            .send(Update::Start(precedence.clone()))
+            .await
+            .unwrap();
+        let (port, precid) = port_and_precid(acks.recv().await.unwrap().unwrap());
+        let client = start_client(port, precid, &precedence, 0).await;
+        let start = Instant::now();
+        client
+            .tracepoint_maybe_wait(HookInvocation::from_short(("slow", 0, 1)))
+            .await;
+        assert!(start.elapsed() < Duration::from_millis(100));
+        client.tracepoint_maybe_wait(slow).await;
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        client.finish().await;
+        updates.send(Update::Finish).await.unwrap();
+        let report = reports.recv().await.unwrap();
+        assert!(report.is_clean(), "{}", report);
+        server_handle.join().await;
+        std::fs::remove_dir_all(scratch_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_interleaving_is_recorded() {
+        let scratch_dir = crate::test_scratch_dir("record");
+        let mut server_handle = server::run(0, 1).await.unwrap();
+        let (updates, acks, reports) = &mut server_handle.updates_acks[0];
+        // The edges force the interleaving, because each hook invocation is recorded before the
+        // notifications that it sends.
+        let mut precedence = Precedence::from_list(
+            2,
+            &[(("A", 0, 0), &[("B", 1, 0)]), (("B", 1, 1), &[("A", 0, 1)])],
+            scratch_dir.clone(),
+            13,
+        );
+        precedence.record_interleaving = true;
+        updates
+            .send(Update::Start(precedence.clone()))
+            .await
+            .unwrap();
+        let (port, precid) = port_and_precid(acks.recv().await.unwrap().unwrap());
+        let clients = [
+            start_client(port, precid, &precedence, 0).await,
+            start_client(port, precid, &precedence, 1).await,
+        ];
+        let interleaving: Vec<_> = [("A", 0, 0), ("B", 1, 0), ("B", 1, 1), ("A", 0, 1)]
+            .into_iter()
+            .map(HookInvocation::from_short)
+            .collect();
+        let mut by_federate = vec![vec![], vec![]];
+        for hook_invocation in &interleaving {
+            by_federate[hook_invocation.hid.1 .0 as usize].push(hook_invocation.clone());
+        }
+        let running = clients
+            .into_iter()
+            .zip(by_federate)
+            .map(|(client, hook_invocations)| {
+                tokio::spawn(async move {
+                    for hook_invocation in hook_invocations {
+                        client.tracepoint_maybe_do(hook_invocation).await;
+                    }
+                    client.finish().await;
+                })
+            });
+        for handle in running.collect::<Vec<_>>() {
+            handle.await.unwrap();
+        }
+        updates.send(Update::Finish).await.unwrap();
+        let report = reports.recv().await.unwrap();
+        assert!(report.is_clean(), "{}", report);
+        assert_eq!(report.interleaving, interleaving);
+        server_handle.join().await;
+        std::fs::remove_dir_all(scratch_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_numbered_tracepoints_report_unlisted_hooks_by_id() {
+        let scratch_dir = crate::test_scratch_dir("numbered");
+        let (mut server_handle, connector) = server::run_in_memory(1).await;
+        let (updates, acks, reports) = &mut server_handle.updates_acks[0];
+        let mut precedence =
+            Precedence::from_list(2, &[(("A", 0, 0), &[("B", 1, 0)])], scratch_dir.clone(), 17);
+        precedence.record_interleaving = true;
+        updates.send(Update::Start(precedence)).await.unwrap();
+        let evars = acks.recv().await.unwrap().unwrap();
+        tokio::task::spawn_blocking(move || {
+            let start = |fedid| {
+                BlockingClient::start_in_memory(
+                    &connector,
+                    &evars,
+                    FederateId(fedid),
+                    Duration::from_secs(10),
+                )
+            };
+            let run = |(client, join_handle): (BlockingClient, MemoryBlockingClientJoinHandle),
+                       hooks: &'static [&'static str]| {
+                std::thread::spawn(move || {
+                    let numbers: Vec<_> = hooks
+                        .iter()
+                        .map(|hid| client.hook_number(&HookId::new(hid.to_string(), client.fedid)))
+                        .collect();
+                    for number in numbers {
+                        client.tracepoint_maybe_do_numbered(NumberedInvocation {
+                            hook: number.unwrap(),
+                            seqnum: crate::SequenceNumberByFileAndLine(0),
+                        });
+                    }
+                    // A number that the client never handed out is ignored.
+                    client.tracepoint_maybe_do_numbered(NumberedInvocation {
+                        hook: crate::intern::HookNumber(u32::MAX),
+                        seqnum: crate::SequenceNumberByFileAndLine(0),
+                    });
+                    client.halt.send(()).unwrap();
+                    drop(client);
+                    join_handle.join().unwrap();
+                })
+            };
+            let notifier = run(start(0), &["unlisted", "A"]);
+            let waiter = run(start(1), &["B"]);
+            notifier.join().unwrap();
+            waiter.join().unwrap();
+        })
+        .await
+        .unwrap();
+        updates.send(Update::Finish).await.unwrap();
+        let report = reports.recv().await.unwrap();
+        assert!(report.is_clean(), "{}", report);
+        assert!(report.timeouts.is_empty(), "{}", report);
+        assert_eq!(
+            report.interleaving,
+            [("unlisted", 0, 0), ("A", 0, 0), ("B", 1, 0)].map(HookInvocation::from_short)
+        );
+        updates.send(Update::Halt).await.unwrap();
+        server_handle.join().await;
+        std::fs::remove_dir_all(scratch_dir).unwrap();
+    }
+}
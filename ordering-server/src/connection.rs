@@ -1,24 +1,22 @@
 use std::{
+    fmt::Display,
     io,
     os::fd::{FromRawFd, IntoRawFd, RawFd},
 };
 
-use bytes::BufMut;
+use bytes::{BufMut, BytesMut};
 use log::{debug, error, info};
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt, BufWriter},
-    net::{unix, UnixStream},
+    net::{tcp, unix, TcpStream, UnixStream},
 };
 
-use crate::{FederateId, Frame, HookId, HookInvocation};
+use crate::{
+    attributes::Attributes,
+    protocol::{FrameDecoder, FrameKind, ProtocolError},
+    FederateId, Frame, HookId, HookInvocation,
+};
 
-const FRAME_SIZE: usize = std::mem::size_of::<Frame>();
-struct FrameBuffer([u8; FRAME_SIZE], usize);
-impl Default for FrameBuffer {
-    fn default() -> Self {
-        Self([0; FRAME_SIZE], 0)
-    }
-}
 pub struct Connection<R, W>
 where
     R: AsyncReadExt + Unpin,
@@ -32,7 +30,7 @@ where
     R: AsyncReadExt + Unpin,
 {
     pub stream: R, // FIXME: should be private
-    buffer: FrameBuffer,
+    decoder: FrameDecoder,
 }
 impl<R> ReadConnection<R>
 where
@@ -41,7 +39,7 @@ where
     pub fn new(stream: R) -> Self {
         Self {
             stream,
-            buffer: FrameBuffer::default(),
+            decoder: FrameDecoder::new(),
         }
     }
 }
@@ -53,24 +51,41 @@ where
     pub stream: BufWriter<W>, // FIXME: should be private
 }
 
-unsafe impl BufMut for FrameBuffer {
-    // FIXME: not thread-safe
-    fn remaining_mut(&self) -> usize {
-        FRAME_SIZE - self.1
-    }
+#[derive(Debug)]
+pub enum FrameError {
+    Io(io::Error),
+    Protocol(ProtocolError),
+    /// The peer closed the connection in the middle of a frame.
+    Truncated {
+        n_buffered: usize,
+    },
+}
 
-    unsafe fn advance_mut(&mut self, cnt: usize) {
-        if self.1 + cnt > FRAME_SIZE {
-            panic!("Frame buffer overflow");
+impl Display for FrameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FrameError::Io(e) => write!(f, "I/O error: {}", e),
+            FrameError::Protocol(e) => write!(f, "{}", e),
+            FrameError::Truncated { n_buffered } => write!(
+                f,
+                "connection closed with {} bytes of an incomplete frame still buffered",
+                n_buffered
+            ),
         }
-        self.1 += cnt;
     }
+}
 
-    fn chunk_mut(&mut self) -> &mut bytes::buf::UninitSlice {
-        if self.1 % FRAME_SIZE != 0 {
-            panic!("Frame buffer is not aligned");
-        }
-        unsafe { std::mem::transmute(&mut self.0[self.1..]) }
+impl std::error::Error for FrameError {}
+
+impl From<io::Error> for FrameError {
+    fn from(e: io::Error) -> Self {
+        FrameError::Io(e)
+    }
+}
+
+impl From<ProtocolError> for FrameError {
+    fn from(e: ProtocolError) -> Self {
+        FrameError::Protocol(e)
     }
 }
 
@@ -78,27 +93,55 @@ impl<R> ReadConnection<R>
 where
     R: AsyncReadExt + Unpin,
 {
-    pub async fn read_frame(&mut self) -> Option<Frame> {
-        debug!("Reading frame...");
-        if self.stream.read_buf(&mut self.buffer).await.unwrap_or(0) == 0 {
-            return None;
+    /// Returns `Ok(None)` if the connection was closed cleanly between two frames.
+    ///
+    /// This is cancellation safe: if it is used in a `select!` and some other branch completes
+    /// first, any part of a frame that was already read is kept for the next call.
+    pub async fn read_frame(&mut self) -> Result<Option<Frame>, FrameError> {
+        loop {
+            if let Some(frame) = self.decoder.next_frame()? {
+                debug!("Got frame");
+                return Ok(Some(frame));
+            }
+            debug!("Reading frame...");
+            if self.stream.read_buf(self.decoder.buffer_mut()).await? == 0 {
+                return if self.decoder.n_buffered() == 0 {
+                    Ok(None)
+                } else {
+                    Err(FrameError::Truncated {
+                        n_buffered: self.decoder.n_buffered(),
+                    })
+                };
+            }
         }
-        debug!("Got frame");
-        if self.buffer.1 < std::mem::size_of::<Frame>() {
-            panic!("Frame buffer is too small");
+    }
+}
+
+impl<R> ReadConnection<R>
+where
+    R: AsyncReadExt + Unpin,
+{
+    /// Like [`Self::read_frame`], but never reads any bytes past the end of the frame that it
+    /// returns. This must be used for the handshake on connections that are handed off to another
+    /// task as a raw file descriptor afterward, because anything buffered here would be lost.
+    pub async fn read_frame_exact(&mut self) -> Result<Option<Frame>, FrameError> {
+        loop {
+            if let Some(frame) = self.decoder.next_frame()? {
+                return Ok(Some(frame));
+            }
+            let n_missing = self.decoder.n_missing()?;
+            let buffer = self.decoder.buffer_mut();
+            buffer.reserve(n_missing);
+            if self.stream.read_buf(&mut buffer.limit(n_missing)).await? == 0 {
+                return if self.decoder.n_buffered() == 0 {
+                    Ok(None)
+                } else {
+                    Err(FrameError::Truncated {
+                        n_buffered: self.decoder.n_buffered(),
+                    })
+                };
+            }
         }
-        let frame = unsafe {
-            std::mem::transmute::<[u8; std::mem::size_of::<Frame>()], Frame>(
-                self.buffer.0[0..std::mem::size_of::<Frame>()]
-                    .try_into()
-                    .unwrap(),
-            )
-        };
-        self.buffer
-            .0
-            .copy_within(std::mem::size_of::<Frame>()..FRAME_SIZE, 0);
-        self.buffer.1 -= std::mem::size_of::<Frame>();
-        Some(frame)
     }
 }
 
@@ -106,18 +149,15 @@ impl<W> WriteConnection<W>
 where
     W: AsyncWriteExt + Unpin,
 {
-    pub async fn write_frame(&mut self, frame: Frame) {
+    pub async fn write_frame(&mut self, frame: &Frame) -> Result<(), FrameError> {
         debug!("Writing frame to the socket: {:?}", frame);
-        self.stream
-            .write_all(&unsafe { std::mem::transmute::<Frame, [u8; FRAME_SIZE]>(frame) })
-            .await
-            .unwrap();
+        let mut encoded = BytesMut::with_capacity(frame.encoded_len());
+        frame.encode(&mut encoded)?;
+        self.stream.write_all(&encoded).await?;
         debug!("Flushing frame to the socket");
-        let result = self.stream.flush().await;
+        self.stream.flush().await?;
         debug!("Flushed frame to the socket");
-        if let Err(e) = result {
-            debug!("Failed to flush frame: {:?}", e);
-        }
+        Ok(())
     }
 }
 
@@ -129,20 +169,19 @@ where
     pub fn new(stream: (R, W)) -> Self {
         let (read, write) = stream;
         Self {
-            read: ReadConnection {
-                stream: read,
-                buffer: FrameBuffer::default(),
-            },
+            read: ReadConnection::new(read),
             write: WriteConnection {
                 stream: BufWriter::new(write),
             },
         }
     }
-    pub async fn read_frame(&mut self) -> Option<Frame> {
+    pub async fn read_frame(&mut self) -> Result<Option<Frame>, FrameError> {
         self.read.read_frame().await
     }
-    #[allow(dead_code)]
-    pub async fn write_frame(&mut self, frame: Frame) {
+    pub async fn read_frame_exact(&mut self) -> Result<Option<Frame>, FrameError> {
+        self.read.read_frame_exact().await
+    }
+    pub async fn write_frame(&mut self, frame: &Frame) -> Result<(), FrameError> {
         self.write.write_frame(frame).await
     }
     pub async fn close(mut self) {
@@ -168,6 +207,23 @@ where
     pub unborrow: unsafe fn((ReadConnection<R>, WriteConnection<W>)),
 }
 
+impl<R, W> Clone for ConnectionManagement<R, W>
+where
+    R: AsyncReadExt + Unpin,
+    W: AsyncWriteExt + Unpin,
+{
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<R, W> Copy for ConnectionManagement<R, W>
+where
+    R: AsyncReadExt + Unpin,
+    W: AsyncWriteExt + Unpin,
+{
+}
+
 pub struct CreateConnectionError {
     pub message: String,
 }
@@ -190,6 +246,25 @@ pub const UNIX_CONNECTION_MANAGEMENT: ConnectionManagement<
     },
 };
 
+/// TCP connections are made afresh for every run, so giving one back closes it.
+pub const TCP_CONNECTION_MANAGEMENT: ConnectionManagement<tcp::OwnedReadHalf, tcp::OwnedWriteHalf> =
+    ConnectionManagement {
+        borrow: |fd| Ok(unsafe { Connection::new(tcp_socket_from_raw_fd(fd)?) }),
+        unborrow: |(r, w)| {
+            if let Err(e) = r.stream.reunite(w.stream.into_inner()) {
+                error!("Failed to reunite connection: {}", e);
+            }
+        },
+    };
+
+unsafe fn tcp_socket_from_raw_fd(
+    fd: RawFd,
+) -> io::Result<(tcp::OwnedReadHalf, tcp::OwnedWriteHalf)> {
+    let std = std::net::TcpStream::from_raw_fd(fd);
+    std.set_nonblocking(true)?;
+    Ok(TcpStream::from_std(std)?.into_split())
+}
+
 unsafe fn socket_from_raw_fd(fd: RawFd) -> io::Result<(unix::OwnedReadHalf, unix::OwnedWriteHalf)> {
     let std = std::os::unix::net::UnixStream::from_raw_fd(fd);
     std.set_nonblocking(true)?;
@@ -198,19 +273,72 @@ unsafe fn socket_from_raw_fd(fd: RawFd) -> io::Result<(unix::OwnedReadHalf, unix
 }
 
 impl Frame {
+    /// The sequence number of a hello frame is the process id of its sender, so that the server can
+    /// tell whether the process is still alive.
+    pub fn hello(precedence_id: u32, federate_id: FederateId, run_id: u32) -> Self {
+        Frame {
+            kind: FrameKind::Hello,
+            precedence_id,
+            federate_id: federate_id.0,
+            hook_id: String::new(),
+            hook_number: None,
+            sequence_number: std::process::id(),
+            run_id,
+            attributes: Attributes::new(),
+        }
+    }
+    pub fn notifier_gone(precedence_id: u32, federate_id: FederateId, run_id: u32) -> Self {
+        Frame {
+            kind: FrameKind::NotifierGone,
+            precedence_id,
+            federate_id: federate_id.0,
+            hook_id: String::new(),
+            hook_number: None,
+            sequence_number: 0,
+            run_id,
+            attributes: Attributes::new(),
+        }
+    }
+    pub fn notify(precedence_id: u32, hook_invocation: &HookInvocation, run_id: u32) -> Self {
+        Self::about(FrameKind::Notify, precedence_id, hook_invocation, run_id)
+    }
+    pub fn timeout(precedence_id: u32, waiter: &HookInvocation, run_id: u32) -> Self {
+        Self::about(FrameKind::Timeout, precedence_id, waiter, run_id)
+    }
+    pub fn record(precedence_id: u32, hook_invocation: &HookInvocation, run_id: u32) -> Self {
+        Self::about(FrameKind::Record, precedence_id, hook_invocation, run_id)
+    }
+    fn about(
+        kind: FrameKind,
+        precedence_id: u32,
+        hook_invocation: &HookInvocation,
+        run_id: u32,
+    ) -> Self {
+        Frame {
+            kind,
+            precedence_id,
+            federate_id: hook_invocation.hid.1 .0,
+            hook_id: hook_invocation.hid.0.clone(),
+            hook_number: None,
+            sequence_number: hook_invocation.seqnum.0,
+            run_id,
+            attributes: Attributes::new(),
+        }
+    }
+    /// Reports `attributes` along with the hook invocation of the frame.
+    pub fn with_attributes(mut self, attributes: &Attributes) -> Self {
+        self.attributes.clone_from(attributes);
+        self
+    }
+    /// The process id of the sender of a hello frame.
+    pub fn pid(&self) -> u32 {
+        self.sequence_number
+    }
     pub fn hid(&self) -> HookId {
-        let nul_range_end = self
-            .hook_id
-            .iter()
-            .position(|&c| c == b'\0')
-            .unwrap_or(self.hook_id.len()); // default to length if no `\0` present
-        HookId(
-            std::str::from_utf8(&self.hook_id[0..nul_range_end])
-                .unwrap()
-                .to_string(),
-            FederateId(self.federate_id),
-        )
+        HookId(self.hook_id.clone(), FederateId(self.federate_id))
     }
+    /// The hook invocation of a frame that names its hook by hook id. Frames that name their hook by
+    /// number need the [`crate::intern::HookTable`] of the precedence instead.
     pub fn hook_invocation(&self) -> HookInvocation {
         HookInvocation {
             hid: self.hid(),
@@ -218,3 +346,69 @@ impl Frame {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SequenceNumberByFileAndLine;
+
+    #[tokio::test]
+    async fn test_read_frame_from_fragmented_stream() {
+        let (client, server) = tokio::io::duplex(64);
+        let (_, mut client_write) = tokio::io::split(client);
+        let (server_read, _) = tokio::io::split(server);
+        let mut reader = ReadConnection::new(server_read);
+        let frames: Vec<_> = (0..4)
+            .map(|i| {
+                Frame::notify(
+                    0,
+                    &HookInvocation {
+                        hid: HookId::new(format!("hook {}", i), FederateId(i)),
+                        seqnum: SequenceNumberByFileAndLine(i as u32),
+                    },
+                    1,
+                )
+            })
+            .collect();
+        let mut wire = BytesMut::new();
+        for frame in &frames {
+            frame.encode(&mut wire).unwrap();
+        }
+        let writer = tokio::spawn(async move {
+            for chunk in wire.chunks(5) {
+                client_write.write_all(chunk).await.unwrap();
+                tokio::task::yield_now().await;
+            }
+        });
+        for frame in &frames {
+            assert_eq!(reader.read_frame().await.unwrap().as_ref(), Some(frame));
+        }
+        writer.await.unwrap();
+        assert!(reader.read_frame().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_read_frame_exact_leaves_next_frame_unread() {
+        let (mut client, server) = tokio::io::duplex(1024);
+        let hello = Frame::hello(0, FederateId(2), 5);
+        let notify = Frame::notify(
+            0,
+            &HookInvocation {
+                hid: HookId::new("A".to_string(), FederateId(2)),
+                seqnum: SequenceNumberByFileAndLine(0),
+            },
+            5,
+        );
+        let mut wire = BytesMut::new();
+        hello.encode(&mut wire).unwrap();
+        notify.encode(&mut wire).unwrap();
+        client.write_all(&wire).await.unwrap();
+        let mut handshake_reader = ReadConnection::new(server);
+        assert_eq!(
+            handshake_reader.read_frame_exact().await.unwrap(),
+            Some(hello)
+        );
+        let mut reader = ReadConnection::new(handshake_reader.stream);
+        assert_eq!(reader.read_frame().await.unwrap(), Some(notify));
+    }
+}
@@ -0,0 +1,576 @@
+//! The control socket of the `ordserv` daemon, which lets test drivers that do not link against
+//! this crate use the ordering server.
+//!
+//! A driver connects to the Unix socket of the daemon and writes one JSON [`Request`] per line. The
+//! daemon answers each request with one JSON [`Response`] on its own line. For example:
+//!
+//! ```text
+//! > {"request":"submit","precedence":{"file":"/tmp/run0/precedence.json"}}
+//! < {"response":"submitted","run":0,"environment":{"ORDSERV_HOST":"127.0.0.1","ORDSERV_PORT":"40123",...}}
+//! > {"request":"status","run":0}
+//! < {"response":"status","status":{"run":0,"run_id":0,"n_connections":2,...}}
+//! > {"request":"tear_down","run":0}
+//! < {"response":"torn_down","summary":{"run_id":0,"anomalies":[],...}}
+//! ```
+//!
+//! A run whose precedence sets `step` holds its notifications until they are released with `step`
+//! requests, each of which answers with what the run is waiting for (see [`crate::step`]):
+//!
+//! ```text
+//! > {"request":"step","run":0,"command":"show"}
+//! < {"response":"stepped","state":{"held":[...],"blocked":[...]}}
+//! > {"request":"step","run":0,"command":"release","index":0}
+//! ```
+//!
+//! When the daemon is started with `--step`, it instead prompts on its terminal for the runs in
+//! step mode, one run after the other (see [`step::prompt`]).
+//!
+//! Every run is served by its own precedence stream, so any number of runs can be in progress at
+//! once. The processes of a run must be launched with the environment returned when the run was
+//! submitted, which tells them how to reach the server.
+
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    sync::mpsc,
+    task::JoinSet,
+};
+
+use crate::{
+    delay::Delay,
+    pattern::PatternEdge,
+    report::{Anomaly, Disconnect, RunReport, TimedOutWait},
+    server::{ServerHandle, ServerSubHandle, Update},
+    step::{self, StepCommand, StepState},
+    timeline::WaitStats,
+    HookId, HookInvocation, Join, Precedence, RunId, ORDSERV_WAIT_TIMEOUT_MILLISECONDS_ENV_VAR,
+};
+
+/// A precedence in a form that is convenient to write by hand or from a script.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrecedenceSpec {
+    pub n_connections: usize,
+    pub scratch_dir: PathBuf,
+    #[serde(default)]
+    pub run_id: u32,
+    #[serde(default)]
+    pub record_timeline: bool,
+    #[serde(default)]
+    pub record_interleaving: bool,
+    #[serde(default)]
+    pub step: bool,
+    /// Each notifier together with the hook invocations that wait for it. Hook invocations are
+    /// written as `[hook id, federate id, sequence number]`.
+    pub sender2waiters: Vec<(OwnedHookInvocationShort, Vec<OwnedHookInvocationShort>)>,
+    #[serde(default)]
+    pub pattern_edges: Vec<PatternEdge>,
+    /// The waiters that do not join their notifiers with the default, [`Join::Any`].
+    #[serde(default)]
+    pub joins: Vec<(OwnedHookInvocationShort, Join)>,
+    #[serde(default)]
+    pub delays: Vec<(OwnedHookInvocationShort, Delay)>,
+}
+
+pub type OwnedHookInvocationShort = (String, i32, u32);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PrecedenceSource {
+    /// The path of a JSON file that contains a [`PrecedenceSpec`].
+    File(PathBuf),
+    Inline(PrecedenceSpec),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "request", rename_all = "snake_case")]
+pub enum Request {
+    /// Starts a run that is governed by the given precedence.
+    Submit {
+        precedence: PrecedenceSource,
+    },
+    Status {
+        run: u32,
+    },
+    /// Ends a run and reports what happened during it.
+    TearDown {
+        run: u32,
+    },
+    /// Steps through a run that is in step mode.
+    Step {
+        run: u32,
+        #[serde(flatten)]
+        command: StepCommand,
+    },
+    /// Tears down every run and stops the daemon.
+    Shutdown,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "response", rename_all = "snake_case")]
+pub enum Response {
+    Submitted {
+        run: u32,
+        /// The environment variables to launch the processes of the run with.
+        environment: HashMap<String, String>,
+    },
+    Status {
+        status: RunStatus,
+    },
+    TornDown {
+        summary: RunSummary,
+    },
+    Stepped {
+        state: StepState,
+    },
+    ShuttingDown,
+    Error {
+        message: String,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunStatus {
+    pub run: u32,
+    pub run_id: RunId,
+    pub n_connections: usize,
+    pub scratch_dir: PathBuf,
+    pub seconds_elapsed: f64,
+}
+
+/// A [`RunReport`] in a form that can be written as JSON, which only allows strings as map keys.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunSummary {
+    pub run_id: RunId,
+    pub anomalies: Vec<Anomaly>,
+    pub timeouts: Vec<TimedOutWait>,
+    pub timeline: Option<PathBuf>,
+    pub wait_stats: Vec<(HookId, WaitStats)>,
+    pub interleaving: Vec<HookInvocation>,
+    pub disconnects: Vec<Disconnect>,
+    /// The report as it would be logged.
+    pub description: String,
+}
+
+impl From<RunReport> for RunSummary {
+    fn from(report: RunReport) -> Self {
+        let description = report.to_string();
+        RunSummary {
+            run_id: report.run_id,
+            anomalies: report.anomalies,
+            timeouts: report.timeouts,
+            timeline: report.timeline,
+            wait_stats: report.wait_stats.into_iter().collect(),
+            interleaving: report.interleaving,
+            disconnects: report.disconnects,
+            description,
+        }
+    }
+}
+
+impl PrecedenceSpec {
+    pub fn into_precedence(self) -> Precedence {
+        let sender2waiters: Vec<_> = self
+            .sender2waiters
+            .iter()
+            .map(|(notifier, waiters)| {
+                (
+                    (notifier.0.as_str(), notifier.1, notifier.2),
+                    waiters
+                        .iter()
+                        .map(|waiter| (waiter.0.as_str(), waiter.1, waiter.2))
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .collect();
+        let elements: Vec<_> = sender2waiters
+            .iter()
+            .map(|(notifier, waiters)| (*notifier, waiters.as_slice()))
+            .collect();
+        let mut precedence =
+            Precedence::from_list(self.n_connections, &elements, self.scratch_dir, self.run_id);
+        precedence.record_timeline = self.record_timeline;
+        precedence.record_interleaving = self.record_interleaving;
+        precedence.step = self.step;
+        precedence.pattern_edges = self.pattern_edges;
+        precedence.joins = self
+            .joins
+            .iter()
+            .map(|(waiter, join)| {
+                (
+                    HookInvocation::from_short((waiter.0.as_str(), waiter.1, waiter.2)),
+                    *join,
+                )
+            })
+            .collect();
+        precedence.delays = self
+            .delays
+            .iter()
+            .map(|(hinvoc, delay)| {
+                (
+                    HookInvocation::from_short((hinvoc.0.as_str(), hinvoc.1, hinvoc.2)),
+                    *delay,
+                )
+            })
+            .collect();
+        precedence
+    }
+}
+
+struct ActiveRun {
+    sub_handle: ServerSubHandle,
+    status: RunStatus,
+    started: Instant,
+}
+
+struct Daemon {
+    server: ServerHandle,
+    runs: Mutex<HashMap<u32, ActiveRun>>,
+    next_run: Mutex<u32>,
+    wait_timeout: Option<Duration>,
+    shutdown: mpsc::Sender<()>,
+    /// The runs in step mode that are waiting to be stepped through from the terminal.
+    prompts: Option<mpsc::UnboundedSender<(u32, mpsc::Sender<Update>)>>,
+}
+
+/// Serves the control socket at `socket_path` until a [`Request::Shutdown`] is received, then tears
+/// down all runs that are still in progress and shuts `server` down.
+///
+/// If `wait_timeout` is given, it is added to the environment of every run so that the processes
+/// of the run know how long to wait for notifications. If `prompt` is set, the runs in step mode
+/// are stepped through from stdin and stdout.
+pub async fn serve(
+    socket_path: &Path,
+    server: ServerHandle,
+    wait_timeout: Option<Duration>,
+    prompt: bool,
+) -> io::Result<()> {
+    let listener = bind(socket_path).await?;
+    info!("Listening for control connections on {:?}", socket_path);
+    let (shutdown_sender, mut shutdown_receiver) = mpsc::channel(1);
+    let prompts = prompt.then(|| {
+        let (prompts, runs) = mpsc::unbounded_channel();
+        tokio::spawn(prompt_runs(runs));
+        prompts
+    });
+    let daemon = Arc::new(Daemon {
+        server,
+        runs: Mutex::new(HashMap::new()),
+        next_run: Mutex::new(0),
+        wait_timeout,
+        shutdown: shutdown_sender,
+        prompts,
+    });
+    let mut connections = JoinSet::new();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                match accepted {
+                    Ok((stream, _)) => {
+                        connections.spawn(handle_control_connection(Arc::clone(&daemon), stream));
+                    }
+                    Err(e) => warn!("Failed to accept control connection: {}", e),
+                }
+            }
+            Some(_) = connections.join_next(), if !connections.is_empty() => {}
+            _ = shutdown_receiver.recv() => break,
+        }
+    }
+    info!("Shutting down");
+    let _ = std::fs::remove_file(socket_path);
+    connections.shutdown().await;
+    let Ok(daemon) = Arc::try_unwrap(daemon) else {
+        unreachable!("every control connection has stopped");
+    };
+    for (_, run) in daemon.runs.into_inner().unwrap() {
+        daemon.server.remove_sub_handle(run.sub_handle).await;
+    }
+    daemon.server.join().await;
+    Ok(())
+}
+
+/// Binds the control socket, replacing a stale socket file left behind by a daemon that did not
+/// shut down cleanly.
+async fn bind(socket_path: &Path) -> io::Result<UnixListener> {
+    if socket_path.exists() {
+        if UnixStream::connect(socket_path).await.is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("a daemon is already listening on {:?}", socket_path),
+            ));
+        }
+        std::fs::remove_file(socket_path)?;
+    }
+    UnixListener::bind(socket_path)
+}
+
+/// Steps through each run of `runs` from the terminal until the run stops stepping or the person
+/// moves on to the next run.
+async fn prompt_runs(mut runs: mpsc::UnboundedReceiver<(u32, mpsc::Sender<Update>)>) {
+    let mut input = BufReader::new(tokio::io::stdin());
+    while let Some((run, updates)) = runs.recv().await {
+        let mut output = tokio::io::stdout();
+        let result = match output
+            .write_all(format!("stepping through run {}\n", run).as_bytes())
+            .await
+        {
+            Ok(()) => step::prompt(&updates, &mut input, output).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            warn!("Stopped prompting for runs in step mode: {}", e);
+            return;
+        }
+    }
+}
+
+async fn handle_control_connection(daemon: Arc<Daemon>, stream: UnixStream) {
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+    loop {
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(e) => {
+                warn!("Failed to read from control connection: {}", e);
+                break;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => daemon.handle(request).await,
+            Err(e) => Response::Error {
+                message: format!("could not parse request: {}", e),
+            },
+        };
+        let mut bytes = serde_json::to_vec(&response).unwrap();
+        bytes.push(b'\n');
+        if let Err(e) = write.write_all(&bytes).await {
+            warn!("Failed to write to control connection: {}", e);
+            break;
+        }
+        if let Response::ShuttingDown = response {
+            let _ = daemon.shutdown.send(()).await;
+            break;
+        }
+    }
+}
+
+impl Daemon {
+    async fn handle(&self, request: Request) -> Response {
+        let result = match request {
+            Request::Submit { precedence } => self.submit(precedence).await,
+            Request::Status { run } => self.status(run),
+            Request::TearDown { run } => self.tear_down(run).await,
+            Request::Step { run, command } => self.step(run, command).await,
+            Request::Shutdown => Ok(Response::ShuttingDown),
+        };
+        result.unwrap_or_else(|message| Response::Error { message })
+    }
+
+    async fn submit(&self, source: PrecedenceSource) -> Result<Response, String> {
+        let spec = match source {
+            PrecedenceSource::Inline(spec) => spec,
+            PrecedenceSource::File(path) => {
+                let contents = tokio::fs::read(&path)
+                    .await
+                    .map_err(|e| format!("could not read {:?}: {}", path, e))?;
+                serde_json::from_slice(&contents)
+                    .map_err(|e| format!("could not parse {:?}: {}", path, e))?
+            }
+        };
+        tokio::fs::create_dir_all(&spec.scratch_dir)
+            .await
+            .map_err(|e| format!("could not create {:?}: {}", spec.scratch_dir, e))?;
+        let precedence = spec.into_precedence();
+        let stepping = precedence.step;
+        let status = RunStatus {
+            run: {
+                let mut next_run = self.next_run.lock().unwrap();
+                *next_run += 1;
+                *next_run - 1
+            },
+            run_id: precedence.run_id,
+            n_connections: precedence.n_connections,
+            scratch_dir: precedence.scratch_dir.clone(),
+            seconds_elapsed: 0.0,
+        };
+        let mut sub_handle = self.server.add_sub_handle();
+        let ack = match sub_handle.0.send(Update::Start(precedence)).await {
+            Ok(()) => sub_handle.1.recv().await,
+            Err(_) => None,
+        };
+        let evars = match ack {
+            Some(Ok(evars)) => evars,
+            Some(Err(e)) => {
+                self.server.remove_sub_handle(sub_handle).await;
+                return Err(e.to_string());
+            }
+            None => return Err("the server has shut down".to_string()),
+        };
+        let mut environment: HashMap<String, String> = evars
+            .0
+            .into_iter()
+            .map(|(k, v)| {
+                (
+                    k.to_string_lossy().into_owned(),
+                    v.to_string_lossy().into_owned(),
+                )
+            })
+            .collect();
+        if let Some(wait_timeout) = self.wait_timeout {
+            environment.insert(
+                ORDSERV_WAIT_TIMEOUT_MILLISECONDS_ENV_VAR.to_string(),
+                wait_timeout.as_millis().to_string(),
+            );
+        }
+        let run = status.run;
+        info!("Started run {}", run);
+        if let (true, Some(prompts)) = (stepping, &self.prompts) {
+            let _ = prompts.send((run, sub_handle.0.clone()));
+        }
+        self.runs.lock().unwrap().insert(
+            run,
+            ActiveRun {
+                sub_handle,
+                status,
+                started: Instant::now(),
+            },
+        );
+        Ok(Response::Submitted { run, environment })
+    }
+
+    fn status(&self, run: u32) -> Result<Response, String> {
+        let runs = self.runs.lock().unwrap();
+        let active = runs.get(&run).ok_or_else(|| no_such_run(run))?;
+        let mut status = active.status.clone();
+        status.seconds_elapsed = active.started.elapsed().as_secs_f64();
+        Ok(Response::Status { status })
+    }
+
+    async fn step(&self, run: u32, command: StepCommand) -> Result<Response, String> {
+        let updates = {
+            let runs = self.runs.lock().unwrap();
+            let active = runs.get(&run).ok_or_else(|| no_such_run(run))?;
+            active.sub_handle.0.clone()
+        };
+        match step::step(&updates, command).await {
+            Ok(state) => Ok(Response::Stepped { state }),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    async fn tear_down(&self, run: u32) -> Result<Response, String> {
+        let mut active = self
+            .runs
+            .lock()
+            .unwrap()
+            .remove(&run)
+            .ok_or_else(|| no_such_run(run))?;
+        let report = match active.sub_handle.0.send(Update::Finish).await {
+            Ok(()) => active.sub_handle.2.recv().await,
+            Err(_) => None,
+        };
+        self.server.remove_sub_handle(active.sub_handle).await;
+        info!("Tore down run {}", run);
+        match report {
+            Some(report) => Ok(Response::TornDown {
+                summary: report.into(),
+            }),
+            None => {
+                error!("Run {} ended without a report", run);
+                Err(format!("run {} ended without a report", run))
+            }
+        }
+    }
+}
+
+fn no_such_run(run: u32) -> String {
+    format!("there is no run {} in progress", run)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn request(
+        lines: &mut tokio::io::Lines<BufReader<tokio::net::unix::OwnedReadHalf>>,
+        write: &mut tokio::net::unix::OwnedWriteHalf,
+        request: &str,
+    ) -> Response {
+        write.write_all(request.as_bytes()).await.unwrap();
+        write.write_all(b"\n").await.unwrap();
+        serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_submit_status_and_tear_down() {
+        let dir = crate::test_scratch_dir("control");
+        let socket_path = dir.join("control.sock");
+        let server = crate::server::run(0, 0).await.unwrap();
+        let daemon = {
+            let socket_path = socket_path.clone();
+            tokio::spawn(async move {
+                serve(&socket_path, server, Some(Duration::from_millis(50)), false)
+                    .await
+                    .unwrap()
+            })
+        };
+        while !socket_path.exists() {
+            tokio::task::yield_now().await;
+        }
+        let (read, mut write) = UnixStream::connect(&socket_path)
+            .await
+            .unwrap()
+            .into_split();
+        let mut lines = BufReader::new(read).lines();
+        let submit = format!(
+            r#"{{"request":"submit","precedence":{{"inline":{{"n_connections":0,"scratch_dir":{:?},"sender2waiters":[[["A",0,0],[["B",1,0]]]]}}}}}}"#,
+            dir.join("run")
+        );
+        let Response::Submitted { run, environment } =
+            request(&mut lines, &mut write, &submit).await
+        else {
+            panic!("expected the run to be submitted");
+        };
+        assert!(environment.contains_key(crate::ORDSERV_PORT_ENV_VAR));
+        assert_eq!(environment[crate::ORDSERV_HOST_ENV_VAR], "127.0.0.1");
+        assert_eq!(environment[ORDSERV_WAIT_TIMEOUT_MILLISECONDS_ENV_VAR], "50");
+        let status = format!(r#"{{"request":"status","run":{}}}"#, run);
+        let Response::Status { status: run_status } =
+            request(&mut lines, &mut write, &status).await
+        else {
+            panic!("expected the status of the run");
+        };
+        assert_eq!(run_status.n_connections, 0);
+        let tear_down = format!(r#"{{"request":"tear_down","run":{}}}"#, run);
+        let Response::TornDown { summary } = request(&mut lines, &mut write, &tear_down).await
+        else {
+            panic!("expected the run to be torn down");
+        };
+        assert!(summary.anomalies.is_empty(), "{}", summary.description);
+        assert!(matches!(
+            request(&mut lines, &mut write, &status).await,
+            Response::Error { .. }
+        ));
+        assert!(matches!(
+            request(&mut lines, &mut write, r#"{"request":"shutdown"}"#).await,
+            Response::ShuttingDown
+        ));
+        drop((lines, write));
+        daemon.await.unwrap();
+        assert!(!socket_path.exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,81 @@
+//! Physical delays that are injected at hook invocations in addition to the precedences.
+//!
+//! Some bugs only show up when one side of a protocol is slow rather than strictly ordered after
+//! the other. A [`Delay`] in [`Precedence::delays`](crate::Precedence::delays) makes a client
+//! sleep at a hook invocation after any wait that the precedence requires there and before the
+//! hook invocation proceeds.
+
+use std::time::Duration;
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Delay {
+    Fixed(Duration),
+    /// A duration drawn uniformly from `min..=max`.
+    Uniform {
+        min: Duration,
+        max: Duration,
+        seed: u64,
+    },
+    /// A duration drawn from the exponential distribution with the given mean.
+    Exponential {
+        mean: Duration,
+        seed: u64,
+    },
+}
+
+impl Delay {
+    /// The duration of this delay. Random delays are drawn from a generator seeded with their
+    /// seed, so the same delay always has the same duration, in every process.
+    pub fn duration(&self) -> Duration {
+        match *self {
+            Delay::Fixed(duration) => duration,
+            Delay::Uniform { min, max, seed } if min < max => {
+                StdRng::seed_from_u64(seed).gen_range(min..=max)
+            }
+            Delay::Uniform { min, .. } => min,
+            Delay::Exponential { mean, seed } => {
+                let u: f64 = StdRng::seed_from_u64(seed).gen();
+                mean.mul_f64(-(1.0 - u).ln())
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Delay {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Delay::Fixed(duration) => write!(f, "{:?}", duration),
+            Delay::Uniform { min, max, seed } => {
+                write!(f, "uniform({:?}..={:?}, seed {})", min, max, seed)
+            }
+            Delay::Exponential { mean, seed } => {
+                write!(f, "exponential(mean {:?}, seed {})", mean, seed)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_random_delays_are_reproducible_and_in_range() {
+        let min = Duration::from_millis(2);
+        let max = Duration::from_millis(9);
+        for seed in 0..100 {
+            let delay = Delay::Uniform { min, max, seed };
+            let duration = delay.duration();
+            assert!((min..=max).contains(&duration));
+            assert_eq!(duration, delay.duration());
+        }
+        let exponential = Delay::Exponential {
+            mean: Duration::from_millis(5),
+            seed: 3,
+        };
+        assert_eq!(exponential.duration(), exponential.duration());
+    }
+}
@@ -0,0 +1,255 @@
+//! Compact numeric ids for the hooks of a precedence.
+//!
+//! Hook ids are strings, so looking them up at every tracepoint and sending them in every frame is
+//! costly. [`Precedence::number_hooks`] gives each hook that the precedence mentions a dense
+//! [`HookNumber`], and the numbers are written to the precedence file along with the rest of the
+//! precedence. A client resolves each hook id to its number once, with
+//! [`crate::client::BlockingClient::hook_number`]; from then on its tracepoints look the hook up by
+//! number, and the frames that it sends carry the number instead of the hook id. A hook that has no
+//! number takes no part in the precedence, so its tracepoints have nothing to do.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    attributes::Attributes, pattern::PatternIndex, FederateId, Frame, HookId, HookInvocation,
+    Precedence, SequenceNumberByFileAndLine,
+};
+
+/// The position of a hook in [`Precedence::hooks`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct HookNumber(pub u32);
+
+/// A hook invocation whose hook is known by its number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NumberedInvocation {
+    pub hook: HookNumber,
+    pub seqnum: SequenceNumberByFileAndLine,
+}
+
+/// Translates between the hook ids and the hook numbers of a precedence.
+#[derive(Debug, Clone, Default)]
+pub struct HookTable {
+    hooks: Vec<HookId>,
+    numbers: HashMap<HookId, HookNumber>,
+}
+
+impl HookTable {
+    pub fn new(hooks: Vec<HookId>) -> Self {
+        let numbers = hooks
+            .iter()
+            .enumerate()
+            .map(|(number, hid)| (hid.clone(), HookNumber(number as u32)))
+            .collect();
+        Self { hooks, numbers }
+    }
+    pub fn len(&self) -> usize {
+        self.hooks.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+    pub fn number(&self, hid: &HookId) -> Option<HookNumber> {
+        self.numbers.get(hid).copied()
+    }
+    pub fn hook(&self, number: HookNumber) -> Option<&HookId> {
+        self.hooks.get(number.0 as usize)
+    }
+    pub fn numbered(&self, hook_invocation: &HookInvocation) -> Option<NumberedInvocation> {
+        Some(NumberedInvocation {
+            hook: self.number(&hook_invocation.hid)?,
+            seqnum: hook_invocation.seqnum,
+        })
+    }
+    pub fn invocation(&self, numbered: NumberedInvocation) -> Option<HookInvocation> {
+        Some(HookInvocation {
+            hid: self.hook(numbered.hook)?.clone(),
+            seqnum: numbered.seqnum,
+        })
+    }
+    /// The hook invocation that `frame` is about, whether the frame names its hook by number or by
+    /// hook id. Returns `None` if the number is not in the table.
+    pub fn frame_invocation(&self, frame: &Frame) -> Option<HookInvocation> {
+        match frame.hook_number {
+            Some(hook) => self.invocation(NumberedInvocation {
+                hook,
+                seqnum: SequenceNumberByFileAndLine(frame.sequence_number),
+            }),
+            None => Some(frame.hook_invocation()),
+        }
+    }
+    /// Like [`HookTable::frame_invocation`], but without building a [`HookInvocation`] when the
+    /// frame already carries a number.
+    pub fn frame_numbered(&self, frame: &Frame) -> Option<NumberedInvocation> {
+        match frame.hook_number {
+            Some(hook) => (hook.0 < self.hooks.len() as u32).then_some(NumberedInvocation {
+                hook,
+                seqnum: SequenceNumberByFileAndLine(frame.sequence_number),
+            }),
+            None => self.numbered(&frame.hook_invocation()),
+        }
+    }
+}
+
+impl Precedence {
+    /// Numbers the hooks that take part in the precedence unless that has been done already. The
+    /// numbering depends only on the contents of the precedence, so a client that gets a precedence
+    /// that was never numbered agrees with the server on the numbers.
+    pub fn number_hooks(&mut self) {
+        if !self.hooks.is_empty() {
+            return;
+        }
+        let mut hooks: Vec<&HookId> = self.mentioned_hooks().collect();
+        hooks.sort_by(|a, b| (a.1 .0, &a.0).cmp(&(b.1 .0, &b.0)));
+        hooks.dedup();
+        self.hooks = hooks.into_iter().cloned().collect();
+    }
+    /// Every hook that the precedence mentions, with repetitions.
+    pub(crate) fn mentioned_hooks(&self) -> impl Iterator<Item = &HookId> {
+        self.sender2waiters
+            .iter()
+            .flat_map(|(notifier, waiters)| std::iter::once(notifier).chain(waiters))
+            .chain(self.joins.keys())
+            .chain(self.delays.keys())
+            .map(|hook_invocation| &hook_invocation.hid)
+            .chain(self.pattern_edges.iter().flat_map(|edge| {
+                std::iter::once(&edge.notifier.hid).chain(edge.waiters.iter().map(|w| &w.hid))
+            }))
+    }
+    pub fn hook_table(&self) -> HookTable {
+        HookTable::new(self.hooks.clone())
+    }
+    /// The waiters of each exact notifier, by number.
+    pub fn numbered_sender2waiters(
+        &self,
+        hooks: &HookTable,
+    ) -> HashMap<NumberedInvocation, Vec<NumberedInvocation>> {
+        let numbered = |hook_invocation| {
+            hooks
+                .numbered(hook_invocation)
+                .expect("every hook of sender2waiters is numbered")
+        };
+        self.sender2waiters
+            .iter()
+            .map(|(notifier, waiters)| (numbered(notifier), waiters.iter().map(numbered).collect()))
+            .collect()
+    }
+}
+
+impl PatternIndex<HookNumber> {
+    pub fn numbered(precedence: &Precedence, hooks: &HookTable) -> Self {
+        Self::with_keys(&precedence.pattern_edges, |hid| hooks.number(hid))
+    }
+    /// The edges whose notifier pattern matches `numbered`, which reported `attributes`.
+    pub fn numbered_notified_by<'a>(
+        &'a self,
+        numbered: NumberedInvocation,
+        attributes: &'a Attributes,
+    ) -> impl Iterator<Item = usize> + 'a {
+        self.notified_by_key(&numbered.hook, numbered.seqnum.0, attributes)
+    }
+    /// The waiter patterns that match `numbered`, which reported `attributes`.
+    pub fn numbered_waiting<'a>(
+        &'a self,
+        numbered: NumberedInvocation,
+        attributes: &'a Attributes,
+    ) -> impl Iterator<Item = crate::pattern::WaiterIndex> + 'a {
+        self.waiting_key(&numbered.hook, numbered.seqnum.0, attributes)
+    }
+}
+
+impl Frame {
+    /// A frame about one of the hook invocations of federate `federate_id` that names the hook by
+    /// number. Building it allocates nothing.
+    pub fn numbered(
+        kind: crate::protocol::FrameKind,
+        precedence_id: u32,
+        federate_id: FederateId,
+        numbered: NumberedInvocation,
+        run_id: u32,
+    ) -> Self {
+        Frame {
+            kind,
+            precedence_id,
+            federate_id: federate_id.0,
+            hook_id: String::new(),
+            hook_number: Some(numbered.hook),
+            sequence_number: numbered.seqnum.0,
+            run_id,
+            attributes: Attributes::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        pattern::{HookInvocationPattern, PatternEdge, SeqnumPattern},
+        protocol::{self, FrameKind},
+    };
+    use bytes::BytesMut;
+
+    #[test]
+    fn test_numbers_are_dense_and_survive_the_wire() {
+        let scratch_dir = crate::test_scratch_dir("intern");
+        let mut precedence = Precedence::from_list(
+            2,
+            &[(("B", 0, 0), &[("A", 1, 3), ("B", 1, 0)])],
+            scratch_dir.clone(),
+            0,
+        );
+        precedence.pattern_edges = vec![PatternEdge {
+            notifier: HookInvocationPattern::new(
+                HookId::new("A".into(), FederateId(1)),
+                SeqnumPattern::Any,
+            ),
+            waiters: vec![HookInvocationPattern::new(
+                HookId::new("C".into(), FederateId(0)),
+                SeqnumPattern::From(2),
+            )],
+        }];
+        precedence.number_hooks();
+        let hooks = precedence.hook_table();
+        let number = |hid: &str, fedid| hooks.number(&HookId::new(hid.into(), FederateId(fedid)));
+        assert_eq!(
+            [
+                number("B", 0),
+                number("C", 0),
+                number("A", 1),
+                number("B", 1)
+            ],
+            [0, 1, 2, 3].map(|n| Some(HookNumber(n)))
+        );
+        assert_eq!(number("unrelated", 0), None);
+        let mut renumbered = precedence.clone();
+        renumbered.number_hooks();
+        assert_eq!(renumbered.hooks, precedence.hooks);
+
+        let waiter = HookInvocation::from_short(("A", 1, 3));
+        let numbered = hooks.numbered(&waiter).unwrap();
+        let frame = Frame::numbered(FrameKind::Notify, 0, FederateId(1), numbered, 7);
+        let mut wire = BytesMut::new();
+        frame.encode(&mut wire).unwrap();
+        let decoded = protocol::decode(&mut wire).unwrap().unwrap();
+        assert_eq!(decoded, frame);
+        assert_eq!(hooks.frame_numbered(&decoded), Some(numbered));
+        assert_eq!(hooks.frame_invocation(&decoded), Some(waiter.clone()));
+        let by_id = Frame::notify(0, &waiter, 7);
+        assert_eq!(hooks.frame_numbered(&by_id), Some(numbered));
+        let unknown = Frame::numbered(
+            FrameKind::Notify,
+            0,
+            FederateId(1),
+            NumberedInvocation {
+                hook: HookNumber(4),
+                ..numbered
+            },
+            7,
+        );
+        assert_eq!(hooks.frame_numbered(&unknown), None);
+        assert_eq!(hooks.frame_invocation(&unknown), None);
+        std::fs::remove_dir_all(scratch_dir).unwrap();
+    }
+}
@@ -1,15 +1,39 @@
-use std::{collections::HashMap, ffi::OsString, fmt::Display, path::PathBuf};
+use std::{
+    collections::HashMap,
+    ffi::{OsStr, OsString},
+    fmt::Display,
+    path::PathBuf,
+};
 
 use serde::{Deserialize, Serialize};
-use tokio::sync::mpsc;
 
+pub mod analysis;
+pub mod attributes;
 pub mod client;
 pub mod connection;
+pub mod control;
+pub mod delay;
+pub mod intern;
+pub mod memory;
+pub mod pattern;
+pub mod protocol;
+pub mod replay;
+pub mod report;
 pub mod server;
+pub mod shm;
+pub mod step;
 pub mod tcpconnectionprovider;
+pub mod threads;
+pub mod timeline;
 
 pub const ORDSERV_PORT_ENV_VAR: &str = "ORDSERV_PORT";
+/// The host that the processes of a run connect to, together with [`ORDSERV_PORT_ENV_VAR`].
+/// Processes connect to `127.0.0.1` if it is not set.
+pub const ORDSERV_HOST_ENV_VAR: &str = "ORDSERV_HOST";
 pub const ORDSERV_WAIT_TIMEOUT_MILLISECONDS_ENV_VAR: &str = "ORDSERV_WAIT_TIMEOUT";
+/// Names the transport through which the processes of a run reach the server when it is not the
+/// default one for the way that they get their connections.
+pub const ORDSERV_TRANSPORT_ENV_VAR: &str = "ORDSERV_TRANSPORT";
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct PrecedenceId(pub u32);
@@ -27,13 +51,57 @@ pub struct HookInvocation {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Precedence {
     pub sender2waiters: HashMap<HookInvocation, Vec<HookInvocation>>,
+    /// The number of processes that the run expects, which are federates -1 (the RTI) through
+    /// `n_connections - 2`. The run does not wait for them, but it is reported if fewer connect.
+    /// Processes whose federates the precedence mentions may connect as well, so this may be 0
+    /// when every process of the run takes part in the precedence. See [`Precedence::federates`].
     pub n_connections: usize,
     pub scratch_dir: PathBuf,
     pub run_id: RunId, // to avoid getting mucked up by stragglers from previous runs
+    /// Whether to record a [`timeline`] of the run.
+    #[serde(default)]
+    pub record_timeline: bool,
+    /// Precedences between families of hook invocations, in addition to `sender2waiters`.
+    #[serde(default)]
+    pub pattern_edges: Vec<pattern::PatternEdge>,
+    /// How each waiter combines the notifications that it waits for. Waiters that are not listed
+    /// join with [`Join::Any`].
+    #[serde(default)]
+    pub joins: HashMap<HookInvocation, Join>,
+    /// How long to sleep at each hook invocation before it proceeds.
+    #[serde(default)]
+    pub delays: HashMap<HookInvocation, delay::Delay>,
+    /// Whether to record the order in which the hook invocations of the run happen. See
+    /// [`replay`].
+    #[serde(default)]
+    pub record_interleaving: bool,
+    /// Whether to hold the notifications of the run until a person releases them. See [`step`].
+    #[serde(default)]
+    pub step: bool,
+    /// The hooks that take part in the precedence. The position of a hook in this list is its
+    /// [`intern::HookNumber`]. Filled in by [`Precedence::number_hooks`].
+    #[serde(default)]
+    pub hooks: Vec<HookId>,
+}
+
+/// How a hook invocation that waits for several notifiers decides that it may proceed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Join {
+    /// Proceed as soon as any one of the notifiers has happened.
+    #[default]
+    Any,
+    /// Proceed only once every notifier has happened. Each pattern edge that the waiter matches
+    /// counts as one notifier.
+    All,
 }
 pub type HookInvocationShort<'a> = (&'a str, i32, u32);
 pub type PrecedenceElement<'a> = (HookInvocationShort<'a>, &'a [HookInvocationShort<'a>]);
 impl Precedence {
+    /// Decodes the contents of the precedence file that the server writes for the processes of a
+    /// run.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        rmp_serde::from_slice(bytes).map_err(|e| e.to_string())
+    }
     pub fn from_list(
         n_connections: usize,
         sender2waiters: &[PrecedenceElement],
@@ -53,8 +121,32 @@ impl Precedence {
             n_connections,
             scratch_dir,
             run_id: RunId(run_id),
+            record_timeline: false,
+            pattern_edges: vec![],
+            joins: HashMap::new(),
+            delays: HashMap::new(),
+            record_interleaving: false,
+            step: false,
+            hooks: vec![],
         }
     }
+    pub fn join(&self, waiter: &HookInvocation) -> Join {
+        self.joins.get(waiter).copied().unwrap_or_default()
+    }
+    /// The federates for which connections are provisioned and notifications are queued: the
+    /// `n_connections` federates that the run expects and every federate that the precedence
+    /// mentions, in order. Federates need not be numbered contiguously, and any of them may
+    /// connect at any time during the run. Processes of other federates may connect as well, but
+    /// only over connections that they make themselves, such as TCP connections.
+    pub fn federates(&self) -> Vec<FederateId> {
+        let mut federates: Vec<_> = (-1..self.n_connections as i32 - 1)
+            .map(FederateId)
+            .chain(self.mentioned_hooks().map(|hid| hid.1))
+            .collect();
+        federates.sort_by_key(|fedid| fedid.0);
+        federates.dedup();
+        federates
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Serialize, Deserialize, Clone)]
@@ -64,6 +156,10 @@ impl HookId {
     pub fn new(hid: String, fedid: FederateId) -> Self {
         Self(hid, fedid)
     }
+    /// The hook id under which the threads in `namespace` invoke this hook. See [`threads`].
+    pub fn in_thread(&self, namespace: &str) -> Self {
+        Self(format!("{}/{}", self.0, namespace), self.1)
+    }
 }
 
 impl Display for HookId {
@@ -72,6 +168,16 @@ impl Display for HookId {
     }
 }
 
+impl Display for HookInvocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}[{}]@federate{}",
+            self.hid, self.seqnum.0, self.hid.1 .0
+        )
+    }
+}
+
 impl HookInvocation {
     pub fn from_short(his: HookInvocationShort) -> Self {
         Self {
@@ -83,28 +189,45 @@ impl HookInvocation {
 #[derive(Debug)]
 pub struct EnvironmentVariables(pub Vec<(OsString, OsString)>);
 
+impl EnvironmentVariables {
+    pub fn get(&self, name: &str) -> Option<&OsStr> {
+        self.0
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_os_str())
+    }
+    /// The id under which the server serves the precedence that it acked with these variables.
+    pub fn precedence_id(&self) -> Option<PrecedenceId> {
+        let id = self.get(server::PRECEDENCE_ID_NAME)?.to_str()?;
+        id.parse().ok().map(PrecedenceId)
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Hash)]
 pub struct FederateId(pub i32);
 
-#[repr(C)]
-#[derive(Debug, Clone, Copy)]
+/// A message exchanged between the ordering server and a client. See [`protocol`] for how it is
+/// laid out on the wire.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Frame {
+    pub kind: protocol::FrameKind,
     pub precedence_id: u32,
     pub federate_id: i32,
-    pub hook_id: [u8; 32], // Assume hook id is no more than 31 ascii characters
+    /// Empty if the frame names its hook by `hook_number`.
+    pub hook_id: String,
+    pub hook_number: Option<intern::HookNumber>,
     pub sequence_number: u32,
     pub run_id: u32,
+    /// What the hook invocation of the frame reported about itself. Empty unless the hook
+    /// invocation reported something that the precedence may match on.
+    pub attributes: attributes::Attributes,
 }
 
-pub(crate) fn channel_vec<T>(
-    n_connection_streams: usize,
-) -> (Vec<mpsc::Sender<T>>, Vec<mpsc::Receiver<T>>) {
-    let mut senders = Vec::with_capacity(n_connection_streams);
-    let mut receivers = Vec::with_capacity(n_connection_streams);
-    for _ in 0..n_connection_streams {
-        let (sender, receiver) = mpsc::channel(1);
-        senders.push(sender);
-        receivers.push(receiver);
-    }
-    (senders, receivers)
+/// Creates a scratch dir of its own for the test `name`. The server writes the precedence file of
+/// a run to its scratch dir, so tests that share one can read each other's precedences.
+#[cfg(test)]
+pub(crate) fn test_scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("ordserv-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
 }
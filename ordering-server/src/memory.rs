@@ -0,0 +1,253 @@
+//! An in-process transport, so that a server and its clients can run as tasks and threads of a
+//! single process.
+//!
+//! Connections are [`DuplexStream`]s instead of sockets. The server still hands connections from
+//! the task that answers their handshakes to the task that serves their run as if they were file
+//! descriptors, so each connection is parked in a registry under a made-up descriptor in the
+//! meantime. The made-up descriptors are negative, so they are never mistaken for real ones.
+
+use std::{
+    collections::BTreeMap,
+    io,
+    os::fd::RawFd,
+    sync::{
+        atomic::{AtomicI32, Ordering},
+        Mutex,
+    },
+};
+
+use log::{debug, error};
+use tokio::{
+    io::{DuplexStream, ReadHalf, WriteHalf},
+    sync::mpsc,
+    task::JoinHandle,
+};
+
+use crate::{
+    connection::{Connection, ConnectionManagement, FrameError},
+    protocol::{FrameKind, ProtocolError},
+    tcpconnectionprovider::{handshake, ConnectionRoutes},
+    FederateId, Frame, PrecedenceId, RunId,
+};
+
+/// How many bytes may be in flight in each direction of a connection.
+const MEMORY_BUFFER_SIZE: usize = 64 * 1024;
+
+pub type MemoryReadHalf = ReadHalf<DuplexStream>;
+pub type MemoryWriteHalf = WriteHalf<DuplexStream>;
+
+static PARKED: Mutex<BTreeMap<RawFd, DuplexStream>> = Mutex::new(BTreeMap::new());
+static NEXT_FD: AtomicI32 = AtomicI32::new(-1);
+
+fn park(stream: DuplexStream) -> RawFd {
+    let fd = NEXT_FD.fetch_sub(1, Ordering::Relaxed);
+    PARKED.lock().unwrap().insert(fd, stream);
+    fd
+}
+
+/// In-memory connections are made afresh for every run, so giving one back closes it.
+pub const MEMORY_CONNECTION_MANAGEMENT: ConnectionManagement<MemoryReadHalf, MemoryWriteHalf> =
+    ConnectionManagement {
+        borrow: |fd| {
+            let stream = PARKED.lock().unwrap().remove(&fd).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no in-memory connection is parked under {}", fd),
+                )
+            })?;
+            Ok(Connection::new(tokio::io::split(stream)))
+        },
+        unborrow: |_| {},
+    };
+
+/// Opens connections to a server that was started with [`crate::server::run_in_memory`].
+#[derive(Debug, Clone)]
+pub struct MemoryConnector {
+    accepted: mpsc::UnboundedSender<DuplexStream>,
+}
+
+impl MemoryConnector {
+    /// Returns the client end of a new connection to the server. This does not need to be called
+    /// from within a runtime.
+    pub fn connect(&self) -> (MemoryReadHalf, MemoryWriteHalf) {
+        let (client, server) = tokio::io::duplex(MEMORY_BUFFER_SIZE);
+        if self.accepted.send(server).is_err() {
+            error!("Connected to an in-memory server that has shut down");
+        }
+        tokio::io::split(client)
+    }
+    /// Opens a connection for `federate_id` and says hello on it, for talking to the server frame
+    /// by frame instead of through a client.
+    pub async fn connect_as(
+        &self,
+        precid: PrecedenceId,
+        federate_id: FederateId,
+        run_id: RunId,
+    ) -> Result<Connection<MemoryReadHalf, MemoryWriteHalf>, FrameError> {
+        let mut connection = Connection::new(self.connect());
+        connection
+            .write_frame(&Frame::hello(precid.0, federate_id, run_id.0))
+            .await?;
+        match connection.read_frame().await? {
+            Some(frame) if frame.kind == FrameKind::Hello => Ok(connection),
+            _ => Err(FrameError::Protocol(ProtocolError::Malformed(
+                "the server did not acknowledge the handshake",
+            ))),
+        }
+    }
+}
+
+/// The in-memory counterpart of [`crate::tcpconnectionprovider::forwarding`]. Returns the
+/// connector through which clients connect and the handle of the task that forwards their
+/// connections, which must be aborted when the server shuts down.
+pub fn forwarding(routes: ConnectionRoutes) -> (MemoryConnector, JoinHandle<()>) {
+    let (accepted, mut accepted_receiver) = mpsc::unbounded_channel();
+    let handle = tokio::spawn(async move {
+        while let Some(stream) = accepted_receiver.recv().await {
+            let routes = routes.clone();
+            tokio::spawn(async move {
+                let Some((connection, hello)) =
+                    handshake(Connection::new(tokio::io::split(stream))).await
+                else {
+                    return;
+                };
+                let Some(connection_sender) =
+                    routes.lock().unwrap().get(&hello.precedence_id).cloned()
+                else {
+                    error!(
+                        "Received handshake for precedence id {}, which is not being served",
+                        hello.precedence_id
+                    );
+                    return;
+                };
+                let (r, w) = connection.into_split();
+                let fd = park(r.stream.unsplit(w.stream.into_inner()));
+                let connection = (
+                    fd,
+                    FederateId(hello.federate_id),
+                    RunId(hello.run_id),
+                    hello.pid(),
+                );
+                if connection_sender.send(connection).await.is_err() {
+                    debug!("Connection receiver dropped; closing connection.");
+                    PARKED.lock().unwrap().remove(&fd);
+                }
+            });
+        }
+    });
+    (MemoryConnector { accepted }, handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, sync::Arc, time::Duration};
+
+    use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+    use super::*;
+    use crate::{
+        client::BlockingClient,
+        server::{run_in_memory, Update},
+        FederateId, HookInvocation, Join, Precedence,
+    };
+
+    const N_FEDERATES: i32 = 3;
+    const N_INVOCATIONS_PER_FEDERATE: u32 = 5;
+
+    /// A precedence whose edges all go forward in a random interleaving of the federates, so that
+    /// it can always be satisfied.
+    fn random_precedence(rng: &mut StdRng, run_id: u32, scratch_dir: PathBuf) -> Precedence {
+        let mut federates: Vec<_> = (0..N_FEDERATES)
+            .flat_map(|fedid| std::iter::repeat_n(fedid, N_INVOCATIONS_PER_FEDERATE as usize))
+            .collect();
+        federates.shuffle(rng);
+        let mut seqnums = vec![0; N_FEDERATES as usize];
+        let interleaving: Vec<_> = federates
+            .into_iter()
+            .map(|fedid| {
+                let seqnum = seqnums[fedid as usize];
+                seqnums[fedid as usize] += 1;
+                HookInvocation::from_short(("hook", fedid, seqnum))
+            })
+            .collect();
+        let mut precedence = Precedence::from_list(N_FEDERATES as usize, &[], scratch_dir, run_id);
+        for (i, notifier) in interleaving.iter().enumerate() {
+            for waiter in &interleaving[i + 1..] {
+                if notifier.hid.1 != waiter.hid.1 && rng.gen_bool(0.2) {
+                    precedence
+                        .sender2waiters
+                        .entry(notifier.clone())
+                        .or_default()
+                        .push(waiter.clone());
+                    precedence.joins.insert(waiter.clone(), Join::All);
+                }
+            }
+        }
+        precedence
+    }
+
+    #[test]
+    fn test_blocking_clients_respect_random_precedences() {
+        let scratch_dir = crate::test_scratch_dir("random-precedences");
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let (mut server_handle, connector) = rt.block_on(run_in_memory(1));
+        let (updates, acks, reports) = &mut server_handle.updates_acks[0];
+        let mut rng = StdRng::seed_from_u64(0);
+        for run_id in 0..10 {
+            let precedence = random_precedence(&mut rng, run_id, scratch_dir.clone());
+            rt.block_on(updates.send(Update::Start(precedence.clone())))
+                .unwrap();
+            let evars = Arc::new(rt.block_on(acks.recv()).unwrap().unwrap());
+            let observed = Arc::new(Mutex::new(vec![]));
+            let threads: Vec<_> = (0..N_FEDERATES)
+                .map(|fedid| {
+                    let (connector, evars, observed) =
+                        (connector.clone(), evars.clone(), observed.clone());
+                    std::thread::spawn(move || {
+                        let (client, jh) = BlockingClient::start_in_memory(
+                            &connector,
+                            &evars,
+                            FederateId(fedid),
+                            Duration::from_secs(5),
+                        );
+                        for seqnum in 0..N_INVOCATIONS_PER_FEDERATE {
+                            let hook_invocation =
+                                HookInvocation::from_short(("hook", fedid, seqnum));
+                            client.tracepoint_maybe_wait(hook_invocation.clone());
+                            observed.lock().unwrap().push(hook_invocation.clone());
+                            client.tracepoint_maybe_notify(hook_invocation);
+                        }
+                        client.halt.send(()).unwrap();
+                        drop(client);
+                        jh.join().unwrap();
+                    })
+                })
+                .collect();
+            for thread in threads {
+                thread.join().unwrap();
+            }
+            rt.block_on(updates.send(Update::Finish)).unwrap();
+            let report = rt.block_on(reports.recv()).unwrap();
+            assert!(report.is_clean(), "{}", report);
+            assert!(report.timeouts.is_empty(), "{}", report);
+            let observed = observed.lock().unwrap();
+            let position = |hook_invocation| {
+                observed
+                    .iter()
+                    .position(|observed| observed == hook_invocation)
+                    .unwrap()
+            };
+            for (notifier, waiters) in &precedence.sender2waiters {
+                for waiter in waiters {
+                    assert!(
+                        position(notifier) < position(waiter),
+                        "{:?} proceeded before {:?}",
+                        waiter,
+                        notifier
+                    );
+                }
+            }
+        }
+        std::fs::remove_dir_all(scratch_dir).unwrap();
+    }
+}
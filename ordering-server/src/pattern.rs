@@ -0,0 +1,290 @@
+//! Precedences between whole families of hook invocations.
+//!
+//! An entry of [`Precedence::sender2waiters`] names exact hook invocations, so an ordering that
+//! applies to many invocations of a hook has to be spelled out one sequence number at a time. A
+//! [`PatternEdge`] instead matches hook invocations by hook id and a [`SeqnumPattern`]: every
+//! invocation of a waiter pattern waits until some invocation of the notifier pattern has
+//! happened. For example, "every invocation of B in federate 1 waits for the 3rd invocation of A in
+//! federate 0" is
+//!
+//! ```
+//! # use ordering_server::{pattern::*, FederateId, HookId};
+//! PatternEdge {
+//!     notifier: HookInvocationPattern::new(HookId::new("A".into(), FederateId(0)), SeqnumPattern::Exact(2)),
+//!     waiters: vec![HookInvocationPattern::new(HookId::new("B".into(), FederateId(1)), SeqnumPattern::Any)],
+//! };
+//! ```
+//!
+//! As with exact precedences, a hook invocation that matches the waiters of several edges may
+//! proceed as soon as any one of those edges has been notified.
+//!
+//! A pattern may also require the hook invocations that it matches to report certain
+//! [`Attributes`]; see [`crate::attributes`].
+
+use std::{collections::HashMap, fmt::Display, hash::Hash};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{attributes::Attributes, HookId, HookInvocation, Precedence};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SeqnumPattern {
+    Exact(u32),
+    /// Every invocation.
+    Any,
+    /// The invocations whose sequence numbers are in `start..end`.
+    Range {
+        start: u32,
+        end: u32,
+    },
+    /// Every `period`-th invocation, starting with the invocation with sequence number `start`.
+    Periodic {
+        start: u32,
+        period: u32,
+    },
+    /// All remaining invocations, starting with the invocation with sequence number `start`.
+    From(u32),
+}
+
+impl SeqnumPattern {
+    pub fn matches(&self, seqnum: u32) -> bool {
+        match *self {
+            SeqnumPattern::Exact(n) => seqnum == n,
+            SeqnumPattern::Any => true,
+            SeqnumPattern::Range { start, end } => (start..end).contains(&seqnum),
+            SeqnumPattern::Periodic { start, period } => {
+                seqnum >= start && (seqnum - start).is_multiple_of(period)
+            }
+            SeqnumPattern::From(start) => seqnum >= start,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct HookInvocationPattern {
+    pub hid: HookId,
+    pub seqnums: SeqnumPattern,
+    /// The attributes that a hook invocation must report to match.
+    #[serde(default)]
+    pub attributes: Attributes,
+}
+
+impl HookInvocationPattern {
+    pub fn new(hid: HookId, seqnums: SeqnumPattern) -> Self {
+        Self {
+            hid,
+            seqnums,
+            attributes: Attributes::new(),
+        }
+    }
+    pub fn with_attributes(mut self, attributes: Attributes) -> Self {
+        self.attributes = attributes;
+        self
+    }
+    /// Whether `hook_invocation` matches, given that it reported `attributes`.
+    pub fn matches(&self, hook_invocation: &HookInvocation, attributes: &Attributes) -> bool {
+        self.hid == hook_invocation.hid
+            && self.seqnums.matches(hook_invocation.seqnum.0)
+            && self.attributes.are_satisfied_by(attributes)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PatternEdge {
+    pub notifier: HookInvocationPattern,
+    pub waiters: Vec<HookInvocationPattern>,
+}
+
+/// Identifies one waiter pattern of one edge of [`Precedence::pattern_edges`].
+pub type WaiterIndex = (usize, usize);
+
+/// Finds the pattern edges that a hook invocation takes part in without looking at the edges of any
+/// other hook. Hooks are looked up by a key of type `K`, which is the hook id unless the hooks have
+/// been numbered (see [`crate::intern`]).
+#[derive(Debug, Clone)]
+pub struct PatternIndex<K = HookId> {
+    by_notifier: HashMap<K, Vec<(SeqnumPattern, Attributes, usize)>>,
+    by_waiter: HashMap<K, Vec<(SeqnumPattern, Attributes, WaiterIndex)>>,
+}
+
+impl PatternIndex {
+    pub fn new(edges: &[PatternEdge]) -> Self {
+        Self::with_keys(edges, |hid| Some(hid.clone()))
+    }
+    /// The edges whose notifier pattern matches `hook_invocation`, which reported `attributes`.
+    pub fn notified_by<'a>(
+        &'a self,
+        hook_invocation: &'a HookInvocation,
+        attributes: &'a Attributes,
+    ) -> impl Iterator<Item = usize> + 'a {
+        self.notified_by_key(&hook_invocation.hid, hook_invocation.seqnum.0, attributes)
+    }
+    /// The waiter patterns that match `hook_invocation`, which reported `attributes`.
+    pub fn waiting<'a>(
+        &'a self,
+        hook_invocation: &'a HookInvocation,
+        attributes: &'a Attributes,
+    ) -> impl Iterator<Item = WaiterIndex> + 'a {
+        self.waiting_key(&hook_invocation.hid, hook_invocation.seqnum.0, attributes)
+    }
+}
+
+impl<K: Eq + Hash> PatternIndex<K> {
+    /// Indexes `edges` by the key that `key` gives the hook of each pattern. Patterns whose hook
+    /// has no key are left out.
+    pub fn with_keys(edges: &[PatternEdge], key: impl Fn(&HookId) -> Option<K>) -> Self {
+        let mut index = PatternIndex {
+            by_notifier: HashMap::new(),
+            by_waiter: HashMap::new(),
+        };
+        for (edge_idx, edge) in edges.iter().enumerate() {
+            if let Some(notifier) = key(&edge.notifier.hid) {
+                index.by_notifier.entry(notifier).or_default().push((
+                    edge.notifier.seqnums,
+                    edge.notifier.attributes.clone(),
+                    edge_idx,
+                ));
+            }
+            for (waiter_idx, waiter) in edge.waiters.iter().enumerate() {
+                if let Some(waiter_key) = key(&waiter.hid) {
+                    index.by_waiter.entry(waiter_key).or_default().push((
+                        waiter.seqnums,
+                        waiter.attributes.clone(),
+                        (edge_idx, waiter_idx),
+                    ));
+                }
+            }
+        }
+        index
+    }
+    /// The edges whose notifier pattern matches the invocation with sequence number `seqnum` of the
+    /// hook with key `hook`, which reported `attributes`.
+    pub fn notified_by_key<'a>(
+        &'a self,
+        hook: &K,
+        seqnum: u32,
+        attributes: &'a Attributes,
+    ) -> impl Iterator<Item = usize> + 'a {
+        self.by_notifier
+            .get(hook)
+            .into_iter()
+            .flatten()
+            .filter(move |(seqnums, required, _)| {
+                seqnums.matches(seqnum) && required.are_satisfied_by(attributes)
+            })
+            .map(|(_, _, edge_idx)| *edge_idx)
+    }
+    /// The waiter patterns that match the invocation with sequence number `seqnum` of the hook with
+    /// key `hook`, which reported `attributes`.
+    pub fn waiting_key<'a>(
+        &'a self,
+        hook: &K,
+        seqnum: u32,
+        attributes: &'a Attributes,
+    ) -> impl Iterator<Item = WaiterIndex> + 'a {
+        self.by_waiter
+            .get(hook)
+            .into_iter()
+            .flatten()
+            .filter(move |(seqnums, required, _)| {
+                seqnums.matches(seqnum) && required.are_satisfied_by(attributes)
+            })
+            .map(|(_, _, waiter)| *waiter)
+    }
+}
+
+impl Precedence {
+    pub fn pattern_index(&self) -> PatternIndex {
+        PatternIndex::new(&self.pattern_edges)
+    }
+}
+
+impl Display for SeqnumPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SeqnumPattern::Exact(n) => write!(f, "{}", n),
+            SeqnumPattern::Any => write!(f, "*"),
+            SeqnumPattern::Range { start, end } => write!(f, "{}..{}", start, end),
+            SeqnumPattern::Periodic { start, period } => write!(f, "{}+{}k", start, period),
+            SeqnumPattern::From(start) => write!(f, "{}..", start),
+        }
+    }
+}
+
+impl Display for HookInvocationPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}[{}]@federate{}",
+            self.hid, self.seqnums, self.hid.1 .0
+        )?;
+        if !self.attributes.is_empty() {
+            write!(f, "{}", self.attributes)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FederateId;
+
+    fn pattern(hid: &str, fedid: i32, seqnums: SeqnumPattern) -> HookInvocationPattern {
+        HookInvocationPattern::new(HookId::new(hid.into(), FederateId(fedid)), seqnums)
+    }
+
+    #[test]
+    fn test_seqnum_patterns() {
+        let matching = |p: SeqnumPattern| (0..10).filter(|n| p.matches(*n)).collect::<Vec<_>>();
+        assert_eq!(matching(SeqnumPattern::Exact(3)), vec![3]);
+        assert_eq!(matching(SeqnumPattern::Any), (0..10).collect::<Vec<_>>());
+        assert_eq!(
+            matching(SeqnumPattern::Range { start: 2, end: 5 }),
+            vec![2, 3, 4]
+        );
+        assert_eq!(
+            matching(SeqnumPattern::Periodic {
+                start: 1,
+                period: 3
+            }),
+            vec![1, 4, 7]
+        );
+        assert_eq!(matching(SeqnumPattern::From(7)), vec![7, 8, 9]);
+    }
+
+    #[test]
+    fn test_index_only_matches_the_right_hook() {
+        let edges = vec![
+            PatternEdge {
+                notifier: pattern("A", 0, SeqnumPattern::Exact(2)),
+                waiters: vec![
+                    pattern("B", 1, SeqnumPattern::Any),
+                    pattern("C", 1, SeqnumPattern::From(4)),
+                ],
+            },
+            PatternEdge {
+                notifier: pattern("A", 0, SeqnumPattern::Any),
+                waiters: vec![pattern("C", 1, SeqnumPattern::Exact(5))],
+            },
+        ];
+        let index = PatternIndex::new(&edges);
+        let no_attributes = Attributes::new();
+        let notified_by = |his| {
+            index
+                .notified_by(&HookInvocation::from_short(his), &no_attributes)
+                .collect::<Vec<_>>()
+        };
+        let waiting = |his| {
+            index
+                .waiting(&HookInvocation::from_short(his), &no_attributes)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(notified_by(("A", 0, 2)), vec![0, 1]);
+        assert_eq!(notified_by(("A", 0, 3)), vec![1]);
+        assert!(notified_by(("A", 1, 2)).is_empty());
+        assert_eq!(waiting(("B", 1, 17)), vec![(0, 0)]);
+        assert!(waiting(("C", 1, 3)).is_empty());
+        assert_eq!(waiting(("C", 1, 5)), vec![(0, 1), (1, 0)]);
+    }
+}
@@ -0,0 +1,425 @@
+//! The wire format of the frames exchanged between the ordering server and its clients.
+//!
+//! Every frame starts with a fixed-size header:
+//!
+//! | bytes | field                                   |
+//! |-------|-----------------------------------------|
+//! | 0..4  | magic number `b"ORDS"`                  |
+//! | 4..6  | protocol version (big endian)           |
+//! | 6     | frame kind                              |
+//! | 7     | reserved (always zero)                  |
+//! | 8..12 | length of the body in bytes (big endian)|
+//!
+//! The layout of the header must never change. This is what makes it possible to give a clear
+//! error when a client and a server that were built against different versions of this crate try
+//! to talk to each other, instead of silently misinterpreting each other's frames.
+//!
+//! The body of a frame is:
+//!
+//! | bytes          | field                                |
+//! |----------------|--------------------------------------|
+//! | 0..4           | precedence id                        |
+//! | 4..8           | federate id                          |
+//! | 8..12          | run id                               |
+//! | 12..16         | sequence number                      |
+//! | 16..20         | hook number                          |
+//! | 20..22         | length `n` of the hook id            |
+//! | 22..22+n       | `n` bytes of UTF-8 hook id           |
+//! | 22+n..24+n     | length `m` of the attributes         |
+//! | 24+n..24+n+m   | `m` bytes of attributes              |
+//!
+//! A frame names its hook either by its [`crate::intern::HookNumber`], in which case the hook id is
+//! empty, or by its hook id, in which case the hook number is [`NO_HOOK_NUMBER`]. A hello frame has
+//! neither, and its sequence number is the process id of its sender.
+//!
+//! The [`crate::attributes::Attributes`] of a frame are a sequence of key-value pairs, sorted by
+//! key. Each key and each value is a big-endian `u16` length followed by that many bytes of UTF-8.
+
+use std::fmt::Display;
+
+use bytes::{Buf, BufMut, BytesMut};
+
+use crate::{attributes::Attributes, intern::HookNumber, Frame};
+
+pub const MAGIC: [u8; 4] = *b"ORDS";
+/// Bump this whenever the layout of the body of any frame changes or a frame kind is added.
+pub const PROTOCOL_VERSION: u16 = 7;
+pub const HEADER_SIZE: usize = 12;
+const FIXED_BODY_SIZE: usize = 24;
+/// The hook number of a frame that names its hook by hook id.
+pub const NO_HOOK_NUMBER: u32 = u32::MAX;
+pub const MAX_HOOK_ID_LEN: usize = u16::MAX as usize;
+/// The maximum number of bytes that the attributes of a frame take up on the wire.
+pub const MAX_ATTRIBUTES_LEN: usize = u16::MAX as usize;
+pub const MAX_BODY_SIZE: usize = FIXED_BODY_SIZE + MAX_HOOK_ID_LEN + MAX_ATTRIBUTES_LEN;
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FrameKind {
+    /// The first frame sent in each direction on a new connection. Its header carries the
+    /// protocol version of the sender.
+    Hello = 0,
+    /// A hook invocation that some other process may be waiting for has occurred.
+    Notify = 1,
+    /// The hook invocation in the frame gave up waiting for the hook invocations that it was
+    /// supposed to wait for.
+    Timeout = 2,
+    /// The hook invocation in the frame has happened. Only sent when the precedence asks for the
+    /// interleaving of the run to be recorded.
+    Record = 3,
+    /// Sent by the server when the federate in the frame went away while the run was still in
+    /// progress. The notifications that it had not sent yet will never arrive, so the hook
+    /// invocations that wait for them may proceed.
+    NotifierGone = 4,
+    /// The hook invocation in the frame started to wait for its notifiers. Only sent when the
+    /// precedence is in step mode; see [`crate::step`].
+    Blocked = 5,
+    /// The hook invocation in the frame got the notifications that it was waiting for. Only sent
+    /// when the precedence is in step mode.
+    Unblocked = 6,
+}
+
+impl TryFrom<u8> for FrameKind {
+    type Error = ProtocolError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(FrameKind::Hello),
+            1 => Ok(FrameKind::Notify),
+            2 => Ok(FrameKind::Timeout),
+            3 => Ok(FrameKind::Record),
+            4 => Ok(FrameKind::NotifierGone),
+            5 => Ok(FrameKind::Blocked),
+            6 => Ok(FrameKind::Unblocked),
+            other => Err(ProtocolError::UnknownFrameKind(other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolError {
+    BadMagic([u8; 4]),
+    VersionMismatch { ours: u16, theirs: u16 },
+    UnknownFrameKind(u8),
+    BodyTooLong(usize),
+    HookIdTooLong(usize),
+    AttributesTooLong(usize),
+    Malformed(&'static str),
+}
+
+impl Display for ProtocolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProtocolError::BadMagic(magic) => write!(
+                f,
+                "expected a frame starting with the magic number {:?}, but got {:?}; the peer does not speak the ordering protocol",
+                MAGIC, magic
+            ),
+            ProtocolError::VersionMismatch { ours, theirs } => write!(
+                f,
+                "protocol version mismatch: this side speaks version {} but the peer speaks version {}; rebuild the client and the server from the same version of ordering-server",
+                ours, theirs
+            ),
+            ProtocolError::UnknownFrameKind(kind) => write!(f, "unknown frame kind {}", kind),
+            ProtocolError::BodyTooLong(len) => write!(
+                f,
+                "frame body of length {} exceeds the maximum of {}",
+                len, MAX_BODY_SIZE
+            ),
+            ProtocolError::HookIdTooLong(len) => write!(
+                f,
+                "hook id of length {} exceeds the maximum of {}",
+                len, MAX_HOOK_ID_LEN
+            ),
+            ProtocolError::AttributesTooLong(len) => write!(
+                f,
+                "attributes of length {} exceed the maximum of {}",
+                len, MAX_ATTRIBUTES_LEN
+            ),
+            ProtocolError::Malformed(why) => write!(f, "malformed frame: {}", why),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub version: u16,
+    pub kind: FrameKind,
+    pub body_length: usize,
+}
+
+impl Header {
+    /// Parses a header from the first [`HEADER_SIZE`] bytes of `src`, which must be at least that
+    /// long.
+    pub fn decode(mut src: &[u8]) -> Result<Self, ProtocolError> {
+        let mut magic = [0; 4];
+        src.copy_to_slice(&mut magic);
+        if magic != MAGIC {
+            return Err(ProtocolError::BadMagic(magic));
+        }
+        let version = src.get_u16();
+        if version != PROTOCOL_VERSION {
+            return Err(ProtocolError::VersionMismatch {
+                ours: PROTOCOL_VERSION,
+                theirs: version,
+            });
+        }
+        let kind = FrameKind::try_from(src.get_u8())?;
+        let _reserved = src.get_u8();
+        let body_length = src.get_u32() as usize;
+        if body_length > MAX_BODY_SIZE {
+            return Err(ProtocolError::BodyTooLong(body_length));
+        }
+        Ok(Header {
+            version,
+            kind,
+            body_length,
+        })
+    }
+}
+
+/// Removes the first complete frame from `src` and decodes it. Returns `Ok(None)` if `src` does not
+/// yet contain a complete frame, in which case `src` is left untouched.
+pub fn decode(src: &mut BytesMut) -> Result<Option<Frame>, ProtocolError> {
+    if src.len() < HEADER_SIZE {
+        return Ok(None);
+    }
+    let header = Header::decode(&src[..HEADER_SIZE])?;
+    if src.len() < HEADER_SIZE + header.body_length {
+        src.reserve(HEADER_SIZE + header.body_length - src.len());
+        return Ok(None);
+    }
+    src.advance(HEADER_SIZE);
+    let body = src.split_to(header.body_length);
+    Frame::decode_body(&header, &body).map(Some)
+}
+
+/// Incrementally decodes a stream of frames that may arrive in arbitrarily sized pieces.
+///
+/// Bytes are appended to the decoder as they are read, and complete frames are taken out of it one
+/// at a time. A partial frame stays in the decoder until the rest of it arrives, and one read may
+/// yield any number of frames.
+#[derive(Debug, Default)]
+pub struct FrameDecoder {
+    buffer: BytesMut,
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        Self {
+            buffer: BytesMut::with_capacity(HEADER_SIZE),
+        }
+    }
+    /// The buffer into which newly read bytes should be appended.
+    pub fn buffer_mut(&mut self) -> &mut BytesMut {
+        &mut self.buffer
+    }
+    pub fn extend_from_slice(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+    /// Returns the next complete frame, if any. Once this returns an error, the stream is
+    /// desynchronized and no further frames can be decoded from it.
+    pub fn next_frame(&mut self) -> Result<Option<Frame>, ProtocolError> {
+        decode(&mut self.buffer)
+    }
+    /// The number of bytes of a partial frame that are waiting for the rest of the frame to arrive.
+    pub fn n_buffered(&self) -> usize {
+        self.buffer.len()
+    }
+    /// The number of bytes that must still be read before it is known whether the first frame in
+    /// the buffer is complete. Reading no more than this many bytes at a time guarantees that no
+    /// bytes of the next frame are read.
+    pub fn n_missing(&self) -> Result<usize, ProtocolError> {
+        if self.buffer.len() < HEADER_SIZE {
+            return Ok(HEADER_SIZE - self.buffer.len());
+        }
+        let header = Header::decode(&self.buffer[..HEADER_SIZE])?;
+        Ok((HEADER_SIZE + header.body_length).saturating_sub(self.buffer.len()))
+    }
+}
+
+impl Frame {
+    /// The number of bytes that this frame occupies on the wire.
+    pub fn encoded_len(&self) -> usize {
+        HEADER_SIZE + FIXED_BODY_SIZE + self.hook_id.len() + attributes_len(&self.attributes)
+    }
+
+    pub fn encode(&self, dst: &mut BytesMut) -> Result<(), ProtocolError> {
+        if self.hook_id.len() > MAX_HOOK_ID_LEN {
+            return Err(ProtocolError::HookIdTooLong(self.hook_id.len()));
+        }
+        let attributes_len = attributes_len(&self.attributes);
+        if attributes_len > MAX_ATTRIBUTES_LEN {
+            return Err(ProtocolError::AttributesTooLong(attributes_len));
+        }
+        dst.reserve(self.encoded_len());
+        dst.put_slice(&MAGIC);
+        dst.put_u16(PROTOCOL_VERSION);
+        dst.put_u8(self.kind as u8);
+        dst.put_u8(0);
+        dst.put_u32((self.encoded_len() - HEADER_SIZE) as u32);
+        dst.put_u32(self.precedence_id);
+        dst.put_i32(self.federate_id);
+        dst.put_u32(self.run_id);
+        dst.put_u32(self.sequence_number);
+        dst.put_u32(self.hook_number.map_or(NO_HOOK_NUMBER, |number| number.0));
+        dst.put_u16(self.hook_id.len() as u16);
+        dst.put_slice(self.hook_id.as_bytes());
+        dst.put_u16(attributes_len as u16);
+        for (key, value) in self.attributes.iter() {
+            dst.put_u16(key.len() as u16);
+            dst.put_slice(key.as_bytes());
+            dst.put_u16(value.len() as u16);
+            dst.put_slice(value.as_bytes());
+        }
+        Ok(())
+    }
+
+    /// Decodes the body of a frame whose header has already been parsed.
+    pub fn decode_body(header: &Header, mut body: &[u8]) -> Result<Self, ProtocolError> {
+        if body.len() < FIXED_BODY_SIZE {
+            return Err(ProtocolError::Malformed(
+                "body is shorter than its fixed part",
+            ));
+        }
+        let precedence_id = body.get_u32();
+        let federate_id = body.get_i32();
+        let run_id = body.get_u32();
+        let sequence_number = body.get_u32();
+        let hook_number = match body.get_u32() {
+            NO_HOOK_NUMBER => None,
+            number => Some(HookNumber(number)),
+        };
+        let hook_id_len = body.get_u16() as usize;
+        if body.len() < hook_id_len + 2 {
+            return Err(ProtocolError::Malformed(
+                "length of hook id does not match length of body",
+            ));
+        }
+        let hook_id = take_str(&mut body, hook_id_len)
+            .ok_or(ProtocolError::Malformed("hook id is not valid UTF-8"))?
+            .to_string();
+        let attributes_len = body.get_u16() as usize;
+        if body.len() != attributes_len {
+            return Err(ProtocolError::Malformed(
+                "length of attributes does not match length of body",
+            ));
+        }
+        let mut attributes = Attributes::new();
+        while body.has_remaining() {
+            let (Some(key), Some(value)) = (take_len_str(&mut body), take_len_str(&mut body))
+            else {
+                return Err(ProtocolError::Malformed(
+                    "attributes are truncated or not valid UTF-8",
+                ));
+            };
+            attributes.insert(key, value);
+        }
+        Ok(Frame {
+            kind: header.kind,
+            precedence_id,
+            federate_id,
+            hook_id,
+            hook_number,
+            sequence_number,
+            run_id,
+            attributes,
+        })
+    }
+}
+
+fn attributes_len(attributes: &Attributes) -> usize {
+    attributes
+        .iter()
+        .map(|(key, value)| 4 + key.len() + value.len())
+        .sum()
+}
+
+/// Takes `len` bytes of UTF-8 off the front of `src`, which must be at least that long.
+fn take_str<'a>(src: &mut &'a [u8], len: usize) -> Option<&'a str> {
+    let (s, rest) = src.split_at(len);
+    *src = rest;
+    std::str::from_utf8(s).ok()
+}
+
+/// Takes a length-prefixed string off the front of `src`.
+fn take_len_str<'a>(src: &mut &'a [u8]) -> Option<&'a str> {
+    if src.len() < 2 {
+        return None;
+    }
+    let len = src.get_u16() as usize;
+    if src.len() < len {
+        return None;
+    }
+    take_str(src, len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(hook_id: &str) -> Frame {
+        Frame {
+            kind: FrameKind::Notify,
+            precedence_id: 3,
+            federate_id: -1,
+            hook_id: hook_id.to_string(),
+            hook_number: None,
+            sequence_number: 7,
+            run_id: 42,
+            attributes: Attributes::new(),
+        }
+    }
+
+    #[test]
+    fn test_long_hook_id_round_trip() {
+        let original = frame(&"a long hook id that does not fit in 32 bytes ".repeat(4))
+            .with_attributes(
+                &Attributes::new()
+                    .with("event", "TAG")
+                    .with("tag", "Ünïcode"),
+            );
+        let mut buf = BytesMut::new();
+        original.encode(&mut buf).unwrap();
+        assert_eq!(buf.len(), original.encoded_len());
+        assert_eq!(decode(&mut buf).unwrap(), Some(original));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_decoder_partial_and_coalesced() {
+        let frames: Vec<_> = (0..5).map(|i| frame(&"B".repeat(i * 13))).collect();
+        let mut wire = BytesMut::new();
+        for f in &frames {
+            f.encode(&mut wire).unwrap();
+        }
+        for chunk_size in [1, 7, HEADER_SIZE, 50, wire.len()] {
+            let mut decoder = FrameDecoder::new();
+            let mut decoded = vec![];
+            for chunk in wire.chunks(chunk_size) {
+                decoder.extend_from_slice(chunk);
+                while let Some(f) = decoder.next_frame().unwrap() {
+                    decoded.push(f);
+                }
+            }
+            assert_eq!(decoded, frames);
+            assert_eq!(decoder.n_buffered(), 0);
+        }
+    }
+
+    #[test]
+    fn test_version_mismatch() {
+        let mut buf = BytesMut::new();
+        frame("A").encode(&mut buf).unwrap();
+        buf[5] = buf[5].wrapping_add(1);
+        assert_eq!(
+            decode(&mut buf),
+            Err(ProtocolError::VersionMismatch {
+                ours: PROTOCOL_VERSION,
+                theirs: PROTOCOL_VERSION + 1
+            })
+        );
+    }
+}
@@ -0,0 +1,80 @@
+//! Replaying the interleaving of hook invocations that was observed during a run.
+//!
+//! When [`Precedence::record_interleaving`] is set, every client reports each hook invocation to
+//! the server as it proceeds, and the order in which the server receives them is returned in
+//! [`crate::report::RunReport::interleaving`]. [`Precedence::replaying`] turns that order into a
+//! precedence that forces the same total order on a later run, as far as the hooks allow: the
+//! processes can still be interleaved differently between consecutive hook invocations.
+
+use std::{collections::HashSet, path::PathBuf};
+
+use crate::{HookInvocation, Precedence};
+
+impl Precedence {
+    /// A precedence in which every hook invocation in `interleaving` waits for the one before it.
+    /// Consecutive hook invocations of the same federate are already ordered by the program, so
+    /// only the edges between different federates are included. Only the first occurrence of a
+    /// hook invocation that occurs more than once is taken into account.
+    pub fn replaying(
+        interleaving: &[HookInvocation],
+        n_connections: usize,
+        scratch_dir: PathBuf,
+        run_id: u32,
+    ) -> Self {
+        let mut precedence = Precedence::from_list(n_connections, &[], scratch_dir, run_id);
+        let mut seen = HashSet::new();
+        let mut previous: Option<&HookInvocation> = None;
+        for hook_invocation in interleaving {
+            if !seen.insert(hook_invocation) {
+                continue;
+            }
+            if let Some(previous) = previous {
+                if previous.hid.1 != hook_invocation.hid.1 {
+                    precedence
+                        .sender2waiters
+                        .entry(previous.clone())
+                        .or_default()
+                        .push(hook_invocation.clone());
+                }
+            }
+            previous = Some(hook_invocation);
+        }
+        precedence
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replaying_chains_federate_switches() {
+        let interleaving: Vec<_> = [
+            ("A", 0, 0),
+            ("A", 0, 1),
+            ("B", 1, 0),
+            ("A", 0, 1),
+            ("C", 2, 0),
+            ("B", 1, 1),
+            ("B", 1, 2),
+            ("A", 0, 2),
+        ]
+        .into_iter()
+        .map(HookInvocation::from_short)
+        .collect();
+        let precedence = Precedence::replaying(&interleaving, 3, "/tmp".into(), 0);
+        let expected = Precedence::from_list(
+            3,
+            &[
+                (("A", 0, 1), &[("B", 1, 0)]),
+                (("B", 1, 0), &[("C", 2, 0)]),
+                (("C", 2, 0), &[("B", 1, 1)]),
+                (("B", 1, 2), &[("A", 0, 2)]),
+            ],
+            "/tmp".into(),
+            0,
+        );
+        assert_eq!(precedence.sender2waiters, expected.sender2waiters);
+        assert_eq!(precedence.check(), Ok(()));
+    }
+}
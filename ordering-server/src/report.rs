@@ -0,0 +1,227 @@
+//! What the server observed during a run, reported back to whoever submitted the precedence.
+
+use std::{collections::HashMap, fmt::Display, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    intern::HookNumber, pattern::HookInvocationPattern, timeline::WaitStats, FederateId, HookId,
+    HookInvocation, RunId,
+};
+
+/// Something that went wrong with the protocol during a run. None of these are fatal to the
+/// server; they are recorded and the run carries on as well as it can.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Anomaly {
+    /// A federate notified the server of a hook invocation that no one waits for.
+    UnexpectedFrame {
+        from: FederateId,
+        hook_invocation: HookInvocation,
+    },
+    /// A hook invocation waits for a notification, but the federate that executes it never
+    /// connected.
+    UnknownDestination {
+        hook_invocation: HookInvocation,
+        waiter: HookInvocation,
+    },
+    /// Like `UnknownDestination`, but for a waiter of a pattern edge.
+    UnknownPatternDestination {
+        hook_invocation: HookInvocation,
+        waiter: HookInvocationPattern,
+    },
+    /// A frame claimed to come from a different federate than the one that owns the connection.
+    FederateMismatch {
+        connection: FederateId,
+        claimed: FederateId,
+    },
+    /// A connection or a frame belonged to some other run, probably a straggler from an earlier
+    /// one.
+    StrayRunId { fedid: FederateId, run_id: RunId },
+    /// The run ended before all of the expected federates connected.
+    MissingConnections { expected: usize, received: usize },
+    /// A connection was handed to the server but could not be used.
+    BadConnection { error: String },
+    /// A notification could not be delivered because the federate that waits for it had already
+    /// closed its connection.
+    EarlyDisconnect {
+        fedid: FederateId,
+        undelivered: HookInvocation,
+    },
+    /// Reading from a federate failed. No further frames are read from it during the run.
+    ReadError { fedid: FederateId, error: String },
+    /// Forwarding a notification to a federate failed.
+    WriteError { fedid: FederateId, error: String },
+    /// A frame named its hook by a number that the precedence does not give any hook.
+    UnknownHookNumber {
+        from: FederateId,
+        number: HookNumber,
+    },
+}
+
+/// A hook invocation that gave up waiting, so the precedence was not fully enforced.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimedOutWait {
+    pub waiter: HookInvocation,
+    /// The hook invocations that `waiter` was waiting for whose notifications had not been
+    /// forwarded to it by the time it gave up.
+    pub unsatisfied_by: Vec<HookInvocation>,
+    /// Like `unsatisfied_by`, but for the notifiers of the pattern edges that `waiter` matched.
+    #[serde(default)]
+    pub unsatisfied_by_patterns: Vec<HookInvocationPattern>,
+}
+
+/// A federate that went away while some of the notifications that it was supposed to send were
+/// still outstanding, so that the hook invocations waiting for them were released instead.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Disconnect {
+    pub fedid: FederateId,
+    /// The process id that the federate sent in its hello frame.
+    pub pid: u32,
+    /// The hook invocations that were waiting for a notification from the federate that had not
+    /// been forwarded to them yet.
+    pub released: Vec<HookInvocation>,
+    /// Like `released`, but for the waiters of the pattern edges whose notifier is the federate.
+    pub released_patterns: Vec<HookInvocationPattern>,
+}
+
+/// Sent once for every run that was acknowledged, when the run ends.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunReport {
+    pub run_id: RunId,
+    pub anomalies: Vec<Anomaly>,
+    #[serde(default)]
+    pub timeouts: Vec<TimedOutWait>,
+    /// Where the timeline of the run was written, if one was recorded.
+    #[serde(default)]
+    pub timeline: Option<PathBuf>,
+    /// How long the waits at each hook took, if a timeline was recorded.
+    #[serde(default)]
+    pub wait_stats: HashMap<HookId, WaitStats>,
+    /// The order in which the hook invocations of the run happened, if it was recorded.
+    #[serde(default)]
+    pub interleaving: Vec<HookInvocation>,
+    /// The federates that went away early, in the order in which the server noticed. The first is
+    /// the process that died first.
+    #[serde(default)]
+    pub disconnects: Vec<Disconnect>,
+}
+
+impl RunReport {
+    pub fn new(run_id: RunId, anomalies: Vec<Anomaly>) -> Self {
+        Self {
+            run_id,
+            anomalies,
+            timeouts: vec![],
+            timeline: None,
+            wait_stats: HashMap::new(),
+            interleaving: vec![],
+            disconnects: vec![],
+        }
+    }
+    pub fn is_clean(&self) -> bool {
+        self.anomalies.is_empty()
+    }
+}
+
+impl Display for Anomaly {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Anomaly::UnexpectedFrame {
+                from,
+                hook_invocation,
+            } => write!(
+                f,
+                "federate {} sent a notification for {}, which no one waits for",
+                from.0, hook_invocation
+            ),
+            Anomaly::UnknownDestination {
+                hook_invocation,
+                waiter,
+            } => write!(
+                f,
+                "could not forward {} to {} because its federate is not connected",
+                hook_invocation, waiter
+            ),
+            Anomaly::UnknownPatternDestination {
+                hook_invocation,
+                waiter,
+            } => write!(
+                f,
+                "could not forward {} to {} because its federate is not connected",
+                hook_invocation, waiter
+            ),
+            Anomaly::FederateMismatch {
+                connection,
+                claimed,
+            } => write!(
+                f,
+                "the connection of federate {} sent a frame claiming to be from federate {}",
+                connection.0, claimed.0
+            ),
+            Anomaly::StrayRunId { fedid, run_id } => write!(
+                f,
+                "federate {} sent a connection or frame for run {}",
+                fedid.0, run_id.0
+            ),
+            Anomaly::MissingConnections { expected, received } => write!(
+                f,
+                "the run ended after only {} of {} connections were received",
+                received, expected
+            ),
+            Anomaly::BadConnection { error } => write!(f, "unusable connection: {}", error),
+            Anomaly::EarlyDisconnect { fedid, undelivered } => write!(
+                f,
+                "federate {} disconnected before {} could be delivered to it",
+                fedid.0, undelivered
+            ),
+            Anomaly::ReadError { fedid, error } => {
+                write!(f, "failed to read from federate {}: {}", fedid.0, error)
+            }
+            Anomaly::WriteError { fedid, error } => {
+                write!(f, "failed to write to federate {}: {}", fedid.0, error)
+            }
+            Anomaly::UnknownHookNumber { from, number } => write!(
+                f,
+                "federate {} sent a frame about unknown hook number {}",
+                from.0, number.0
+            ),
+        }
+    }
+}
+
+impl Display for RunReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "run {}: {} anomalies",
+            self.run_id.0,
+            self.anomalies.len()
+        )?;
+        for anomaly in &self.anomalies {
+            write!(f, "\n    {}", anomaly)?;
+        }
+        for timeout in &self.timeouts {
+            write!(f, "\n    {} timed out waiting for", timeout.waiter)?;
+            for notifier in &timeout.unsatisfied_by {
+                write!(f, " {}", notifier)?;
+            }
+            for notifier in &timeout.unsatisfied_by_patterns {
+                write!(f, " {}", notifier)?;
+            }
+        }
+        for disconnect in &self.disconnects {
+            write!(
+                f,
+                "\n    federate {} (pid {}) went away early, releasing",
+                disconnect.fedid.0, disconnect.pid
+            )?;
+            for waiter in &disconnect.released {
+                write!(f, " {}", waiter)?;
+            }
+            for waiter in &disconnect.released_patterns {
+                write!(f, " {}", waiter)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,18 +1,41 @@
-use std::{collections::HashMap, env, ffi::c_int, os::fd::RawFd};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    env,
+    ffi::{c_int, OsString},
+    io,
+    os::fd::RawFd,
+    sync::Arc,
+    time::Duration,
+};
 
 use log::{debug, error, info, warn};
-use tokio::sync::mpsc;
-use tokio::task::JoinHandle;
+use tokio::sync::{mpsc, watch};
+use tokio::task::{JoinHandle, JoinSet};
 
 use crate::{
-    channel_vec,
-    connection::{ConnectionManagement, UNIX_CONNECTION_MANAGEMENT},
-    tcpconnectionprovider::reusing,
-    EnvironmentVariables, FederateId, Precedence, PrecedenceId, RunId,
+    analysis::PrecedenceError,
+    connection::{
+        ConnectionManagement, ReadConnection, WriteConnection, TCP_CONNECTION_MANAGEMENT,
+    },
+    memory::{self, MemoryConnector, MEMORY_CONNECTION_MANAGEMENT},
+    pattern::{HookInvocationPattern, PatternEdge, WaiterIndex},
+    protocol::FrameKind,
+    report::{Anomaly, Disconnect, RunReport, TimedOutWait},
+    shm::{SHARED_MEMORY_TRANSPORT_NAME, SHM_REUSABLE_TRANSPORT},
+    step::{StepCommand, StepError, StepReply, StepState},
+    tcpconnectionprovider::{
+        forwarding, reusing, ConnectionRoutes, ReusableTransport, TcpConnectionElt,
+        UNIX_REUSABLE_TRANSPORT,
+    },
+    timeline::{self, EventKind, Recorder},
+    EnvironmentVariables, FederateId, Frame, HookInvocation, Precedence, PrecedenceId, RunId,
+    ORDSERV_HOST_ENV_VAR, ORDSERV_PORT_ENV_VAR, ORDSERV_TRANSPORT_ENV_VAR,
 };
 
 pub(crate) const PRECEDENCE_FILE_NAME: &str = "ORDSERV_PRECEDENCE_FILE";
 pub(crate) const PRECEDENCE_ID_NAME: &str = "ORDSERV_PRECEDENCE_ID";
+/// How often the server checks whether the processes of a run are still alive.
+const PROCESS_POLL_INTERVAL: Duration = Duration::from_millis(100);
 
 pub(crate) fn connection_raw_fd_for(fedid: FederateId) -> RawFd {
     let fd = env::var_os(evar_name_for(fedid)).unwrap_or_else(|| {
@@ -24,242 +47,1001 @@ pub(crate) fn connection_raw_fd_for(fedid: FederateId) -> RawFd {
     fd.as_os_str().to_str().unwrap().parse::<c_int>().unwrap()
 }
 
-fn evar_name_for(fedid: FederateId) -> String {
+pub(crate) fn evar_name_for(fedid: FederateId) -> String {
     format!("ORDSERV_CONNECTION_{}", fedid.0)
 }
 
 pub struct ServerHandle {
     pub updates_acks: Vec<ServerSubHandle>,
-    pub join_handle: JoinHandle<()>,
+    join_handle: JoinHandle<()>,
+    new_streams: mpsc::UnboundedSender<ServerSubHandleInternal>,
 }
 pub type ServerSubHandle = (
-    mpsc::Sender<Option<Precedence>>,
-    mpsc::Receiver<EnvironmentVariables>,
+    mpsc::Sender<Update>,
+    mpsc::Receiver<Ack>,
+    mpsc::UnboundedReceiver<RunReport>,
+);
+type ServerSubHandleInternal = (
+    mpsc::Receiver<Update>,
+    mpsc::Sender<Ack>,
+    mpsc::UnboundedSender<RunReport>,
 );
+/// The response to a precedence. A precedence that no execution can satisfy is rejected before
+/// any client is told about it.
+pub type Ack = Result<EnvironmentVariables, PrecedenceError>;
+
+#[derive(Debug)]
+#[allow(clippy::large_enum_variant)] // at most one update is in flight per run
+pub enum Update {
+    /// Ends the current run, if there is one, and starts a new run that is governed by the given
+    /// precedence.
+    Start(Precedence),
+    /// Ends the current run. Its [`RunReport`] is sent once the connections of the run have been
+    /// recovered.
+    Finish,
+    /// Ends the current run, if there is one, and stops serving the precedence stream. Closing the
+    /// channel has the same effect.
+    Halt,
+    /// Steps through the current run, which must be in step mode. See [`crate::step`].
+    Step(StepCommand, StepReply),
+}
+
+fn sub_handle() -> (ServerSubHandle, ServerSubHandleInternal) {
+    let (update_sender, update_receiver) = mpsc::channel(1);
+    let (ack_sender, ack_receiver) = mpsc::channel(1);
+    let (report_sender, report_receiver) = mpsc::unbounded_channel();
+    (
+        (update_sender, ack_receiver, report_receiver),
+        (update_receiver, ack_sender, report_sender),
+    )
+}
+
+impl ServerHandle {
+    /// Starts serving `capacity` precedence streams, whose sub-handles are in `updates_acks`.
+    fn new(
+        capacity: usize,
+        join_handle: JoinHandle<()>,
+        new_streams: mpsc::UnboundedSender<ServerSubHandleInternal>,
+    ) -> Self {
+        let mut handle = ServerHandle {
+            updates_acks: Vec::with_capacity(capacity),
+            join_handle,
+            new_streams,
+        };
+        for _ in 0..capacity {
+            let sub_handle = handle.add_sub_handle();
+            handle.updates_acks.push(sub_handle);
+        }
+        handle
+    }
+
+    /// Starts serving one more precedence stream while the server runs. Streams are independent of
+    /// each other, so this can be used to give each of several concurrent test drivers its own
+    /// stream.
+    ///
+    /// The stream is served until [`Update::Halt`] is sent to it or its update sender is dropped.
+    pub fn add_sub_handle(&self) -> ServerSubHandle {
+        let (theirs, mine) = sub_handle();
+        if self.new_streams.send(mine).is_err() {
+            // The channels of the sub-handle are closed, so whoever uses it will find out.
+            error!("Tried to add a precedence stream to a server that has shut down");
+        }
+        theirs
+    }
+
+    /// Stops serving the precedence stream of `sub_handle` and waits until the stream has ended its
+    /// current run, if any, and released its connections. The report of that run is discarded.
+    pub async fn remove_sub_handle(&self, sub_handle: ServerSubHandle) {
+        let (updates, _acks, mut reports) = sub_handle;
+        let _ = updates.send(Update::Halt).await;
+        // The stream holds on to its report sender until it is done.
+        while reports.recv().await.is_some() {}
+    }
+
+    /// Halts every precedence stream in `updates_acks` and waits for the server to shut down, which
+    /// happens once the streams of any sub-handles that were added with
+    /// [`ServerHandle::add_sub_handle`] have also been halted.
+    pub async fn join(self) {
+        drop(self.updates_acks);
+        drop(self.new_streams);
+        self.join_handle.await.unwrap();
+    }
+}
 
 /// This function spawns a process that assumes that each element of `updates_acks` is managed by a
 /// single sequential process that repeatedly:
-/// 1. Sends a precedence
+/// 1. Sends a precedence in an [`Update::Start`]
 /// 2. Waits for an ack
-/// 3. Spawns the promised number of processes, which each send a frame, upon which connections to
-///    them are forwarded here via the connection_receiver.
+/// 3. Spawns the promised number of processes, which each connect to `port` over TCP and send a
+///    hello frame, upon which their connections are forwarded to the precedence stream that they
+///    name.
 /// 4. Waits for all the processes to finish
+/// 5. Sends [`Update::Finish`] and receives the [`RunReport`] of the run
+///
+/// Each precedence stream ends when [`Update::Halt`] is received from it. More streams can be added
+/// with [`ServerHandle::add_sub_handle`].
 ///
-/// This ends when None is received from the precedence stream.
-// pub async fn run(port: u16, capacity: usize) -> ServerHandle {
-//     let mut my_updates_acks = Vec::with_capacity(capacity);
-//     let mut their_updates_acks = Vec::with_capacity(capacity);
-//     for _ in 0..capacity {
-//         let (update_sender, update_receiver) = mpsc::channel(1);
-//         let (ack_sender, ack_receiver) = mpsc::channel(1);
-//         their_updates_acks.push((update_sender, ack_receiver));
-//         my_updates_acks.push((update_receiver, ack_sender));
-//     }
-//     ServerHandle {
-//         updates_acks: their_updates_acks,
-//         join_handle: run_server(port, my_updates_acks).await,
-//     }
-// }
+/// Unlike [`run_reusing_connections`], this does not require the processes to inherit any file
+/// descriptors from the server, so it works for processes that are launched by wrappers,
+/// containers, or scripts that close inherited file descriptors. The acks tell the processes which
+/// port to connect to. If `port` is 0, an ephemeral port is used.
+///
+/// The server only listens on the loopback interface; see [`run_on`] for processes that reach it
+/// from another network namespace.
+pub async fn run(port: u16, capacity: usize) -> io::Result<ServerHandle> {
+    run_on("127.0.0.1", port, "127.0.0.1", capacity).await
+}
+
+/// Like [`run`], but listens on `bind_host`, such as `0.0.0.0` to accept processes that run in
+/// containers, and tells the processes of each run to connect to `advertised_host`, which is the
+/// name or address of the server as the processes see it.
+pub async fn run_on(
+    bind_host: &str,
+    port: u16,
+    advertised_host: &str,
+    capacity: usize,
+) -> io::Result<ServerHandle> {
+    let (new_streams_sender, new_streams_receiver) = mpsc::unbounded_channel();
+    let join_handle = run_server(bind_host, port, advertised_host, new_streams_receiver).await?;
+    Ok(ServerHandle::new(capacity, join_handle, new_streams_sender))
+}
 
 /// This function spawns a process that assumes that each element of `updates_acks` is managed by a
 /// single sequential process that repeatedly:
-/// 1. Sends a precedence
+/// 1. Sends a precedence in an [`Update::Start`]
 /// 2. Waits for an ack
 /// 3. Spawns the promised number of processes, which each send a frame, upon which connections to
 ///    them are forwarded here via the connection_receiver.
 /// 4. Waits for all the processes to finish
+/// 5. Sends [`Update::Finish`] and receives the [`RunReport`] of the run
+///
+/// Each precedence stream ends when [`Update::Halt`] is received from it. More streams can be added
+/// with [`ServerHandle::add_sub_handle`].
 ///
-/// This ends when None is received from the precedence stream.
+/// Each stream has its own pool of connections, which starts out with
+/// `n_preallocated_connections` connections, grows whenever a run needs more connections than the
+/// pool has, and shrinks when connections go unused for a while.
 pub async fn run_reusing_connections(
     capacity: usize,
-    max_n_simultaneous_connections: usize,
+    n_preallocated_connections: usize,
 ) -> ServerHandle {
-    let mut my_updates_acks = Vec::with_capacity(capacity);
-    let mut their_updates_acks = Vec::with_capacity(capacity);
-    for _ in 0..capacity {
-        let (update_sender, update_receiver) = mpsc::channel(1);
-        let (ack_sender, ack_receiver) = mpsc::channel(1);
-        their_updates_acks.push((update_sender, ack_receiver));
-        my_updates_acks.push((update_receiver, ack_sender));
+    let (new_streams_sender, new_streams_receiver) = mpsc::unbounded_channel();
+    let join_handle = run_server_reusing_connections(
+        new_streams_receiver,
+        n_preallocated_connections,
+        UNIX_REUSABLE_TRANSPORT,
+        vec![],
+    )
+    .await;
+    ServerHandle::new(capacity, join_handle, new_streams_sender)
+}
+
+/// Like [`run_reusing_connections`], but frames pass through shared memory instead of through
+/// sockets, which saves a system call and a wakeup on each hop of a notification when the
+/// processes are busy. The processes of each run must share a host with the server. See
+/// [`crate::shm`].
+///
+/// The processes are told to use shared memory through [`ORDSERV_TRANSPORT_ENV_VAR`].
+pub async fn run_shared_memory(capacity: usize, n_preallocated_connections: usize) -> ServerHandle {
+    let (new_streams_sender, new_streams_receiver) = mpsc::unbounded_channel();
+    let join_handle = run_server_reusing_connections(
+        new_streams_receiver,
+        n_preallocated_connections,
+        SHM_REUSABLE_TRANSPORT,
+        vec![(
+            ORDSERV_TRANSPORT_ENV_VAR.into(),
+            SHARED_MEMORY_TRANSPORT_NAME.into(),
+        )],
+    )
+    .await;
+    ServerHandle::new(capacity, join_handle, new_streams_sender)
+}
+
+/// Like [`run`], but the clients run in the same process as the server and connect through the
+/// returned [`MemoryConnector`] instead of over TCP. See [`crate::memory`].
+pub async fn run_in_memory(capacity: usize) -> (ServerHandle, MemoryConnector) {
+    let (new_streams_sender, new_streams_receiver) = mpsc::unbounded_channel();
+    let routes = ConnectionRoutes::default();
+    let (connector, listener_handle) = memory::forwarding(routes.clone());
+    let join_handle = serve_routed_streams(
+        new_streams_receiver,
+        routes,
+        listener_handle,
+        MEMORY_CONNECTION_MANAGEMENT,
+        vec![],
+    );
+    (
+        ServerHandle::new(capacity, join_handle, new_streams_sender),
+        connector,
+    )
+}
+
+/// The frames that the readers of a run pass on to its router.
+enum ReaderEvent {
+    Frame(Frame),
+    /// The federate connected, and from now on its frames are read. `queue` is its queue if it did
+    /// not already have one.
+    Joined {
+        fedid: FederateId,
+        pid: u32,
+        queue: Option<mpsc::UnboundedSender<Frame>>,
+    },
+    /// The federate closed its connection or its process died, so nothing more can be delivered to
+    /// it.
+    Closed(FederateId),
+}
+
+/// A waiter to which the router of a run forwards a notification.
+enum Destination<'a> {
+    Exact {
+        notifier: &'a HookInvocation,
+        waiter: &'a HookInvocation,
+    },
+    Pattern {
+        index: WaiterIndex,
+        waiter: &'a HookInvocationPattern,
+    },
+}
+
+impl Destination<'_> {
+    fn fedid(&self) -> FederateId {
+        match self {
+            Destination::Exact { waiter, .. } => waiter.hid.1,
+            Destination::Pattern { waiter, .. } => waiter.hid.1,
+        }
     }
-    ServerHandle {
-        updates_acks: their_updates_acks,
-        join_handle: run_server_reusing_connections(
-            my_updates_acks,
-            max_n_simultaneous_connections,
-        )
-        .await,
+    fn unknown(&self, hook_invocation: HookInvocation) -> Anomaly {
+        match self {
+            Destination::Exact { waiter, .. } => Anomaly::UnknownDestination {
+                hook_invocation,
+                waiter: (*waiter).clone(),
+            },
+            Destination::Pattern { waiter, .. } => Anomaly::UnknownPatternDestination {
+                hook_invocation,
+                waiter: (*waiter).clone(),
+            },
+        }
+    }
+    fn forward_event(&self, notification: HookInvocation) -> EventKind {
+        match self {
+            Destination::Exact { waiter, .. } => EventKind::Forward {
+                notification,
+                waiter: (*waiter).clone(),
+            },
+            Destination::Pattern { waiter, .. } => EventKind::ForwardToPattern {
+                notification,
+                waiter: (*waiter).clone(),
+            },
+        }
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn process_precedence_stream<R, W>(
-    mut precedence_stream: mpsc::Receiver<Option<Precedence>>,
-    acks: mpsc::Sender<EnvironmentVariables>,
-    mut connection_receiver: mpsc::Receiver<(RawFd, FederateId, RunId)>,
+    mut precedence_stream: mpsc::Receiver<Update>,
+    acks: mpsc::Sender<Ack>,
+    reports: mpsc::UnboundedSender<RunReport>,
+    mut connection_receiver: mpsc::Receiver<TcpConnectionElt>,
     precid: PrecedenceId,
-    connection_requests: Option<mpsc::Sender<(usize, RunId)>>,
+    connection_requests: Option<mpsc::Sender<(Vec<FederateId>, RunId)>>,
     connection_management: ConnectionManagement<R, W>,
+    client_evars: Vec<(OsString, OsString)>,
 ) where
     R: tokio::io::AsyncRead + Unpin + Send + 'static,
     W: tokio::io::AsyncWrite + Unpin + Send + 'static,
 {
-    let mut outer_precedence = precedence_stream.recv().await.unwrap_or(None);
-    let mut n_successful_connections = 0;
-    let mut n_attempted_connections = 0;
-    'outer: while let Some(precedence) = outer_precedence.take() {
+    let mut outer_update = precedence_stream.recv().await.unwrap_or(Update::Halt);
+    loop {
+        let precedence = match outer_update {
+            Update::Start(mut precedence) => {
+                precedence.number_hooks();
+                precedence
+            }
+            Update::Finish => {
+                warn!("Received finish while no run was in progress");
+                outer_update = precedence_stream.recv().await.unwrap_or(Update::Halt);
+                continue;
+            }
+            Update::Step(_, reply) => {
+                let _ = reply.send(Err(StepError::NoRun));
+                outer_update = precedence_stream.recv().await.unwrap_or(Update::Halt);
+                continue;
+            }
+            Update::Halt => break,
+        };
         debug!("Received precedence");
-        acks.send(environment_variables_for_clients(&precedence, precid).await)
-            .await
-            .unwrap();
-        debug!("Expecting {} connections", precedence.n_connections);
+        if let Err(e) = precedence.check() {
+            warn!("Rejecting precedence: {}", e);
+            acks.send(Err(e)).await.unwrap();
+            outer_update = precedence_stream.recv().await.unwrap_or(Update::Halt);
+            continue;
+        }
+        let mut evars = environment_variables_for_clients(&precedence, precid).await;
+        evars.0.extend(client_evars.iter().cloned());
+        acks.send(Ok(evars)).await.unwrap();
+        let federates = precedence.federates();
+        debug!("Provisioning connections for federates {:?}", federates);
         if let Some(connection_requests) = &connection_requests {
             connection_requests
-                .send((precedence.n_connections, RunId(precedence.run_id.0)))
+                .send((federates.clone(), RunId(precedence.run_id.0)))
                 .await
                 .unwrap();
         }
-        n_attempted_connections += precedence.n_connections;
-        let mut writers = HashMap::new();
-        let mut readers = HashMap::new();
-        let mut n_connected = 0;
-        while n_connected < precedence.n_connections {
-            tokio::select! {
-                new_connection = connection_receiver.recv() => {
-                    let (raw_connection, fedid, run_id) = new_connection.unwrap();
-                    let connection = unsafe {(connection_management.borrow)(raw_connection)};
-                    if run_id.0 != precedence.run_id.0 {
-                        error!("Received connection with run_id {} but precedence has run_id {}. This indicates a bug in the test framework, but I am not failing fast now due to lack of time.", run_id.0, precedence.run_id.0);
-                    } else if let Ok(connection) = connection {
-                        debug!("Received connection from {:?}", fedid);
-                        let (reader, writer) = connection.into_split();
-                        writers.insert(fedid, writer);
-                        readers.insert(fedid, reader);
-                        n_connected += 1;
-                        n_successful_connections += 1;
-                    } else {
-                        error!("Failed to accept connection: {:?} even though it should have been vetted by the connection provider before it was sent here", raw_connection);
-                    }
-                }
-                new_precedence = precedence_stream.recv() => {
-                    warn!("Received new precedence while waiting for connections");
-                    outer_precedence = new_precedence.unwrap_or(None);
-                    continue 'outer;
-                }
-            }
-        }
-        debug!(
-            "All connections received (success rate = {} / {} = {})",
-            n_successful_connections,
-            n_attempted_connections,
-            n_successful_connections as f64 / n_attempted_connections as f64
-        );
+        let mut anomalies = vec![];
+        let recorder = precedence
+            .record_timeline
+            .then(|| Arc::new(Recorder::new(None)));
         let (send_frames, mut recv_frames) = mpsc::channel(1);
         let (halt_sender, mut halt_receiver) = tokio::sync::watch::channel(());
+        // Each federate has a queue and a writer of its own, so that a federate that is slow to read
+        // its connection only delays the notifications that are meant for it. The queues of the
+        // federates that the precedence mentions exist from the start, so that the notifications
+        // for a federate that has not connected yet wait for it.
+        let (queues, mut unjoined): (HashMap<_, _>, HashMap<_, _>) = federates
+            .iter()
+            .map(|fedid| {
+                let (queue, frames) = mpsc::unbounded_channel();
+                ((*fedid, queue), (*fedid, frames))
+            })
+            .unzip();
         let mut reader_handles = HashMap::new();
-        for (fedid, mut reader) in readers.into_iter() {
-            let mut halt_receiver = halt_sender.subscribe();
-            let send_frames = send_frames.clone();
-            reader_handles.insert(
-                fedid,
-                tokio::spawn(async move {
-                    loop {
-                        debug!("Waiting for frame from {:?}", fedid);
-                        tokio::select! {
-                            _ = halt_receiver.changed() => {
-                                debug!("Reader received halt signal");
-                                // halt_receiver.mark_changed();
-                                break;
+        let mut writer_handles = HashMap::new();
+        let router_recorder = recorder.clone();
+        let patterns = precedence.pattern_index();
+        let hooks = precedence.hook_table();
+        let precedence_step = precedence.step;
+        let (step_requests_sender, mut step_requests) =
+            mpsc::unbounded_channel::<(StepCommand, StepReply)>();
+        let router_handle = tokio::spawn(async move {
+            let mut queues = queues;
+            let mut pids = HashMap::new();
+            // The notifications that were queued for federates that have not connected yet, as the
+            // anomalies to report if the federates never do.
+            let mut unjoined_destinations: HashMap<FederateId, Vec<Anomaly>> = HashMap::new();
+            let mut stepping = precedence.step;
+            let mut held = VecDeque::new();
+            // Where the held notifications are going. A waiter whose notification is held is not
+            // released when its notifier goes away, because the notification may still be
+            // released.
+            let mut held_for = HashSet::new();
+            let mut held_for_patterns = HashSet::new();
+            let mut released = VecDeque::new();
+            let mut blocked = vec![];
+            let mut anomalies = vec![];
+            let mut timeouts = vec![];
+            let mut interleaving = vec![];
+            let mut closed = HashSet::new();
+            let mut forwarded = HashSet::new();
+            let mut forwarded_patterns = HashSet::new();
+            let mut disconnects = vec![];
+            // The router stops once every reader has stopped, so that the frames that the readers
+            // pass on after the run is halted are still recorded. They are no longer forwarded. A
+            // notification counts as forwarded once it is queued for the federate that waits for it.
+            let mut halted = false;
+            loop {
+                // Notifications that were released in step mode are routed before anything else.
+                let (frame, was_held) = match released.pop_front() {
+                    Some(frame) => (frame, true),
+                    None => {
+                        let event = tokio::select! {
+                            _ = halt_receiver.changed(), if !halted => {
+                                debug!("Router received halt signal");
+                                halted = true;
+                                continue;
                             }
-                            frame = reader.read_frame() => {
-                                match frame {
-                                    Some(frame) => {
-                                        debug!("Received frame: {:?} from {:?}", frame, fedid);
-                                        assert!(fedid.0 == frame.federate_id);
-                                        assert!(precedence.run_id.0 == frame.run_id);
-                                        send_frames.send(frame).await.unwrap_or_else(|_| {
-                                            warn!("Failed to send frame. This is not strictly an error condition because the two halt receivers (in the frame sender and receiver) are racing with each other, but it should be unusual because it should be uncommon for programs to finish while frames are in flight. Because of the timeout when waiting for in-flight frames, it can happen under 'normal' conditions, however.");
-                                        });
-                                    }
-                                    None => {
-                                        info!(target: "server", "Connection closed");
-                                        break;
-                                    }
-                                }
+                            Some((command, reply)) = step_requests.recv() => {
+                                let state = apply_step(command, &mut stepping, &mut held, &mut released, &blocked);
+                                let _ = reply.send(state);
+                                continue;
                             }
-                        }
-                    }
-                    reader
-                }),
-            );
-        }
-        let writer_handle = tokio::spawn(async move {
-            loop {
-                tokio::select! {
-                    _ = halt_receiver.changed() => {
-                        debug!("Writer received halt signal");
-                        // halt_receiver.mark_changed();
-                        break;
-                    }
-                    frame = recv_frames.recv() => {
-                        match frame {
-                            Some(frame) => {
-                                for dest in precedence
-                                    .sender2waiters
-                                    .get(&frame.hook_invocation())
-                                    .unwrap_or_else(|| {
-                                        panic!("Received frame {:?} with hid {:?} for which there are no waiters; the actual precedence is:\n    {:?}", frame, frame.hid(), precedence);
-                                    })
-                                {
-                                    debug!("Forwarding frame to {:?}", dest);
-                                    let writers_debug = writers.keys().cloned().collect::<Vec<_>>(); // FIXME: this is just for debugging
-                                    tokio::select!{
-                                        _ = halt_receiver.changed() => {
-                                            debug!("Writer received halt signal");
-                                            // halt_receiver.mark_changed();
-                                            break;
-                                        }
-                                        _ = writers
-                                        .get_mut(&dest.hid.1)
-                                        .unwrap_or_else(|| {
-                                            panic!("Received frame {:?} with hid {:?} and dest id {:?} for which there are no writers for the dest; the actual precedence is:\n    {:?}, and the writers are:\n    {:?}", frame, frame.hid(), &dest.hid.1, precedence, writers_debug);
-                                        })
-                                        .write_frame(frame) => {
-                                            debug!("Frame forwarded to {:?}", dest);
-                                        }
+                            event = recv_frames.recv() => event,
+                        };
+                        let frame = match event {
+                            Some(ReaderEvent::Frame(frame)) => frame,
+                            Some(ReaderEvent::Joined { fedid, pid, queue }) => {
+                                debug!("{:?} joined the run", fedid);
+                                pids.insert(fedid, pid);
+                                queues.extend(queue.map(|queue| (fedid, queue)));
+                                unjoined_destinations.remove(&fedid);
+                                continue;
+                            }
+                            Some(ReaderEvent::Closed(fedid)) => {
+                                blocked.retain(|waiter: &HookInvocation| waiter.hid.1 != fedid);
+                                if !closed.insert(fedid) || halted {
+                                    continue;
+                                }
+                                let Some(disconnect) = early_disconnect(
+                                    &precedence.sender2waiters,
+                                    &precedence.pattern_edges,
+                                    fedid,
+                                    pids[&fedid],
+                                    &(&forwarded | &held_for),
+                                    &(&forwarded_patterns | &held_for_patterns),
+                                    &closed,
+                                ) else {
+                                    continue;
+                                };
+                                warn!(
+                                    "Federate {} (pid {}) went away early",
+                                    fedid.0, disconnect.pid
+                                );
+                                // Each client lets every hook invocation that waits for the federate
+                                // proceed when it gets the frame, so each federate needs it only once.
+                                let gone =
+                                    Frame::notifier_gone(precid.0, fedid, precedence.run_id.0);
+                                let released: HashSet<_> = disconnect
+                                    .released
+                                    .iter()
+                                    .map(|waiter| waiter.hid.1)
+                                    .chain(
+                                        disconnect
+                                            .released_patterns
+                                            .iter()
+                                            .map(|waiter| waiter.hid.1),
+                                    )
+                                    .collect();
+                                for waiter_fedid in released {
+                                    if let Some(queue) = queues.get(&waiter_fedid) {
+                                        // The writer stops only when the run is halted.
+                                        let _ = queue.send(gone.clone());
                                     }
                                 }
+                                disconnects.push(disconnect);
+                                continue;
                             }
                             None => {
-                                info!(target: "server", "Connection closed");
+                                info!(target: "server", "All connections closed");
                                 break;
                             }
+                        };
+                        (frame, false)
+                    }
+                };
+                let Some(hook_invocation) = hooks.frame_invocation(&frame) else {
+                    warn!("Received frame {:?} about an unknown hook number", frame);
+                    anomalies.push(Anomaly::UnknownHookNumber {
+                        from: FederateId(frame.federate_id),
+                        number: frame.hook_number.unwrap(),
+                    });
+                    continue;
+                };
+                match frame.kind {
+                    FrameKind::Record => {
+                        interleaving.push(hook_invocation);
+                        continue;
+                    }
+                    FrameKind::Blocked => {
+                        blocked.push(hook_invocation);
+                        continue;
+                    }
+                    FrameKind::Unblocked => {
+                        unblock(&mut blocked, &hook_invocation);
+                        continue;
+                    }
+                    _ => {}
+                }
+                if frame.kind == FrameKind::Timeout {
+                    warn!("{} timed out", hook_invocation);
+                    unblock(&mut blocked, &hook_invocation);
+                    let unsatisfied_by = precedence
+                        .sender2waiters
+                        .iter()
+                        .filter(|(notifier, waiters)| {
+                            waiters.contains(&hook_invocation)
+                                && !forwarded.contains(&(*notifier, &hook_invocation))
+                        })
+                        .map(|(notifier, _)| notifier.clone())
+                        .collect();
+                    let unsatisfied_by_patterns = patterns
+                        .waiting(&hook_invocation, &frame.attributes)
+                        .filter(|index| !forwarded_patterns.contains(index))
+                        .map(|(edge, _)| precedence.pattern_edges[edge].notifier.clone())
+                        .collect();
+                    timeouts.push(TimedOutWait {
+                        waiter: hook_invocation,
+                        unsatisfied_by,
+                        unsatisfied_by_patterns,
+                    });
+                    continue;
+                }
+                let mut dests = vec![];
+                if let Some((notifier, waiters)) =
+                    precedence.sender2waiters.get_key_value(&hook_invocation)
+                {
+                    dests.extend(
+                        waiters
+                            .iter()
+                            .map(|waiter| Destination::Exact { notifier, waiter }),
+                    );
+                }
+                for edge in patterns.notified_by(&hook_invocation, &frame.attributes) {
+                    dests.extend(
+                        precedence.pattern_edges[edge]
+                            .waiters
+                            .iter()
+                            .enumerate()
+                            .map(|(waiter_idx, waiter)| Destination::Pattern {
+                                index: (edge, waiter_idx),
+                                waiter,
+                            }),
+                    );
+                }
+                if dests.is_empty() {
+                    warn!("Received frame {:?} for which there are no waiters", frame);
+                    anomalies.push(Anomaly::UnexpectedFrame {
+                        from: FederateId(frame.federate_id),
+                        hook_invocation,
+                    });
+                    continue;
+                }
+                if stepping && !was_held && !halted {
+                    debug!("Holding {} until it is released", hook_invocation);
+                    for dest in &dests {
+                        match dest {
+                            Destination::Exact { notifier, waiter } => {
+                                held_for.insert((*notifier, *waiter));
+                            }
+                            Destination::Pattern { index, .. } => {
+                                held_for_patterns.insert(*index);
+                            }
                         }
                     }
+                    held.push_back((frame, hook_invocation));
+                    continue;
+                }
+                // A client lets all of the waiters of a notification proceed when the
+                // notification arrives, so each federate needs the frame only once.
+                let mut queued: HashMap<FederateId, bool> = HashMap::new();
+                for dest in dests {
+                    let fedid = dest.fedid();
+                    debug!("Forwarding frame to {:?}", fedid);
+                    if closed.contains(&fedid) {
+                        anomalies.push(Anomaly::EarlyDisconnect {
+                            fedid,
+                            undelivered: hook_invocation.clone(),
+                        });
+                        continue;
+                    }
+                    let result = match queued.get(&fedid) {
+                        Some(ok) => *ok,
+                        None => {
+                            let Some(queue) = queues.get(&fedid) else {
+                                warn!(
+                                    "Received frame {:?} for {:?}, whose federate is not connected",
+                                    frame, fedid
+                                );
+                                anomalies.push(dest.unknown(hook_invocation.clone()));
+                                continue;
+                            };
+                            if halted {
+                                continue;
+                            }
+                            let ok = queue.send(frame.clone()).is_ok();
+                            queued.insert(fedid, ok);
+                            ok
+                        }
+                    };
+                    if !result {
+                        continue;
+                    }
+                    debug!("Frame queued for {:?}", fedid);
+                    if !pids.contains_key(&fedid) {
+                        unjoined_destinations
+                            .entry(fedid)
+                            .or_default()
+                            .push(dest.unknown(hook_invocation.clone()));
+                    }
+                    match &dest {
+                        Destination::Exact { notifier, waiter } => {
+                            forwarded.insert((*notifier, *waiter));
+                        }
+                        Destination::Pattern { index, .. } => {
+                            forwarded_patterns.insert(*index);
+                        }
+                    }
+                    if let Some(recorder) = &router_recorder {
+                        recorder.record(dest.forward_event(hook_invocation.clone()));
+                    }
                 }
             }
-            writers
+            anomalies.extend(unjoined_destinations.into_values().flatten());
+            (anomalies, timeouts, interleaving, disconnects)
         });
-        debug!("Awaiting a new precedence");
-        outer_precedence = precedence_stream.recv().await.unwrap_or(None);
-        debug!("Received new precedence");
-        halt_sender.send(()).unwrap();
-        let mut writer_handle = writer_handle.await.unwrap();
+        debug!("Awaiting the end of the run");
+        let run_id = precedence.run_id;
+        outer_update = loop {
+            let new_connection = tokio::select! {
+                update = precedence_stream.recv() => match update.unwrap_or(Update::Halt) {
+                    Update::Step(command, reply) if precedence_step => {
+                        if let Err(mpsc::error::SendError((_, reply))) =
+                            step_requests_sender.send((command, reply))
+                        {
+                            let _ = reply.send(Err(StepError::NoRun));
+                        }
+                        continue;
+                    }
+                    Update::Step(_, reply) => {
+                        let _ = reply.send(Err(StepError::NotStepping));
+                        continue;
+                    }
+                    update => break update,
+                },
+                Some(new_connection) = connection_receiver.recv() => new_connection,
+            };
+            // Processes may connect at any time during the run, including processes that were
+            // forked from a process of the run after it started.
+            let (raw_connection, fedid, connection_run_id, pid) = new_connection;
+            let connection = unsafe { (connection_management.borrow)(raw_connection) };
+            if connection_run_id != run_id {
+                error!("Received connection with run_id {} but precedence has run_id {}. This indicates a bug in the test framework, but I am not failing fast now due to lack of time.", connection_run_id.0, run_id.0);
+                anomalies.push(Anomaly::StrayRunId {
+                    fedid,
+                    run_id: connection_run_id,
+                });
+                continue;
+            }
+            let (reader, writer) = match connection {
+                Ok(connection) => connection.into_split(),
+                Err(e) => {
+                    error!("Failed to accept connection: {:?} even though it should have been vetted by the connection provider before it was sent here", raw_connection);
+                    anomalies.push(Anomaly::BadConnection {
+                        error: e.to_string(),
+                    });
+                    continue;
+                }
+            };
+            if reader_handles.contains_key(&fedid) {
+                error!("{:?} connected a second time during the run", fedid);
+                anomalies.push(Anomaly::BadConnection {
+                    error: format!("federate {} connected a second time", fedid.0),
+                });
+                unsafe { (connection_management.unborrow)((reader, writer)) };
+                continue;
+            }
+            debug!("Received connection from {:?} (pid {})", fedid, pid);
+            let (queue, frames) = match unjoined.remove(&fedid) {
+                Some(frames) => (None, frames),
+                None => {
+                    let (queue, frames) = mpsc::unbounded_channel();
+                    (Some(queue), frames)
+                }
+            };
+            // The router learns about the federate before it gets any of its frames.
+            if send_frames
+                .send(ReaderEvent::Joined { fedid, pid, queue })
+                .await
+                .is_err()
+            {
+                error!("The router of the run stopped before the run ended");
+            }
+            reader_handles.insert(
+                fedid,
+                tokio::spawn(read_from_federate(
+                    fedid,
+                    reader,
+                    send_frames.clone(),
+                    halt_sender.subscribe(),
+                    run_id,
+                )),
+            );
+            writer_handles.insert(
+                fedid,
+                tokio::spawn(write_to_destination(
+                    fedid,
+                    writer,
+                    frames,
+                    halt_sender.subscribe(),
+                )),
+            );
+            // Clients in the same process as the server cannot die on their own.
+            if pid != std::process::id() {
+                tokio::spawn(watch_process(
+                    fedid,
+                    pid,
+                    send_frames.clone(),
+                    halt_sender.subscribe(),
+                ));
+            }
+        };
+        debug!("Run ended");
+        // The readers, the router and the writers may all have stopped already if every client
+        // disconnected.
+        let _ = halt_sender.send(());
+        // The router stops once the readers and this sender are gone.
+        drop(send_frames);
+        if precedence.n_connections > reader_handles.len() {
+            anomalies.push(Anomaly::MissingConnections {
+                expected: precedence.n_connections,
+                received: reader_handles.len(),
+            });
+        }
+        let (router_anomalies, timeouts, interleaving, disconnects) = router_handle.await.unwrap();
         for fedid in reader_handles.keys().cloned().collect::<Vec<_>>() {
             let join_result = reader_handles.remove_entry(&fedid).unwrap().1.await;
-            if let Err(e) = join_result {
-                error!("Failed to join reader thread for {:?}. This is very bad because it means that we cannot recover the socket handle, but it is non-fatal because later when we find out that the socket handle is f**ed, we'll make a new one. Error:\n    {:?}", fedid, e);
-            } else {
-                unsafe {
-                    (connection_management.unborrow)((
-                        join_result.unwrap(),
-                        writer_handle.remove_entry(&fedid).unwrap().1,
-                    ))
+            let writer_join_result = writer_handles.remove(&fedid).unwrap().await;
+            match (join_result, writer_join_result) {
+                (Ok((reader, reader_anomalies)), Ok((writer, writer_anomalies))) => {
+                    anomalies.extend(reader_anomalies);
+                    anomalies.extend(writer_anomalies);
+                    unsafe { (connection_management.unborrow)((reader, writer)) }
+                }
+                (Err(e), _) | (_, Err(e)) => {
+                    error!("Failed to join reader or writer thread for {:?}. This is very bad because it means that we cannot recover the socket handle, but it is non-fatal because later when we find out that the socket handle is f**ed, we'll make a new one. Error:\n    {:?}", fedid, e);
                 }
             }
         }
         debug!("unborrows done");
+        anomalies.extend(router_anomalies);
+        let mut report = RunReport::new(precedence.run_id, anomalies);
+        report.timeouts = timeouts;
+        report.interleaving = interleaving;
+        report.disconnects = disconnects;
+        if let Some(recorder) = recorder {
+            let (path, events) =
+                timeline::merge(&precedence.scratch_dir, recorder.take_events()).await;
+            report.wait_stats = timeline::wait_stats(&events);
+            report.timeline = Some(path);
+        }
+        send_report(&reports, report);
+        if let Update::Finish = outer_update {
+            outer_update = precedence_stream.recv().await.unwrap_or(Update::Halt);
+        }
+    }
+    debug!("Received halt from precedence stream");
+}
+
+/// Carries out a step command for the router of a run, moving the notifications that it releases
+/// from `held` to `released`.
+fn apply_step(
+    command: StepCommand,
+    stepping: &mut bool,
+    held: &mut VecDeque<(Frame, HookInvocation)>,
+    released: &mut VecDeque<Frame>,
+    blocked: &[HookInvocation],
+) -> Result<StepState, StepError> {
+    if !*stepping {
+        return Err(StepError::NotStepping);
+    }
+    match command {
+        StepCommand::Show => {}
+        StepCommand::Release { index } => {
+            let (frame, hook_invocation) =
+                held.remove(index).ok_or(StepError::NoSuchNotification {
+                    index,
+                    n_held: held.len(),
+                })?;
+            info!(target: "server", "Releasing {}", hook_invocation);
+            released.push_back(frame);
+        }
+        StepCommand::Continue => {
+            info!(target: "server", "Releasing all held notifications and no longer stepping");
+            released.extend(held.drain(..).map(|(frame, _)| frame));
+            *stepping = false;
+        }
+    }
+    Ok(StepState {
+        held: held
+            .iter()
+            .map(|(_, hook_invocation)| hook_invocation.clone())
+            .collect(),
+        blocked: blocked.to_vec(),
+    })
+}
+
+/// Forgets the first wait of `hook_invocation` in `blocked`.
+fn unblock(blocked: &mut Vec<HookInvocation>, hook_invocation: &HookInvocation) {
+    if let Some(position) = blocked.iter().position(|waiter| waiter == hook_invocation) {
+        blocked.remove(position);
+    }
+}
+
+/// Passes the frames that `fedid` sends during the run `run_id` on to the router of the run, until
+/// the federate closes its connection or the run is halted.
+async fn read_from_federate<R>(
+    fedid: FederateId,
+    mut reader: ReadConnection<R>,
+    send_frames: mpsc::Sender<ReaderEvent>,
+    mut halt_receiver: watch::Receiver<()>,
+    run_id: RunId,
+) -> (ReadConnection<R>, Vec<Anomaly>)
+where
+    R: tokio::io::AsyncRead + Unpin,
+{
+    let mut anomalies = vec![];
+    let mut halted = false;
+    loop {
+        debug!("Waiting for frame from {:?}", fedid);
+        let frame = if halted {
+            // Frames that arrived before the run was finished, such as the records of the last hook
+            // invocations, are still passed on.
+            tokio::select! {
+                biased;
+                frame = reader.read_frame() => frame,
+                _ = std::future::ready(()) => break,
+            }
+        } else {
+            tokio::select! {
+                _ = halt_receiver.changed() => {
+                    debug!("Reader received halt signal");
+                    halted = true;
+                    continue;
+                }
+                frame = reader.read_frame() => frame,
+            }
+        };
+        let event = match frame {
+            Ok(Some(frame)) => {
+                debug!("Received frame: {:?} from {:?}", frame, fedid);
+                if fedid.0 != frame.federate_id {
+                    anomalies.push(Anomaly::FederateMismatch {
+                        connection: fedid,
+                        claimed: FederateId(frame.federate_id),
+                    });
+                    continue;
+                }
+                if run_id.0 != frame.run_id {
+                    anomalies.push(Anomaly::StrayRunId {
+                        fedid,
+                        run_id: RunId(frame.run_id),
+                    });
+                    continue;
+                }
+                ReaderEvent::Frame(frame)
+            }
+            Ok(None) => {
+                info!(target: "server", "Connection closed");
+                ReaderEvent::Closed(fedid)
+            }
+            Err(e) => {
+                error!("Failed to read frame from {:?}: {}", fedid, e);
+                anomalies.push(Anomaly::ReadError {
+                    fedid,
+                    error: e.to_string(),
+                });
+                ReaderEvent::Closed(fedid)
+            }
+        };
+        let closed = matches!(event, ReaderEvent::Closed(_));
+        send_frames.send(event).await.unwrap_or_else(|_| {
+            warn!("Failed to send frame. This is not strictly an error condition because the two halt receivers (in the frame sender and receiver) are racing with each other, but it should be unusual because it should be uncommon for programs to finish while frames are in flight. Because of the timeout when waiting for in-flight frames, it can happen under 'normal' conditions, however.");
+        });
+        if closed {
+            break;
+        }
+    }
+    (reader, anomalies)
+}
+
+/// Writes the frames that the router of a run queues for `fedid` until the run is halted.
+async fn write_to_destination<W>(
+    fedid: FederateId,
+    mut writer: WriteConnection<W>,
+    mut frames: mpsc::UnboundedReceiver<Frame>,
+    mut halt_receiver: watch::Receiver<()>,
+) -> (WriteConnection<W>, Vec<Anomaly>)
+where
+    W: tokio::io::AsyncWrite + Unpin,
+{
+    let mut anomalies = vec![];
+    loop {
+        let frame = tokio::select! {
+            _ = halt_receiver.changed() => break,
+            frame = frames.recv() => match frame {
+                Some(frame) => frame,
+                None => break,
+            },
+        };
+        let result = tokio::select! {
+            _ = halt_receiver.changed() => {
+                debug!("Writer for {:?} received halt signal", fedid);
+                break;
+            }
+            result = writer.write_frame(&frame) => result,
+        };
+        match result {
+            Ok(()) => debug!("Frame forwarded to {:?}", fedid),
+            // The federate may be on its way out as well.
+            Err(e) if frame.kind == FrameKind::NotifierGone => debug!(
+                "Failed to tell {:?} that {} is gone: {}",
+                fedid, frame.federate_id, e
+            ),
+            Err(e) => {
+                error!("Failed to forward frame {:?} to {:?}: {}", frame, fedid, e);
+                anomalies.push(Anomaly::WriteError {
+                    fedid,
+                    error: e.to_string(),
+                });
+            }
+        }
+    }
+    (writer, anomalies)
+}
+
+/// Describes the departure of `fedid` if any of the hook invocations of the federates that are still
+/// connected were waiting for notifications from it that had not been forwarded yet.
+fn early_disconnect(
+    sender2waiters: &HashMap<HookInvocation, Vec<HookInvocation>>,
+    pattern_edges: &[PatternEdge],
+    fedid: FederateId,
+    pid: u32,
+    forwarded: &HashSet<(&HookInvocation, &HookInvocation)>,
+    forwarded_patterns: &HashSet<WaiterIndex>,
+    closed: &HashSet<FederateId>,
+) -> Option<Disconnect> {
+    let mut released = vec![];
+    for (notifier, waiters) in sender2waiters {
+        if notifier.hid.1 != fedid {
+            continue;
+        }
+        for waiter in waiters {
+            if !forwarded.contains(&(notifier, waiter))
+                && !closed.contains(&waiter.hid.1)
+                && !released.contains(waiter)
+            {
+                released.push(waiter.clone());
+            }
+        }
+    }
+    let mut released_patterns = vec![];
+    for (edge_idx, edge) in pattern_edges.iter().enumerate() {
+        if edge.notifier.hid.1 != fedid {
+            continue;
+        }
+        for (waiter_idx, waiter) in edge.waiters.iter().enumerate() {
+            if !forwarded_patterns.contains(&(edge_idx, waiter_idx))
+                && !closed.contains(&waiter.hid.1)
+            {
+                released_patterns.push(waiter.clone());
+            }
+        }
+    }
+    if released.is_empty() && released_patterns.is_empty() {
+        return None;
+    }
+    Some(Disconnect {
+        fedid,
+        pid,
+        released,
+        released_patterns,
+    })
+}
+
+/// Tells the router of a run when the process of a client dies. The connections of
+/// [`run_reusing_connections`] and [`run_shared_memory`] are not closed when that happens, because
+/// the server holds on to both of their ends.
+async fn watch_process(
+    fedid: FederateId,
+    pid: u32,
+    send_frames: mpsc::Sender<ReaderEvent>,
+    mut halt_receiver: watch::Receiver<()>,
+) {
+    let mut interval = tokio::time::interval(PROCESS_POLL_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = halt_receiver.changed() => return,
+            _ = interval.tick() => {}
+        }
+        if !process_is_alive(pid) {
+            info!(target: "server", "The process {} of {:?} died", pid, fedid);
+            let _ = send_frames.send(ReaderEvent::Closed(fedid)).await;
+            return;
+        }
+    }
+}
+
+fn process_is_alive(pid: u32) -> bool {
+    // Signal 0 is never delivered; it only checks that the process exists.
+    let exists = unsafe { libc::kill(pid as libc::pid_t, 0) } == 0;
+    exists || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+fn send_report(reports: &mpsc::UnboundedSender<RunReport>, report: RunReport) {
+    if !report.is_clean() {
+        warn!("{}", report);
+    }
+    if reports.send(report).is_err() {
+        debug!("Run report dropped because no one is listening for it");
     }
-    debug!("Received None from precedence stream");
 }
 
 async fn environment_variables_for_clients(
@@ -276,118 +1058,602 @@ async fn environment_variables_for_clients(
     ])
 }
 
-// async fn run_server(
-//     port: u16,
-//     updates_acks: Vec<(
-//         mpsc::Receiver<Option<Precedence>>,
-//         mpsc::Sender<EnvironmentVariables>,
-//     )>,
-// ) -> JoinHandle<()> {
-//     let mut handles = Vec::with_capacity(updates_acks.len() + 1);
-//     let connection_receivers = forwarding(port, updates_acks.len()).await;
-//     for (precid, ((update_receiver, ack_sender), connection_receiver)) in updates_acks
-//         .into_iter()
-//         .zip(connection_receivers.into_iter())
-//         .enumerate()
-//     {
-//         handles.push(tokio::spawn(process_precedence_stream(
-//             update_receiver,
-//             ack_sender,
-//             connection_receiver,
-//             PrecedenceId(precid as u32),
-//             None,
-//             ConnectionManagement {
-//                 borrow: (),
-//                 unborrow: (),
-//             },
-//         )));
-//     }
-//     tokio::spawn(async move {
-//         for handle in handles {
-//             handle.await.unwrap();
-//         }
-//     })
-// }
-
-async fn run_server_reusing_connections(
-    updates_acks: Vec<(
-        mpsc::Receiver<Option<Precedence>>,
-        mpsc::Sender<EnvironmentVariables>,
-    )>,
-    max_n_simultaneous_connections: usize,
-) -> JoinHandle<()> {
-    let mut handles = Vec::with_capacity(updates_acks.len() * 2 + 1);
-    let (connection_requests_senders, connection_requests_receivers) =
-        channel_vec(updates_acks.len());
-    let (granted_connections_senders, granted_connections_receivers) =
-        channel_vec(updates_acks.len());
-    let (connection_receivers, abort_handles) = reusing(
-        updates_acks.len(),
-        max_n_simultaneous_connections,
-        connection_requests_receivers,
-        granted_connections_senders,
-    );
-    for (
-        precid,
-        (
-            (((update_receiver, ack_sender), connection_receiver), connection_requests_sender),
-            mut granted_connections_receiver,
-        ),
-    ) in updates_acks
-        .into_iter()
-        .zip(connection_receivers.into_iter())
-        .zip(connection_requests_senders.into_iter())
-        .zip(granted_connections_receivers.into_iter())
-        .enumerate()
-    {
-        let (evars_sender, mut evars_receiver) = mpsc::channel::<EnvironmentVariables>(1);
-        handles.push(tokio::spawn(process_precedence_stream(
-            update_receiver,
-            evars_sender,
-            connection_receiver,
-            PrecedenceId(precid as u32),
-            Some(connection_requests_sender),
-            UNIX_CONNECTION_MANAGEMENT,
-        )));
-        handles.push(tokio::spawn(async move {
-            let mut evars_option = evars_receiver.recv().await;
-            while let Some(mut evars) = evars_option.take() {
-                tokio::select! {
-                    granted_connections = granted_connections_receiver.recv() => {
-                        for (fednum, granted) in granted_connections
-                            .unwrap()
-                            .iter()
-                            .enumerate()
-                        {
-                            evars.0.push((
-                                evar_name_for(FederateId(fednum as i32 - 1)).into(), // Start counting at -1 cuz RTI is -1. Assumes contiguousness
-                                granted.to_string().into(),
-                            ));
-                        }
-                        ack_sender.send(evars).await.unwrap();
-                        evars_option = evars_receiver.recv().await;
-                    }
-                    next_evars = evars_receiver.recv() => {
-                        if next_evars.is_some() {
-                            error!("received next evars when it was not expected. Continuing...");
-                        }
-                        evars_option = next_evars;
-                    }
-                }
-            }
-        }));
+async fn run_server(
+    bind_host: &str,
+    port: u16,
+    advertised_host: &str,
+    new_streams: mpsc::UnboundedReceiver<ServerSubHandleInternal>,
+) -> io::Result<JoinHandle<()>> {
+    let routes = ConnectionRoutes::default();
+    let (port, listener_handle) = forwarding(bind_host, port, routes.clone()).await?;
+    info!("Listening for clients on {}:{}", bind_host, port);
+    Ok(serve_routed_streams(
+        new_streams,
+        routes,
+        listener_handle,
+        TCP_CONNECTION_MANAGEMENT,
+        vec![
+            (ORDSERV_HOST_ENV_VAR.into(), advertised_host.into()),
+            (ORDSERV_PORT_ENV_VAR.into(), port.to_string().into()),
+        ],
+    ))
+}
+
+/// Serves each precedence stream with the connections that `routes` forwards to it by precedence
+/// id, until no more streams can be added. Then the task that forwards the connections is
+/// aborted.
+fn serve_routed_streams<R, W>(
+    mut new_streams: mpsc::UnboundedReceiver<ServerSubHandleInternal>,
+    routes: ConnectionRoutes,
+    listener_handle: JoinHandle<()>,
+    connection_management: ConnectionManagement<R, W>,
+    client_evars: Vec<(OsString, OsString)>,
+) -> JoinHandle<()>
+where
+    R: tokio::io::AsyncRead + Unpin + Send + 'static,
+    W: tokio::io::AsyncWrite + Unpin + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut streams = JoinSet::new();
+        let mut precids = 0..;
+        while let Some((update_receiver, ack_sender, report_sender)) =
+            next_stream(&mut new_streams, &mut streams).await
+        {
+            let precid = precids.next().unwrap();
+            let (connection_sender, connection_receiver) = mpsc::channel(1);
+            routes.lock().unwrap().insert(precid, connection_sender);
+            let routes = routes.clone();
+            let client_evars = client_evars.clone();
+            streams.spawn(async move {
+                process_precedence_stream(
+                    update_receiver,
+                    ack_sender,
+                    report_sender,
+                    connection_receiver,
+                    PrecedenceId(precid),
+                    None,
+                    connection_management,
+                    client_evars,
+                )
+                .await;
+                routes.lock().unwrap().remove(&precid);
+            });
+        }
+        while let Some(result) = streams.join_next().await {
+            result.unwrap();
+        }
+        listener_handle.abort();
+    })
+}
+
+/// Waits for the next precedence stream to be added, joining the tasks of the streams that end in
+/// the meantime. Returns `None` once no more streams can be added.
+async fn next_stream(
+    new_streams: &mut mpsc::UnboundedReceiver<ServerSubHandleInternal>,
+    streams: &mut JoinSet<()>,
+) -> Option<ServerSubHandleInternal> {
+    loop {
+        tokio::select! {
+            new_stream = new_streams.recv() => return new_stream,
+            Some(result) = streams.join_next(), if !streams.is_empty() => result.unwrap(),
+        }
     }
+}
+
+async fn run_server_reusing_connections<R, W>(
+    mut new_streams: mpsc::UnboundedReceiver<ServerSubHandleInternal>,
+    n_preallocated_connections: usize,
+    transport: ReusableTransport<R, W>,
+    client_evars: Vec<(OsString, OsString)>,
+) -> JoinHandle<()>
+where
+    R: tokio::io::AsyncRead + Unpin + Send + 'static,
+    W: tokio::io::AsyncWrite + Unpin + Send + 'static,
+{
     tokio::spawn(async move {
-        let id = rand::random::<u32>();
-        for (k, handle) in handles.into_iter().enumerate() {
-            info!("Waiting for handle {} ({})", k, id);
-            handle.await.unwrap();
-            info!("Handle {} done ({})", k, id);
+        let mut streams = JoinSet::new();
+        let mut precids = 0..;
+        while let Some((update_receiver, ack_sender, report_sender)) =
+            next_stream(&mut new_streams, &mut streams).await
+        {
+            let (connection_requests_sender, connection_requests_receiver) = mpsc::channel(1);
+            let (granted_connections_sender, granted_connections_receiver) = mpsc::channel(1);
+            let (connection_receiver, pool_handle) = reusing(
+                transport,
+                n_preallocated_connections,
+                connection_requests_receiver,
+                granted_connections_sender,
+            );
+            let (evars_sender, evars_receiver) = mpsc::channel::<Ack>(1);
+            streams.spawn(async move { pool_handle.await.unwrap() });
+            streams.spawn(process_precedence_stream(
+                update_receiver,
+                evars_sender,
+                report_sender,
+                connection_receiver,
+                PrecedenceId(precids.next().unwrap()),
+                Some(connection_requests_sender),
+                transport.management,
+                client_evars.clone(),
+            ));
+            streams.spawn(add_granted_connections_to_acks(
+                evars_receiver,
+                granted_connections_receiver,
+                ack_sender,
+            ));
         }
-        for (k, handle) in abort_handles.into_iter().enumerate() {
-            info!("Waiting for abort handle {} ({})", k, id);
-            handle.abort();
-            info!("Abort handle {} done ({})", k, id);
+        while let Some(result) = streams.join_next().await {
+            result.unwrap();
         }
     })
 }
+
+/// Tells the processes of each run which of the inherited file descriptors to use by adding the
+/// connections granted by the connection pool to the ack of the run.
+async fn add_granted_connections_to_acks(
+    mut evars_receiver: mpsc::Receiver<Ack>,
+    mut granted_connections_receiver: mpsc::Receiver<Vec<(FederateId, RawFd)>>,
+    ack_sender: mpsc::Sender<Ack>,
+) {
+    let mut evars_option = evars_receiver.recv().await;
+    while let Some(ack) = evars_option.take() {
+        let mut evars = match ack {
+            Ok(evars) => evars,
+            Err(e) => {
+                // No connections are requested for a rejected precedence.
+                ack_sender.send(Err(e)).await.unwrap();
+                evars_option = evars_receiver.recv().await;
+                continue;
+            }
+        };
+        tokio::select! {
+            granted_connections = granted_connections_receiver.recv() => {
+                for (fedid, granted) in granted_connections.unwrap() {
+                    evars
+                        .0
+                        .push((evar_name_for(fedid).into(), granted.to_string().into()));
+                }
+                ack_sender.send(Ok(evars)).await.unwrap();
+                evars_option = evars_receiver.recv().await;
+            }
+            next_evars = evars_receiver.recv() => {
+                if next_evars.is_some() {
+                    error!("received next evars when it was not expected. Continuing...");
+                }
+                evars_option = next_evars;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::{
+        client::{BlockingClient, ClientConfig, ClientTransport, MemoryBlockingClientJoinHandle},
+        pattern::SeqnumPattern,
+        HookId, HookInvocation,
+    };
+
+    #[tokio::test]
+    async fn test_tcp_mode_reports_bind_errors_and_advertises_its_host() {
+        let scratch_dir = crate::test_scratch_dir("bind");
+        let mut server_handle = run_on("127.0.0.1", 0, "ordserv.test", 1).await.unwrap();
+        let (updates, acks, reports) = &mut server_handle.updates_acks[0];
+        let precedence = Precedence::from_list(0, &[], scratch_dir.clone(), 0);
+        updates.send(Update::Start(precedence)).await.unwrap();
+        let evars = acks.recv().await.unwrap().unwrap();
+        assert_eq!(evars.get(ORDSERV_HOST_ENV_VAR).unwrap(), "ordserv.test");
+        let port: u16 = evars
+            .get(ORDSERV_PORT_ENV_VAR)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        let e = run(port, 0).await.err().expect("the port is taken");
+        assert_eq!(e.kind(), io::ErrorKind::AddrInUse);
+        updates.send(Update::Finish).await.unwrap();
+        reports.recv().await.unwrap();
+        updates.send(Update::Halt).await.unwrap();
+        server_handle.join().await;
+        std::fs::remove_dir_all(scratch_dir).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_tcp_mode_orders_hook_invocations() {
+        let scratch_dir = crate::test_scratch_dir("tcp");
+        let mut server_handle = run(0, 1).await.unwrap();
+        let (updates, acks, reports) = &mut server_handle.updates_acks[0];
+        let mut precedence = Precedence::from_list(
+            2,
+            &[(("notifier", 0, 0), &[("waiter", 1, 0)])],
+            scratch_dir.clone(),
+            0,
+        );
+        precedence.record_timeline = true;
+        updates
+            .send(Update::Start(precedence.clone()))
+            .await
+            .unwrap();
+        let evars = acks.recv().await.unwrap().unwrap();
+        let evar = |name| evars.get(name).unwrap().to_str().unwrap().to_string();
+        let address = format!(
+            "{}:{}",
+            evar(ORDSERV_HOST_ENV_VAR),
+            evar(ORDSERV_PORT_ENV_VAR)
+        );
+        let precid = PrecedenceId(evar(PRECEDENCE_ID_NAME).parse().unwrap());
+        let spawn_client = move |fedid, action: fn(&BlockingClient)| {
+            let config = ClientConfig {
+                federate_id: FederateId(fedid),
+                wait_timeout: Duration::from_secs(10),
+                transport: ClientTransport::Tcp(address.clone()),
+                precedence: precedence.clone(),
+                precid,
+            };
+            std::thread::spawn(move || {
+                let (client, join_handle) = BlockingClient::start_with_config(config);
+                action(&client);
+                client.halt.send(()).unwrap();
+                drop(client);
+                join_handle.join().unwrap();
+            })
+        };
+        let spawn_waiter = spawn_client.clone();
+        let waiter = std::thread::spawn(move || {
+            let start = Instant::now();
+            spawn_waiter(1, |client| {
+                client.tracepoint_maybe_wait(HookInvocation::from_short(("waiter", 1, 0)))
+            })
+            .join()
+            .unwrap();
+            start.elapsed()
+        });
+        let notifier = spawn_client(0, |client| {
+            std::thread::sleep(Duration::from_millis(200));
+            client.tracepoint_maybe_notify(HookInvocation::from_short(("notifier", 0, 0)));
+        });
+        let waited = tokio::task::spawn_blocking(move || {
+            notifier.join().unwrap();
+            waiter.join().unwrap()
+        })
+        .await
+        .unwrap();
+        assert!(waited >= Duration::from_millis(200));
+        assert!(waited < Duration::from_secs(10));
+        updates.send(Update::Finish).await.unwrap();
+        let report = reports.recv().await.unwrap();
+        assert!(report.is_clean(), "{}", report);
+        let stats = &report.wait_stats[&HookInvocation::from_short(("waiter", 1, 0)).hid];
+        assert_eq!((stats.n_waits, stats.n_timeouts), (1, 0));
+        assert!(report.timeline.unwrap().exists());
+        updates.send(Update::Halt).await.unwrap();
+        server_handle.join().await;
+        std::fs::remove_dir_all(scratch_dir).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_waiters_of_a_federate_that_went_away_are_released() {
+        let scratch_dir = crate::test_scratch_dir("went-away");
+        let (mut server_handle, connector) = run_in_memory(1).await;
+        let (updates, acks, reports) = &mut server_handle.updates_acks[0];
+        let precedence = Precedence::from_list(
+            2,
+            &[(("never sent", 0, 0), &[("waiter", 1, 0)])],
+            scratch_dir.clone(),
+            9,
+        );
+        updates.send(Update::Start(precedence)).await.unwrap();
+        let evars = acks.recv().await.unwrap().unwrap();
+        let waited = tokio::task::spawn_blocking(move || {
+            let start = |fedid| {
+                BlockingClient::start_in_memory(
+                    &connector,
+                    &evars,
+                    FederateId(fedid),
+                    Duration::from_secs(10),
+                )
+            };
+            let (crashing, crashing_join_handle) = start(0);
+            let (waiter, waiter_join_handle) = start(1);
+            let start = Instant::now();
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(100));
+                crashing.halt.send(()).unwrap();
+                drop(crashing);
+                crashing_join_handle.join().unwrap();
+            });
+            waiter.tracepoint_maybe_wait(HookInvocation::from_short(("waiter", 1, 0)));
+            let waited = start.elapsed();
+            waiter.halt.send(()).unwrap();
+            drop(waiter);
+            waiter_join_handle.join().unwrap();
+            waited
+        })
+        .await
+        .unwrap();
+        assert!(waited < Duration::from_secs(5), "{:?}", waited);
+        updates.send(Update::Finish).await.unwrap();
+        let report = reports.recv().await.unwrap();
+        assert!(report.is_clean(), "{}", report);
+        assert!(report.timeouts.is_empty(), "{}", report);
+        assert_eq!(
+            report.disconnects,
+            vec![Disconnect {
+                fedid: FederateId(0),
+                pid: std::process::id(),
+                released: vec![HookInvocation::from_short(("waiter", 1, 0))],
+                released_patterns: vec![],
+            }]
+        );
+        updates.send(Update::Halt).await.unwrap();
+        server_handle.join().await;
+        std::fs::remove_dir_all(scratch_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sparse_federates_may_connect_late() {
+        let scratch_dir = crate::test_scratch_dir("sparse-late");
+        let (mut server_handle, connector) = run_in_memory(1).await;
+        let (updates, acks, reports) = &mut server_handle.updates_acks[0];
+        let precedence = |run_id| {
+            Precedence::from_list(
+                0,
+                &[(("A", 3, 0), &[("B", 7, 0)])],
+                scratch_dir.clone(),
+                run_id,
+            )
+        };
+        let run = |evars: EnvironmentVariables, connect_waiter: bool| {
+            let connector = connector.clone();
+            tokio::task::spawn_blocking(move || {
+                let start = |fedid| {
+                    BlockingClient::start_in_memory(
+                        &connector,
+                        &evars,
+                        FederateId(fedid),
+                        Duration::from_secs(10),
+                    )
+                };
+                let stop =
+                    |(client, join_handle): (BlockingClient, MemoryBlockingClientJoinHandle)| {
+                        client.halt.send(()).unwrap();
+                        drop(client);
+                        join_handle.join().unwrap();
+                    };
+                let notifier = start(3);
+                notifier
+                    .0
+                    .tracepoint_maybe_notify(HookInvocation::from_short(("A", 3, 0)));
+                stop(notifier);
+                if !connect_waiter {
+                    return Duration::ZERO;
+                }
+                // The waiter connects after its notifier has gone away.
+                let waiter = start(7);
+                let start = Instant::now();
+                waiter
+                    .0
+                    .tracepoint_maybe_wait(HookInvocation::from_short(("B", 7, 0)));
+                let waited = start.elapsed();
+                stop(waiter);
+                waited
+            })
+        };
+        updates.send(Update::Start(precedence(0))).await.unwrap();
+        let evars = acks.recv().await.unwrap().unwrap();
+        let waited = run(evars, true).await.unwrap();
+        assert!(waited < Duration::from_secs(5), "{:?}", waited);
+        updates.send(Update::Finish).await.unwrap();
+        let report = reports.recv().await.unwrap();
+        assert!(report.is_clean(), "{}", report);
+        assert!(report.timeouts.is_empty(), "{}", report);
+
+        updates.send(Update::Start(precedence(1))).await.unwrap();
+        let evars = acks.recv().await.unwrap().unwrap();
+        run(evars, false).await.unwrap();
+        updates.send(Update::Finish).await.unwrap();
+        let report = reports.recv().await.unwrap();
+        assert_eq!(
+            report.anomalies,
+            vec![Anomaly::UnknownDestination {
+                hook_invocation: HookInvocation::from_short(("A", 3, 0)),
+                waiter: HookInvocation::from_short(("B", 7, 0)),
+            }]
+        );
+        updates.send(Update::Halt).await.unwrap();
+        server_handle.join().await;
+        std::fs::remove_dir_all(scratch_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_connections_are_granted_to_sparse_federates() {
+        let scratch_dir = crate::test_scratch_dir("sparse-granted");
+        let mut server_handle = run_reusing_connections(1, 0).await;
+        let (updates, acks, reports) = &mut server_handle.updates_acks[0];
+        let precedence =
+            Precedence::from_list(0, &[(("A", 2, 0), &[("B", 5, 0)])], scratch_dir.clone(), 0);
+        updates.send(Update::Start(precedence)).await.unwrap();
+        let evars = acks.recv().await.unwrap().unwrap();
+        let granted = |fedid| evars.get(&evar_name_for(FederateId(fedid))).is_some();
+        assert!(granted(2) && granted(5) && !granted(-1), "{:?}", evars);
+        updates.send(Update::Finish).await.unwrap();
+        reports.recv().await.unwrap();
+        updates.send(Update::Halt).await.unwrap();
+        server_handle.join().await;
+        std::fs::remove_dir_all(scratch_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_a_federate_that_does_not_read_holds_up_no_one_else() {
+        // Enough frames to fill the buffers of an in-memory connection many times over.
+        const N_NOTIFICATIONS: u32 = 20_000;
+        let scratch_dir = crate::test_scratch_dir("stalled");
+        let (mut server_handle, connector) = run_in_memory(1).await;
+        let (updates, acks, reports) = &mut server_handle.updates_acks[0];
+        let pattern = |hid: &str, fedid| {
+            HookInvocationPattern::new(
+                HookId::new(hid.into(), FederateId(fedid)),
+                SeqnumPattern::Any,
+            )
+        };
+        let mut precedence = Precedence::from_list(3, &[], scratch_dir.clone(), 19);
+        precedence.pattern_edges = vec![PatternEdge {
+            notifier: pattern("notifier", 0),
+            waiters: vec![pattern("stalled", 1), pattern("reader", 2)],
+        }];
+        updates.send(Update::Start(precedence)).await.unwrap();
+        let precid = acks.recv().await.unwrap().unwrap().precedence_id().unwrap();
+        let connect = |fedid| connector.connect_as(precid, FederateId(fedid), RunId(19));
+        let mut notifier = connect(0).await.unwrap();
+        let _stalled = connect(1).await.unwrap();
+        let mut reader = connect(2).await.unwrap();
+        let notifying = tokio::spawn(async move {
+            for seqnum in 0..N_NOTIFICATIONS {
+                let notification = HookInvocation::from_short(("notifier", 0, seqnum));
+                notifier
+                    .write_frame(&Frame::notify(precid.0, &notification, 19))
+                    .await
+                    .unwrap();
+            }
+            notifier
+        });
+        for seqnum in 0..N_NOTIFICATIONS {
+            let frame = tokio::time::timeout(Duration::from_secs(5), reader.read_frame())
+                .await
+                .expect("forwarding to the federate that reads is stuck")
+                .unwrap()
+                .unwrap();
+            assert_eq!(frame.sequence_number, seqnum);
+        }
+        let _notifier = notifying.await.unwrap();
+        updates.send(Update::Finish).await.unwrap();
+        let report = reports.recv().await.unwrap();
+        assert!(report.is_clean(), "{}", report);
+        updates.send(Update::Halt).await.unwrap();
+        server_handle.join().await;
+        std::fs::remove_dir_all(scratch_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_anomalies_and_timeouts_are_reported() {
+        let scratch_dir = crate::test_scratch_dir("anomalies");
+        let mut server_handle = run(0, 1).await.unwrap();
+        let (updates, acks, reports) = &mut server_handle.updates_acks[0];
+        updates
+            .send(Update::Start(Precedence::from_list(
+                1,
+                &[(("ping", 0, 0), &[("pong", 0, 0)])],
+                scratch_dir.clone(),
+                5,
+            )))
+            .await
+            .unwrap();
+        let evars = acks.recv().await.unwrap().unwrap();
+        let (_, port) = evars
+            .0
+            .iter()
+            .find(|(k, _)| k == ORDSERV_PORT_ENV_VAR)
+            .unwrap();
+        let stream =
+            tokio::net::TcpStream::connect(("127.0.0.1", port.to_str().unwrap().parse().unwrap()))
+                .await
+                .unwrap();
+        let mut connection = crate::connection::Connection::new(stream.into_split());
+        connection
+            .write_frame(&Frame::hello(0, FederateId(0), 5))
+            .await
+            .unwrap();
+        assert_eq!(
+            connection.read_frame().await.unwrap().unwrap().kind,
+            crate::protocol::FrameKind::Hello
+        );
+        let unexpected = HookInvocation::from_short(("nobody waits for this", 0, 0));
+        let ping = HookInvocation::from_short(("ping", 0, 0));
+        let pong = HookInvocation::from_short(("pong", 0, 0));
+        for frame in [
+            Frame::notify(0, &ping, 4),
+            Frame::notify(0, &unexpected, 5),
+            Frame::timeout(0, &pong, 5),
+            Frame::notify(0, &ping, 5),
+        ] {
+            connection.write_frame(&frame).await.unwrap();
+        }
+        // Frames are handled in order, so once the ping comes back the others have been handled.
+        assert_eq!(
+            connection
+                .read_frame()
+                .await
+                .unwrap()
+                .unwrap()
+                .hook_invocation(),
+            ping
+        );
+        updates.send(Update::Finish).await.unwrap();
+        let report = reports.recv().await.unwrap();
+        assert_eq!(report.run_id, RunId(5));
+        assert_eq!(
+            report.anomalies,
+            vec![
+                Anomaly::StrayRunId {
+                    fedid: FederateId(0),
+                    run_id: RunId(4)
+                },
+                Anomaly::UnexpectedFrame {
+                    from: FederateId(0),
+                    hook_invocation: unexpected
+                },
+            ]
+        );
+        assert_eq!(
+            report.timeouts,
+            vec![TimedOutWait {
+                waiter: pong,
+                unsatisfied_by: vec![ping],
+                unsatisfied_by_patterns: vec![],
+            }]
+        );
+        updates.send(Update::Halt).await.unwrap();
+        server_handle.join().await;
+        std::fs::remove_dir_all(scratch_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sub_handles_can_be_added_and_removed() {
+        let scratch_dir = crate::test_scratch_dir("sub-handles");
+        let server_handle = run(0, 0).await.unwrap();
+        let mut precids = vec![];
+        for _ in 0..2 {
+            let (updates, mut acks, mut reports) = server_handle.add_sub_handle();
+            updates
+                .send(Update::Start(Precedence::from_list(
+                    0,
+                    &[],
+                    scratch_dir.clone(),
+                    0,
+                )))
+                .await
+                .unwrap();
+            let evars = acks.recv().await.unwrap().unwrap();
+            precids.push(
+                evars
+                    .0
+                    .into_iter()
+                    .find(|(k, _)| k == PRECEDENCE_ID_NAME)
+                    .unwrap()
+                    .1,
+            );
+            updates.send(Update::Finish).await.unwrap();
+            assert!(reports.recv().await.unwrap().is_clean());
+            server_handle
+                .remove_sub_handle((updates, acks, reports))
+                .await;
+        }
+        assert_ne!(precids[0], precids[1]);
+        server_handle.join().await;
+        std::fs::remove_dir_all(scratch_dir).unwrap();
+    }
+}
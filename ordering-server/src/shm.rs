@@ -0,0 +1,501 @@
+//! A transport for runs whose processes all share a host with the server, in which frames pass
+//! through shared memory instead of through sockets.
+//!
+//! Each connection is a memfd that holds two single-producer single-consumer byte rings, one for
+//! each direction, and the eventfds that wake the ends of the rings. A reader or writer that finds
+//! its ring empty or full says so in the ring before it waits for its eventfd, and the other end
+//! only writes to the eventfd if someone is waiting, so a busy connection passes frames without any
+//! system calls at all.
+//!
+//! Like the sockets of [`crate::server::run_reusing_connections`], connections are pooled and
+//! inherited by the processes of each run, and the server holds on to both of their ends. The
+//! client end is a duplicate of the server end, and the mapping records which descriptor belongs to
+//! the server, so a single [`ConnectionManagement`] can borrow either end. The eventfds are
+//! inherited along with the memfd and keep their numbers, so the mapping records them too.
+
+use std::{
+    io,
+    os::fd::{AsRawFd, RawFd},
+    pin::Pin,
+    ptr::NonNull,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+
+use tokio::io::{unix::AsyncFd, AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::{
+    connection::{Connection, ConnectionManagement},
+    tcpconnectionprovider::ReusableTransport,
+};
+
+/// How many bytes may be in flight in each direction of a connection.
+const RING_CAPACITY: usize = 64 * 1024;
+
+/// The value of [`crate::ORDSERV_TRANSPORT_ENV_VAR`] in the environment of the processes of a run
+/// that is served by [`crate::server::run_shared_memory`].
+pub const SHARED_MEMORY_TRANSPORT_NAME: &str = "shared-memory";
+
+#[repr(C, align(64))]
+struct RingHeader {
+    /// How many bytes have been read from the ring so far.
+    head: AtomicU64,
+    /// How many bytes have been written to the ring so far.
+    tail: AtomicU64,
+    reader_parked: AtomicBool,
+    writer_parked: AtomicBool,
+    /// Set once the writer has shut down, after its last write.
+    closed: AtomicBool,
+    /// Written to wake the reader.
+    data_ready: RawFd,
+    /// Written to wake the writer.
+    space_ready: RawFd,
+}
+
+#[repr(C)]
+struct Region {
+    server_fd: RawFd,
+    to_server: RingHeader,
+    to_client: RingHeader,
+    to_server_data: [u8; RING_CAPACITY],
+    to_client_data: [u8; RING_CAPACITY],
+}
+
+/// A mapping of the region of a connection, which is unmapped once both halves are dropped.
+struct Mapping(NonNull<Region>);
+
+// The region is only accessed through atomics and through the ring ends, of which each half has
+// exactly one.
+unsafe impl Send for Mapping {}
+unsafe impl Sync for Mapping {}
+
+impl Mapping {
+    unsafe fn new(fd: RawFd) -> io::Result<Self> {
+        let region = libc::mmap(
+            std::ptr::null_mut(),
+            std::mem::size_of::<Region>(),
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_SHARED,
+            fd,
+            0,
+        );
+        if region == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mapping(NonNull::new_unchecked(region as *mut Region)))
+    }
+    fn region(&self) -> &Region {
+        unsafe { self.0.as_ref() }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.0.as_ptr() as *mut _, std::mem::size_of::<Region>());
+        }
+    }
+}
+
+/// One end of a ring. The data is reached through a raw pointer because the other end writes to
+/// the parts of it that this end does not own.
+struct RingEnd {
+    _mapping: Arc<Mapping>,
+    header: *const RingHeader,
+    data: *mut u8,
+}
+
+unsafe impl Send for RingEnd {}
+unsafe impl Sync for RingEnd {}
+
+impl RingEnd {
+    fn header(&self) -> &RingHeader {
+        unsafe { &*self.header }
+    }
+    /// Copies as many bytes as are available into `dst`. Only the reader calls this.
+    fn read(&self, dst: &mut [u8]) -> usize {
+        let header = self.header();
+        let head = header.head.load(Ordering::Relaxed);
+        let tail = header.tail.load(Ordering::Acquire);
+        let n = dst.len().min((tail - head) as usize);
+        let start = head as usize % RING_CAPACITY;
+        let first = n.min(RING_CAPACITY - start);
+        unsafe {
+            std::ptr::copy_nonoverlapping(self.data.add(start), dst.as_mut_ptr(), first);
+            std::ptr::copy_nonoverlapping(self.data, dst.as_mut_ptr().add(first), n - first);
+        }
+        header.head.store(head + n as u64, Ordering::SeqCst);
+        n
+    }
+    /// Copies as much of `src` as fits into the ring. Only the writer calls this.
+    fn write(&self, src: &[u8]) -> usize {
+        let header = self.header();
+        let head = header.head.load(Ordering::Acquire);
+        let tail = header.tail.load(Ordering::Relaxed);
+        let n = src.len().min(RING_CAPACITY - (tail - head) as usize);
+        let start = tail as usize % RING_CAPACITY;
+        let first = n.min(RING_CAPACITY - start);
+        unsafe {
+            std::ptr::copy_nonoverlapping(src.as_ptr(), self.data.add(start), first);
+            std::ptr::copy_nonoverlapping(src.as_ptr().add(first), self.data, n - first);
+        }
+        header.tail.store(tail + n as u64, Ordering::SeqCst);
+        n
+    }
+}
+
+/// An eventfd that belongs to the connection, so it is not closed when it is dropped.
+struct EventFd(RawFd);
+
+impl AsRawFd for EventFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+fn signal(fd: RawFd) {
+    let one: u64 = 1;
+    // The write only fails if the counter is about to overflow, in which case the waiter is awake
+    // anyway.
+    unsafe {
+        libc::write(fd, &one as *const u64 as *const _, 8);
+    }
+}
+
+/// Waits until `fd` has been signalled since it was last drained, and drains it.
+fn poll_signalled(fd: &AsyncFd<EventFd>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    let mut guard = match fd.poll_read_ready(cx) {
+        Poll::Ready(Ok(guard)) => guard,
+        Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+        Poll::Pending => return Poll::Pending,
+    };
+    let mut counter: u64 = 0;
+    unsafe {
+        libc::read(fd.as_raw_fd(), &mut counter as *mut u64 as *mut _, 8);
+    }
+    guard.clear_ready();
+    Poll::Ready(Ok(()))
+}
+
+pub struct ShmReadHalf {
+    ring: RingEnd,
+    data_ready: AsyncFd<EventFd>,
+}
+
+pub struct ShmWriteHalf {
+    ring: RingEnd,
+    space_ready: AsyncFd<EventFd>,
+}
+
+impl AsyncRead for ShmReadHalf {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            let header = this.ring.header();
+            let closed = header.closed.load(Ordering::Acquire);
+            let n = this.ring.read(buf.initialize_unfilled());
+            if n > 0 {
+                buf.advance(n);
+                if header.writer_parked.swap(false, Ordering::SeqCst) {
+                    signal(header.space_ready);
+                }
+                return Poll::Ready(Ok(()));
+            }
+            if closed || buf.remaining() == 0 {
+                return Poll::Ready(Ok(()));
+            }
+            header.reader_parked.store(true, Ordering::SeqCst);
+            if header.tail.load(Ordering::SeqCst) != header.head.load(Ordering::Relaxed)
+                || header.closed.load(Ordering::SeqCst)
+            {
+                continue;
+            }
+            match poll_signalled(&this.data_ready, cx) {
+                Poll::Ready(Ok(())) => {}
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+impl AsyncWrite for ShmWriteHalf {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        src: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        loop {
+            let header = this.ring.header();
+            let n = this.ring.write(src);
+            if n > 0 || src.is_empty() {
+                if header.reader_parked.swap(false, Ordering::SeqCst) {
+                    signal(header.data_ready);
+                }
+                return Poll::Ready(Ok(n));
+            }
+            header.writer_parked.store(true, Ordering::SeqCst);
+            let in_flight =
+                header.tail.load(Ordering::Relaxed) - header.head.load(Ordering::SeqCst);
+            if (in_flight as usize) < RING_CAPACITY {
+                continue;
+            }
+            match poll_signalled(&this.space_ready, cx) {
+                Poll::Ready(Ok(())) => {}
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // Written bytes are visible to the reader right away.
+        Poll::Ready(Ok(()))
+    }
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let header = self.ring.header();
+        header.closed.store(true, Ordering::SeqCst);
+        if header.reader_parked.swap(false, Ordering::SeqCst) {
+            signal(header.data_ready);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Giving back a connection only unmaps it; the pool closes its descriptors.
+pub const SHM_CONNECTION_MANAGEMENT: ConnectionManagement<ShmReadHalf, ShmWriteHalf> =
+    ConnectionManagement {
+        borrow: |fd| Ok(Connection::new(unsafe { halves_from_raw_fd(fd)? })),
+        unborrow: |_| {},
+    };
+
+/// Shared-memory connections for [`crate::tcpconnectionprovider::reusing`].
+pub const SHM_REUSABLE_TRANSPORT: ReusableTransport<ShmReadHalf, ShmWriteHalf> =
+    ReusableTransport {
+        management: SHM_CONNECTION_MANAGEMENT,
+        pair: make_server_and_client_region_pair,
+        close: close_region_pair,
+    };
+
+unsafe fn halves_from_raw_fd(fd: RawFd) -> io::Result<(ShmReadHalf, ShmWriteHalf)> {
+    let mapping = Arc::new(Mapping::new(fd)?);
+    let region = mapping.0.as_ptr();
+    let (incoming, incoming_data, outgoing, outgoing_data) = if (*region).server_fd == fd {
+        (
+            std::ptr::addr_of!((*region).to_server),
+            std::ptr::addr_of_mut!((*region).to_server_data) as *mut u8,
+            std::ptr::addr_of!((*region).to_client),
+            std::ptr::addr_of_mut!((*region).to_client_data) as *mut u8,
+        )
+    } else {
+        (
+            std::ptr::addr_of!((*region).to_client),
+            std::ptr::addr_of_mut!((*region).to_client_data) as *mut u8,
+            std::ptr::addr_of!((*region).to_server),
+            std::ptr::addr_of_mut!((*region).to_server_data) as *mut u8,
+        )
+    };
+    let read = ShmReadHalf {
+        data_ready: AsyncFd::new(EventFd((*incoming).data_ready))?,
+        ring: RingEnd {
+            _mapping: Arc::clone(&mapping),
+            header: incoming,
+            data: incoming_data,
+        },
+    };
+    let write = ShmWriteHalf {
+        space_ready: AsyncFd::new(EventFd((*outgoing).space_ready))?,
+        ring: RingEnd {
+            _mapping: mapping,
+            header: outgoing,
+            data: outgoing_data,
+        },
+    };
+    Ok((read, write))
+}
+
+/// Makes a region whose descriptors are all inherited by child processes. Returns the server end
+/// and the client end.
+fn make_server_and_client_region_pair() -> (RawFd, RawFd) {
+    unsafe {
+        let fd = libc::memfd_create(c"ordserv".as_ptr(), 0);
+        assert!(fd >= 0, "memfd_create: {}", io::Error::last_os_error());
+        assert_eq!(
+            libc::ftruncate(fd, std::mem::size_of::<Region>() as libc::off_t),
+            0,
+            "ftruncate: {}",
+            io::Error::last_os_error()
+        );
+        let mapping = Mapping::new(fd).expect("Failed to map shared memory");
+        let region = mapping.0.as_ptr();
+        // The new pages are zeroed, so only the descriptors have to be filled in.
+        (*region).server_fd = fd;
+        for header in [
+            std::ptr::addr_of_mut!((*region).to_server),
+            std::ptr::addr_of_mut!((*region).to_client),
+        ] {
+            (*header).data_ready = make_eventfd();
+            (*header).space_ready = make_eventfd();
+        }
+        let client = libc::dup(fd);
+        assert!(client >= 0, "dup: {}", io::Error::last_os_error());
+        (fd, client)
+    }
+}
+
+fn make_eventfd() -> RawFd {
+    let fd = unsafe { libc::eventfd(0, libc::EFD_NONBLOCK) };
+    assert!(fd >= 0, "eventfd: {}", io::Error::last_os_error());
+    fd
+}
+
+unsafe fn close_region_pair(server: RawFd, client: RawFd) -> (io::Result<()>, io::Result<()>) {
+    if let Ok(mapping) = Mapping::new(server) {
+        let region = mapping.region();
+        for header in [&region.to_server, &region.to_client] {
+            libc::close(header.data_ready);
+            libc::close(header.space_ready);
+        }
+    }
+    (close(server), close(client))
+}
+
+fn close(fd: RawFd) -> io::Result<()> {
+    if unsafe { libc::close(fd) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        protocol::FrameKind,
+        report::Anomaly,
+        server::{run_shared_memory, Update},
+        EnvironmentVariables, FederateId, Frame, HookInvocation, Precedence, RunId,
+        ORDSERV_TRANSPORT_ENV_VAR,
+    };
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_frames_wrap_around_the_rings_in_both_directions() {
+        let (server, client) = make_server_and_client_region_pair();
+        let mut server_end = unsafe { (SHM_CONNECTION_MANAGEMENT.borrow)(server) }.unwrap();
+        let mut client_end = unsafe { (SHM_CONNECTION_MANAGEMENT.borrow)(client) }.unwrap();
+        // Many times the capacity of a ring, so the writer has to wait for the reader.
+        const N_FRAMES: u32 = 20_000;
+        let frame = |seqnum| {
+            Frame::notify(
+                3,
+                &HookInvocation::from_short(("ring", 1, seqnum)),
+                RunId(7).0,
+            )
+        };
+        let writer = tokio::spawn(async move {
+            for seqnum in 0..N_FRAMES {
+                client_end.write_frame(&frame(seqnum)).await.unwrap();
+            }
+            let reply = client_end.read_frame().await.unwrap().unwrap();
+            client_end.close().await;
+            reply
+        });
+        for seqnum in 0..N_FRAMES {
+            let received = server_end.read_frame().await.unwrap().unwrap();
+            assert_eq!(received, frame(seqnum));
+        }
+        server_end
+            .write_frame(&Frame::hello(3, FederateId(1), 7))
+            .await
+            .unwrap();
+        assert_eq!(writer.await.unwrap().kind, FrameKind::Hello);
+        assert_eq!(server_end.read_frame().await.unwrap(), None);
+        drop(server_end);
+        let (server_closed, client_closed) = unsafe { close_region_pair(server, client) };
+        server_closed.unwrap();
+        client_closed.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_shared_memory_mode_forwards_notifications_of_the_current_run() {
+        let scratch_dir = crate::test_scratch_dir("shm");
+        let mut server_handle = run_shared_memory(1, 0).await;
+        let (updates, acks, reports) = &mut server_handle.updates_acks[0];
+        let precedence = Precedence::from_list(
+            2,
+            &[(("notifier", -1, 0), &[("waiter", 0, 0)])],
+            scratch_dir.clone(),
+            4,
+        );
+        updates.send(Update::Start(precedence)).await.unwrap();
+        let evars: EnvironmentVariables = acks.recv().await.unwrap().unwrap();
+        let evar = |name: &str| evars.get(name).unwrap().to_str().unwrap().to_string();
+        assert_eq!(
+            evar(ORDSERV_TRANSPORT_ENV_VAR),
+            SHARED_MEMORY_TRANSPORT_NAME
+        );
+        let precid = evars.precedence_id().unwrap();
+        let connect = |fedid| {
+            let fd = evar(&format!("ORDSERV_CONNECTION_{}", fedid))
+                .parse()
+                .unwrap();
+            unsafe { (SHM_CONNECTION_MANAGEMENT.borrow)(fd) }.unwrap()
+        };
+        // The connections are numbered from the RTI, which is federate -1, on.
+        let mut notifier = connect(-1);
+        let mut waiter = connect(0);
+        for (connection, fedid) in [(&mut notifier, -1), (&mut waiter, 0)] {
+            connection
+                .write_frame(&Frame::hello(precid.0, FederateId(fedid), 4))
+                .await
+                .unwrap();
+            assert_eq!(
+                connection.read_frame().await.unwrap().unwrap().kind,
+                FrameKind::Hello
+            );
+        }
+        let notification = HookInvocation::from_short(("notifier", -1, 0));
+        // A straggler from an earlier run is not forwarded.
+        notifier
+            .write_frame(&Frame::notify(precid.0, &notification, 3))
+            .await
+            .unwrap();
+        notifier
+            .write_frame(&Frame::notify(precid.0, &notification, 4))
+            .await
+            .unwrap();
+        let forwarded = tokio::time::timeout(Duration::from_secs(5), waiter.read_frame())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(forwarded.run_id, 4);
+        assert_eq!(forwarded.hook_invocation(), notification);
+        updates.send(Update::Finish).await.unwrap();
+        let report = reports.recv().await.unwrap();
+        assert!(
+            matches!(
+                report.anomalies[..],
+                [Anomaly::StrayRunId {
+                    fedid: FederateId(-1),
+                    run_id: RunId(3)
+                }]
+            ),
+            "{}",
+            report
+        );
+        updates.send(Update::Halt).await.unwrap();
+        server_handle.join().await;
+        std::fs::remove_dir_all(scratch_dir).unwrap();
+    }
+}
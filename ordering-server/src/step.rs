@@ -0,0 +1,311 @@
+//! Step mode, in which a person decides when each notification of a run is delivered.
+//!
+//! When [`crate::Precedence::step`] is set, the router of the run holds every notification that it
+//! receives instead of forwarding it, and the clients tell the server whenever one of their hook
+//! invocations starts or stops waiting. A driver sends [`Update::Step`] to the precedence stream of
+//! the run to see what is held and what is blocked, and to release held notifications one at a
+//! time. [`prompt`] does this from a terminal, which is how `ordserv --step` steps through its
+//! runs, and the control socket accepts the same commands (see [`crate::control`]).
+//!
+//! The processes of a run in step mode wait as long as a person takes to release their
+//! notifications, so they should be given a generous wait timeout.
+
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt},
+    sync::{mpsc, oneshot},
+};
+
+use crate::{server::Update, HookInvocation};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum StepCommand {
+    /// Reports what is held and blocked without releasing anything.
+    Show,
+    /// Forwards the notification at position `index` of [`StepState::held`].
+    Release { index: usize },
+    /// Forwards every held notification and stops holding notifications for the rest of the run.
+    Continue,
+}
+
+/// What a run in step mode is waiting for.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StepState {
+    /// The notifications that have been received but not forwarded, oldest first.
+    pub held: Vec<HookInvocation>,
+    /// The hook invocations that are blocked in `tracepoint_maybe_wait`, in the order in which
+    /// they started to wait.
+    pub blocked: Vec<HookInvocation>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StepError {
+    /// The precedence stream has no run in progress.
+    NoRun,
+    /// The run in progress was not started in step mode.
+    NotStepping,
+    NoSuchNotification {
+        index: usize,
+        n_held: usize,
+    },
+}
+
+pub type StepReply = oneshot::Sender<Result<StepState, StepError>>;
+
+impl Display for StepState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "held notifications:")?;
+        if self.held.is_empty() {
+            writeln!(f, "  (none)")?;
+        }
+        for (index, notification) in self.held.iter().enumerate() {
+            writeln!(f, "  [{}] {}", index, notification)?;
+        }
+        writeln!(f, "blocked:")?;
+        if self.blocked.is_empty() {
+            writeln!(f, "  (none)")?;
+        }
+        for waiter in &self.blocked {
+            writeln!(f, "  federate {}: {}", waiter.hid.1 .0, waiter)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for StepError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StepError::NoRun => write!(f, "no run is in progress"),
+            StepError::NotStepping => write!(f, "the run is not in step mode"),
+            StepError::NoSuchNotification { index, n_held } => write!(
+                f,
+                "there is no held notification {} (there are {})",
+                index, n_held
+            ),
+        }
+    }
+}
+
+impl std::error::Error for StepError {}
+
+/// Sends `command` to the precedence stream of `updates` and waits for the state of its run.
+pub async fn step(
+    updates: &mpsc::Sender<Update>,
+    command: StepCommand,
+) -> Result<StepState, StepError> {
+    let (reply, state) = oneshot::channel();
+    if updates.send(Update::Step(command, reply)).await.is_err() {
+        return Err(StepError::NoRun);
+    }
+    state.await.unwrap_or(Err(StepError::NoRun))
+}
+
+const PROMPT_HELP: &str = "\
+commands:
+  <enter>, next   release the oldest held notification
+  <n>             release held notification <n>
+  show            show what is held and blocked
+  continue        release everything and stop stepping
+  quit            stop prompting; notifications stay held
+";
+
+/// Lets a person step through the run of `updates` by typing commands into `input`. Returns when
+/// the person quits, `input` ends, or the run stops stepping.
+pub async fn prompt<I, O>(
+    updates: &mpsc::Sender<Update>,
+    input: I,
+    mut output: O,
+) -> std::io::Result<()>
+where
+    I: AsyncBufRead + Unpin,
+    O: AsyncWrite + Unpin,
+{
+    let mut lines = input.lines();
+    output.write_all(PROMPT_HELP.as_bytes()).await?;
+    let mut command = StepCommand::Show;
+    loop {
+        match step(updates, command).await {
+            Ok(state) => output.write_all(state.to_string().as_bytes()).await?,
+            Err(e) => {
+                output.write_all(format!("{}\n", e).as_bytes()).await?;
+                if matches!(e, StepError::NoRun | StepError::NotStepping) {
+                    return Ok(());
+                }
+            }
+        }
+        if command == StepCommand::Continue {
+            return Ok(());
+        }
+        command = loop {
+            output.write_all(b"step> ").await?;
+            output.flush().await?;
+            let Some(line) = lines.next_line().await? else {
+                return Ok(());
+            };
+            match line.trim() {
+                "" | "n" | "next" => break StepCommand::Release { index: 0 },
+                "s" | "show" => break StepCommand::Show,
+                "c" | "continue" => break StepCommand::Continue,
+                "q" | "quit" => return Ok(()),
+                other => match other.parse() {
+                    Ok(index) => break StepCommand::Release { index },
+                    Err(_) => output.write_all(PROMPT_HELP.as_bytes()).await?,
+                },
+            }
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use super::*;
+    use crate::{
+        client::BlockingClient,
+        control::Request,
+        server::{run_in_memory, Update},
+        FederateId, Precedence,
+    };
+
+    /// Shows the state of the run until `done` holds for it.
+    async fn show_until(
+        updates: &mpsc::Sender<Update>,
+        done: impl Fn(&StepState) -> bool,
+    ) -> StepState {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let state = step(updates, StepCommand::Show).await.unwrap();
+            if done(&state) {
+                return state;
+            }
+            assert!(Instant::now() < deadline, "gave up waiting:\n{}", state);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_notifications_are_held_until_released() {
+        let scratch_dir = crate::test_scratch_dir("step");
+        let (mut server_handle, connector) = run_in_memory(1).await;
+        let (updates, acks, reports) = &mut server_handle.updates_acks[0];
+        assert_eq!(
+            step(updates, StepCommand::Show).await,
+            Err(StepError::NoRun)
+        );
+        let mut precedence = Precedence::from_list(
+            2,
+            &[
+                (("A", 0, 0), &[("waits for A", 1, 0)]),
+                (("B", 0, 0), &[("waits for B", 1, 0)]),
+            ],
+            scratch_dir.clone(),
+            0,
+        );
+        precedence.step = true;
+        updates.send(Update::Start(precedence)).await.unwrap();
+        let evars = acks.recv().await.unwrap().unwrap();
+        let start = |fedid| {
+            BlockingClient::start_in_memory(
+                &connector,
+                &evars,
+                FederateId(fedid),
+                Duration::from_secs(10),
+            )
+        };
+        let (notifier, notifier_join_handle) = start(0);
+        let (waiter, waiter_join_handle) = start(1);
+        let notifier = std::thread::spawn(move || {
+            notifier.tracepoint_maybe_notify(HookInvocation::from_short(("A", 0, 0)));
+            notifier.tracepoint_maybe_notify(HookInvocation::from_short(("B", 0, 0)));
+            notifier.halt.send(()).unwrap();
+            drop(notifier);
+            notifier_join_handle.join().unwrap();
+        });
+        let (order_sender, mut order) = mpsc::unbounded_channel();
+        let waiter = std::thread::spawn(move || {
+            let waiter = Arc::new(waiter);
+            let threads: Vec<_> = ["waits for A", "waits for B"]
+                .into_iter()
+                .map(|hid| {
+                    let waiter = Arc::clone(&waiter);
+                    let order_sender = order_sender.clone();
+                    std::thread::spawn(move || {
+                        waiter.tracepoint_maybe_wait(HookInvocation::from_short((hid, 1, 0)));
+                        order_sender.send(hid).unwrap();
+                    })
+                })
+                .collect();
+            for thread in threads {
+                thread.join().unwrap();
+            }
+            let waiter = Arc::into_inner(waiter).unwrap();
+            waiter.halt.send(()).unwrap();
+            drop(waiter);
+            waiter_join_handle.join().unwrap();
+        });
+        let state = show_until(updates, |state| {
+            state.held.len() == 2 && state.blocked.len() == 2
+        })
+        .await;
+        assert_eq!(
+            state.held,
+            vec![
+                HookInvocation::from_short(("A", 0, 0)),
+                HookInvocation::from_short(("B", 0, 0))
+            ]
+        );
+        assert_eq!(
+            step(updates, StepCommand::Release { index: 2 }).await,
+            Err(StepError::NoSuchNotification {
+                index: 2,
+                n_held: 2
+            })
+        );
+        // Releasing B first lets its waiter go first, although A was notified first.
+        let state = step(updates, StepCommand::Release { index: 1 })
+            .await
+            .unwrap();
+        assert_eq!(state.held, vec![HookInvocation::from_short(("A", 0, 0))]);
+        show_until(updates, |state| state.blocked.len() == 1).await;
+        assert_eq!(order.recv().await.unwrap(), "waits for B");
+        let mut output = vec![];
+        prompt(updates, &b"continue\n"[..], &mut output)
+            .await
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("[0] A"), "{}", output);
+        assert!(output.contains("federate 1: waits for A"), "{}", output);
+        assert_eq!(order.recv().await.unwrap(), "waits for A");
+        tokio::task::spawn_blocking(move || {
+            notifier.join().unwrap();
+            waiter.join().unwrap();
+        })
+        .await
+        .unwrap();
+        updates.send(Update::Finish).await.unwrap();
+        let report = reports.recv().await.unwrap();
+        assert!(report.is_clean(), "{}", report);
+        assert!(report.timeouts.is_empty(), "{}", report);
+        updates.send(Update::Halt).await.unwrap();
+        server_handle.join().await;
+
+        let request: Request =
+            serde_json::from_str(r#"{"request":"step","run":3,"command":"release","index":1}"#)
+                .unwrap();
+        assert!(matches!(
+            request,
+            Request::Step {
+                run: 3,
+                command: StepCommand::Release { index: 1 }
+            }
+        ));
+        std::fs::remove_dir_all(scratch_dir).unwrap();
+    }
+}
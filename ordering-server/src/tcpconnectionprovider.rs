@@ -1,105 +1,341 @@
-use std::os::fd::{IntoRawFd, RawFd};
+use std::{
+    collections::HashMap,
+    io,
+    os::fd::{IntoRawFd, RawFd},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
-use log::{debug, info, warn};
-use tokio::{net::TcpListener, sync::mpsc};
+use log::{debug, error, info, warn};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{unix, TcpListener},
+    sync::{mpsc, watch},
+    task::JoinSet,
+};
 
-use crate::channel_vec;
 use crate::connection::UNIX_CONNECTION_MANAGEMENT;
-use crate::{connection::Connection, FederateId, RunId};
+use crate::connection::{ConnectionManagement, FrameError};
+use crate::protocol::FrameKind;
+use crate::{connection::Connection, FederateId, Frame, RunId};
 
-pub type ConnectionElt<R, W> = (Connection<R, W>, FederateId, RunId);
-pub type TcpConnectionElt =
-    ConnectionElt<tokio::net::tcp::OwnedReadHalf, tokio::net::tcp::OwnedWriteHalf>;
-pub type UnixConnectionElt = (RawFd, FederateId, RunId);
+/// How long a pooled connection may go unused before it is closed.
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
 
+/// A connection that has completed its handshake, handed off as a raw file descriptor together
+/// with the federate and the run that it claims to belong to and the process id of the client.
+pub type TcpConnectionElt = (RawFd, FederateId, RunId, u32);
+pub type UnixConnectionElt = (RawFd, FederateId, RunId, u32);
+
+/// The precedence streams that are currently being served, by precedence id. Streams are added and
+/// removed while the listener runs.
+pub type ConnectionRoutes = Arc<Mutex<HashMap<u32, mpsc::Sender<TcpConnectionElt>>>>;
+
+/// Listens for TCP connections on `port` of `bind_host` (an ephemeral port if `port` is 0) and
+/// forwards each connection to the precedence stream in `routes` that is named by the precedence id
+/// of its handshake. Returns the port that is actually being listened on and the handle of the
+/// listening task, which must be aborted when the server shuts down.
 pub async fn forwarding(
+    bind_host: &str,
     port: u16,
-    n_connection_streams: usize,
-) -> Vec<mpsc::Receiver<TcpConnectionElt>> {
-    let (senders, receivers) = channel_vec(n_connection_streams);
-    let listener = TcpListener::bind(("127.0.0.1", port)).await.unwrap();
-    let _drop_me = tokio::spawn(forward_tcp_connections(listener, senders, port));
-    receivers
+    routes: ConnectionRoutes,
+) -> io::Result<(u16, tokio::task::JoinHandle<()>)> {
+    let listener = TcpListener::bind((bind_host, port)).await?;
+    let port = listener.local_addr()?.port();
+    let listener_handle = tokio::spawn(forward_tcp_connections(listener, routes, port));
+    Ok((port, listener_handle))
 }
 
-async fn forward_tcp_connections(
-    listener: TcpListener,
-    connection_senders: Vec<mpsc::Sender<TcpConnectionElt>>,
-    port: u16,
-) {
+async fn forward_tcp_connections(listener: TcpListener, routes: ConnectionRoutes, port: u16) {
     loop {
         debug!("Listening for connections on port {}", port);
-        let mut connection = Connection::new(listener.accept().await.unwrap().0.into_split());
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                warn!("Failed to accept connection: {}", e);
+                continue;
+            }
+        };
         info!("Accepted connection");
-        let frame = connection.read_frame().await;
-        match frame {
-            Some(frame) => {
-                debug!("Received initial frame: {:?}", frame);
-                if frame.hook_id[0] != b'S' {
-                    eprintln!(
-                        "Received frame with hook_id {:?} instead of 'S'",
-                        frame.hook_id
+        let routes = routes.clone();
+        // Handshakes are done concurrently so that one slow client cannot hold up the others.
+        tokio::spawn(async move {
+            if let Some((connection, hello)) = handshake(Connection::new(stream.into_split())).await
+            {
+                let Some(connection_sender) =
+                    routes.lock().unwrap().get(&hello.precedence_id).cloned()
+                else {
+                    error!(
+                        "Received handshake for precedence id {}, which is not being served",
+                        hello.precedence_id
                     );
-                    connection.close().await;
-                    eprintln!("Forcibly closed connection; client should crash.");
-                    continue;
-                }
+                    return;
+                };
+                let (r, w) = connection.into_split();
+                let raw_fd = match r.stream.reunite(w.stream.into_inner()) {
+                    Ok(stream) => match stream.into_std() {
+                        Ok(std) => std.into_raw_fd(),
+                        Err(e) => {
+                            error!("Failed to hand off connection: {}", e);
+                            return;
+                        }
+                    },
+                    Err(e) => {
+                        error!("Failed to hand off connection: {}", e);
+                        return;
+                    }
+                };
                 debug!("Sending connection to client-specific thread");
-                if connection_senders[frame.precedence_id as usize]
+                if connection_sender
                     .send((
-                        connection,
-                        FederateId(frame.federate_id),
-                        RunId(frame.run_id),
+                        raw_fd,
+                        FederateId(hello.federate_id),
+                        RunId(hello.run_id),
+                        hello.pid(),
                     ))
                     .await
                     .is_err()
                 {
-                    debug!("Connection receiver dropped; closing channel.");
-                    break;
+                    debug!("Connection receiver dropped; closing connection.");
+                    unsafe {
+                        libc::close(raw_fd);
+                    }
                 }
             }
-            None => {
-                eprintln!("A client disconnected without sending a frame");
+        });
+    }
+}
+
+/// Answers the hello frame of a newly accepted connection. Returns the connection together with its
+/// hello frame.
+pub(crate) async fn handshake<R, W>(
+    mut connection: Connection<R, W>,
+) -> Option<(Connection<R, W>, Frame)>
+where
+    R: AsyncReadExt + Unpin,
+    W: AsyncWriteExt + Unpin,
+{
+    match connection.read_frame_exact().await {
+        Ok(Some(frame)) => {
+            debug!("Received initial frame: {:?}", frame);
+            if frame.kind != FrameKind::Hello {
+                eprintln!("Received {:?} frame instead of a hello frame", frame.kind);
+                connection.close().await;
+                eprintln!("Forcibly closed connection; client should crash.");
+                return None;
+            }
+            if let Err(e) = connection
+                .write_frame(&Frame::hello(
+                    frame.precedence_id,
+                    FederateId(frame.federate_id),
+                    frame.run_id,
+                ))
+                .await
+            {
+                eprintln!("Failed to acknowledge handshake: {}", e);
+                return None;
             }
+            Some((connection, frame))
         }
+        Ok(None) => {
+            eprintln!("A client disconnected without sending a frame");
+            None
+        }
+        Err(e) => {
+            reject_handshake(&mut connection, &e).await;
+            connection.close().await;
+            None
+        }
+    }
+}
+
+/// Tells a client whose first frame could not be understood which protocol version this server
+/// speaks, so that the client can report the mismatch instead of waiting forever.
+async fn reject_handshake<R, W>(connection: &mut Connection<R, W>, e: &FrameError)
+where
+    R: AsyncReadExt + Unpin,
+    W: AsyncWriteExt + Unpin,
+{
+    error!("Rejecting connection because its handshake failed: {}", e);
+    if let FrameError::Protocol(_) = e {
+        // If the peer is gone there is nobody to tell.
+        let _ = connection
+            .write_frame(&Frame::hello(0, FederateId(0), 0))
+            .await;
+    }
+}
+
+/// How the connections of a [`ConnectionPool`] are made, borrowed, and closed.
+pub struct ReusableTransport<R, W>
+where
+    R: AsyncReadExt + Unpin,
+    W: AsyncWriteExt + Unpin,
+{
+    pub management: ConnectionManagement<R, W>,
+    /// Makes a connection whose ends are inherited by child processes, and returns its server end
+    /// and its client end.
+    pub pair: fn() -> (RawFd, RawFd),
+    /// Closes both ends of a connection that was made by `pair`.
+    pub close: unsafe fn(RawFd, RawFd) -> (io::Result<()>, io::Result<()>),
+}
+
+impl<R, W> Clone for ReusableTransport<R, W>
+where
+    R: AsyncReadExt + Unpin,
+    W: AsyncWriteExt + Unpin,
+{
+    fn clone(&self) -> Self {
+        *self
     }
 }
 
-pub fn reusing(
-    n_connection_streams: usize,
-    max_n_simultaneous_connections: usize,
-    connection_requests: Vec<mpsc::Receiver<(usize, RunId)>>,
-    granted_connections: Vec<mpsc::Sender<Vec<RawFd>>>,
+impl<R, W> Copy for ReusableTransport<R, W>
+where
+    R: AsyncReadExt + Unpin,
+    W: AsyncWriteExt + Unpin,
+{
+}
+
+/// Unix socket pairs, as used by [`crate::server::run_reusing_connections`].
+pub const UNIX_REUSABLE_TRANSPORT: ReusableTransport<unix::OwnedReadHalf, unix::OwnedWriteHalf> =
+    ReusableTransport {
+        management: UNIX_CONNECTION_MANAGEMENT,
+        pair: make_server_and_client_connection_pair,
+        close: |server, client| (close(server), close(client)),
+    };
+
+fn close(fd: RawFd) -> io::Result<()> {
+    if unsafe { libc::close(fd) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Serves the connections of a single precedence stream from a [`ConnectionPool`] of `transport`
+/// connections that starts out with `n_preallocated_connections` connections. Returns the receiver
+/// of the connections that complete their handshakes and the handle of the serving task, which
+/// ends and closes the pool once `connection_requests` is closed.
+///
+/// Each request names the federates of a run, each of which is granted a connection of the pool.
+pub fn reusing<R, W>(
+    transport: ReusableTransport<R, W>,
+    n_preallocated_connections: usize,
+    connection_requests: mpsc::Receiver<(Vec<FederateId>, RunId)>,
+    granted_connections: mpsc::Sender<Vec<(FederateId, RawFd)>>,
 ) -> (
-    Vec<mpsc::Receiver<UnixConnectionElt>>,
-    Vec<tokio::task::JoinHandle<()>>,
-) {
-    let (senders, receivers) = channel_vec(n_connection_streams);
-    let mut connection_table: Vec<Vec<(RawFd, RawFd)>> = Vec::with_capacity(n_connection_streams);
-    for _ in 0..n_connection_streams {
-        let mut connections = Vec::with_capacity(max_n_simultaneous_connections);
-        for _ in 0..max_n_simultaneous_connections {
-            let (server_connection, client_connection) = make_server_and_client_connection_pair();
-            connections.push((server_connection, client_connection));
+    mpsc::Receiver<UnixConnectionElt>,
+    tokio::task::JoinHandle<()>,
+)
+where
+    R: AsyncReadExt + Unpin + Send + 'static,
+    W: AsyncWriteExt + Unpin + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel(1);
+    let handle = tokio::spawn(reuse_tcp_connections(
+        ConnectionPool::new(transport, n_preallocated_connections),
+        sender,
+        connection_requests,
+        granted_connections,
+    ));
+    (receiver, handle)
+}
+
+/// Connections whose client ends are inherited by the processes of a run. A run that needs more
+/// connections than the pool has makes the pool grow, and connections that go unused for
+/// [`POOL_IDLE_TIMEOUT`] are closed.
+///
+/// Every run uses a prefix of the pool, so the connections are ordered from most to least recently
+/// used.
+struct ConnectionPool<R, W>
+where
+    R: AsyncReadExt + Unpin,
+    W: AsyncWriteExt + Unpin,
+{
+    transport: ReusableTransport<R, W>,
+    connections: Vec<PooledConnection>,
+}
+
+struct PooledConnection {
+    server: RawFd,
+    client: RawFd,
+    last_used: Instant,
+}
+
+impl<R, W> ConnectionPool<R, W>
+where
+    R: AsyncReadExt + Unpin,
+    W: AsyncWriteExt + Unpin,
+{
+    fn new(transport: ReusableTransport<R, W>, n_connections: usize) -> Self {
+        let mut pool = ConnectionPool {
+            transport,
+            connections: Vec::with_capacity(n_connections),
+        };
+        pool.grow(n_connections, Instant::now());
+        pool
+    }
+    fn grow(&mut self, n_connections: usize, now: Instant) {
+        while self.connections.len() < n_connections {
+            let (server, client) = (self.transport.pair)();
+            self.connections.push(PooledConnection {
+                server,
+                client,
+                last_used: now,
+            });
+        }
+    }
+    /// Returns the first `n_connections` connections of the pool, which grows if necessary.
+    fn reserve(&mut self, n_connections: usize, now: Instant) -> &mut [PooledConnection] {
+        self.grow(n_connections, now);
+        for connection in &mut self.connections[..n_connections] {
+            connection.last_used = now;
         }
-        connection_table.push(connections);
-    }
-    let mut abort_handles = Vec::with_capacity(n_connection_streams);
-    for (((connection_requestor, connection_list), connection_sender), granted_connection_sender) in
-        connection_requests
-            .into_iter()
-            .zip(connection_table.into_iter())
-            .zip(senders.into_iter())
-            .zip(granted_connections.into_iter())
+        self.shrink(n_connections, now);
+        &mut self.connections[..n_connections]
+    }
+    /// Closes the connections that have been idle for too long, except for the first `n_in_use`.
+    fn shrink(&mut self, n_in_use: usize, now: Instant) {
+        while self.connections.len() > n_in_use
+            && now.duration_since(self.connections.last().unwrap().last_used) >= POOL_IDLE_TIMEOUT
+        {
+            self.connections.pop().unwrap().close(self.transport);
+        }
+    }
+    fn len(&self) -> usize {
+        self.connections.len()
+    }
+}
+
+impl<R, W> Drop for ConnectionPool<R, W>
+where
+    R: AsyncReadExt + Unpin,
+    W: AsyncWriteExt + Unpin,
+{
+    fn drop(&mut self) {
+        for connection in self.connections.drain(..) {
+            connection.close(self.transport);
+        }
+    }
+}
+
+impl PooledConnection {
+    fn close<R, W>(self, transport: ReusableTransport<R, W>)
+    where
+        R: AsyncReadExt + Unpin,
+        W: AsyncWriteExt + Unpin,
     {
-        abort_handles.push(tokio::spawn(reuse_tcp_connections(
-            connection_list,
-            connection_sender,
-            connection_requestor,
-            granted_connection_sender,
-        )));
+        let (success_server, success_client) =
+            unsafe { (transport.close)(self.server, self.client) };
+        if success_server.is_err() || success_client.is_err() {
+            // The server end is closed by whoever drops a borrowed connection instead of giving it
+            // back, so it may already be gone.
+            debug!(
+                "Closing pooled connection ({}, {}) returned {:?} and {:?}",
+                self.server, self.client, success_server, success_client
+            );
+        }
     }
-    (receivers, abort_handles)
 }
 
 static FCNTL_MUTEX: std::sync::Mutex<()> = std::sync::Mutex::new(());
@@ -122,115 +358,225 @@ fn make_server_and_client_connection_pair() -> (RawFd, RawFd) {
     (server_connection, client_connection)
 }
 
-async fn reuse_tcp_connections(
-    mut connection_list: Vec<(RawFd, RawFd)>,
+async fn reuse_tcp_connections<R, W>(
+    mut pool: ConnectionPool<R, W>,
     connection_sender: mpsc::Sender<UnixConnectionElt>,
-    mut n_connections_receiver: mpsc::Receiver<(usize, RunId)>,
-    granted_connection_sender: mpsc::Sender<Vec<RawFd>>,
-) {
-    let mut n_connections_option = n_connections_receiver.recv().await;
-    'outer_outer: while let Some((n_connections, run_id)) = n_connections_option {
-        let mut server_connections_borrowed = Vec::with_capacity(n_connections);
-        for (server_connection, client_connection) in connection_list.iter_mut().take(n_connections)
+    mut requests: mpsc::Receiver<(Vec<FederateId>, RunId)>,
+    granted_connection_sender: mpsc::Sender<Vec<(FederateId, RawFd)>>,
+) where
+    R: AsyncReadExt + Unpin + Send + 'static,
+    W: AsyncWriteExt + Unpin + Send + 'static,
+{
+    let transport = pool.transport;
+    let mut request = next_request(&mut requests, &mut pool, 0).await;
+    'outer: while let Some((federates, run_id)) = request {
+        let n_connections = federates.len();
+        if n_connections > pool.len() {
+            info!(
+                "Growing connection pool from {} to {} connections",
+                pool.len(),
+                n_connections
+            );
+        }
+        // The handshakes are done concurrently, because the processes of a run may start in any
+        // order and at any time during the run.
+        let mut handshakes = JoinSet::new();
+        let (give_up, given_up) = watch::channel(());
+        for PooledConnection {
+            server: server_connection,
+            client: client_connection,
+            ..
+        } in pool.reserve(n_connections, Instant::now())
         {
             let mut server_connection_borrowed =
-                unsafe { (UNIX_CONNECTION_MANAGEMENT.borrow)(*server_connection) };
+                unsafe { (transport.management.borrow)(*server_connection) };
             while let Err(e) = server_connection_borrowed {
-                let (success_server, err_server, success_client, err_client) = unsafe {
-                    (
-                        libc::close(*server_connection),
-                        std::io::Error::last_os_error(),
-                        libc::close(*client_connection),
-                        std::io::Error::last_os_error(),
-                    )
-                };
+                let (success_server, success_client) =
+                    unsafe { (transport.close)(*server_connection, *client_connection) };
                 warn!(
-                    "Failed to borrow connection: {:?}. Closed the file descriptor with code {} on server side and {} on client side (errors {:?} and {:?}).",
-                    e, success_server, success_client, err_server, err_client
+                    "Failed to borrow connection: {:?}. Closing it returned {:?} on server side and {:?} on client side.",
+                    e, success_server, success_client
                 );
-                if success_server != 0 || success_client != 0 {
+                if success_server.is_err() || success_client.is_err() {
                     panic!("Failed to close file descriptor");
                 }
-                (*server_connection, *client_connection) = make_server_and_client_connection_pair();
+                (*server_connection, *client_connection) = (transport.pair)();
                 server_connection_borrowed =
-                    unsafe { (UNIX_CONNECTION_MANAGEMENT.borrow)(*server_connection) };
+                    unsafe { (transport.management.borrow)(*server_connection) };
             }
-            server_connections_borrowed
-                .push((*server_connection, server_connection_borrowed.unwrap()));
+            handshakes.spawn(pooled_handshake(
+                transport,
+                *server_connection,
+                server_connection_borrowed.unwrap(),
+                run_id,
+                given_up.clone(),
+            ));
         }
-        granted_connection_sender
+        if granted_connection_sender
             .send(
-                connection_list
+                federates
                     .iter()
-                    .take(n_connections)
-                    .map(|(_, client_connection)| client_connection)
-                    .cloned()
+                    .zip(&pool.connections)
+                    .map(|(fedid, connection)| (*fedid, connection.client))
                     .collect(),
             )
             .await
-            .unwrap();
-        'outer: for (server_connection, mut server_connection_borrowed) in
-            server_connections_borrowed
+            .is_err()
         {
-            // Connection::new(unsafe { socket_from_raw_fd(*server_connection) });
-            loop {
-                tokio::select! {
-                    frame = server_connection_borrowed.read_frame() => {
-                        match frame {
-                            Some(frame) => {
-                                debug!("Received initial frame: {:?}", frame);
-                                if frame.hook_id[0] != b'S' {
-                                    // eprintln!(
-                                    //     "Received frame with hook_id {:?} instead of 'S'",
-                                    //     frame.hook_id
-                                    // );
-                                    // server_connection_borrowed.close().await;
-                                    // eprintln!("Forcibly closed connection; client should crash.");
-                                    // continue;
-                                    warn!(
-                                    "Expected initial frame to have hook_id 'S', but got {:?}. This is not strictly an error condition because it is possible for frames from prior runs to be received by the server.",
-                                    frame.hook_id
-                                );
-                                    continue;
-                                }
-                                if frame.run_id != run_id.0 {
-                                    warn!("Received frame with run_id {} but expected {}", frame.run_id, run_id.0);
-                                    continue;
-                                }
-                                unsafe {
-                                    (UNIX_CONNECTION_MANAGEMENT.unborrow)(
-                                        server_connection_borrowed.into_split(),
-                                    );
-                                }
-                                // let second_frame = server_connection.read_frame().await; // DEBUG
-                                // debug!("Second frame: {:?}", second_frame); // DEBUG
-                                if connection_sender
-                                    .send((
-                                        server_connection,
-                                        FederateId(frame.federate_id),
-                                        RunId(frame.run_id),
-                                    ))
-                                    .await
-                                    .is_err()
-                                {
-                                    debug!("Connection sender dropped; closing channel.");
-                                    break 'outer;
-                                }
-                                break;
-                            }
-                            None => {
-                                eprintln!("A client disconnected without sending a frame");
-                                break;
-                            }
-                        }
-                    }
-                    n_connections_option_next = n_connections_receiver.recv() => {
-                        n_connections_option = n_connections_option_next;
-                        continue 'outer_outer;
+            debug!("Granted connections receiver dropped; closing connection pool.");
+            give_up_on(handshakes, give_up).await;
+            break;
+        }
+        loop {
+            if handshakes.is_empty() {
+                request = next_request(&mut requests, &mut pool, n_connections).await;
+                continue 'outer;
+            }
+            tokio::select! {
+                Some(handshake) = handshakes.join_next() => {
+                    let Some(connection) = handshake.unwrap() else {
+                        continue;
+                    };
+                    if connection_sender.send(connection).await.is_err() {
+                        debug!("Connection sender dropped; closing channel.");
+                        give_up_on(handshakes, give_up).await;
+                        break 'outer;
                     }
                 }
+                next_request = requests.recv() => {
+                    // The connections that have not completed their handshakes are given up on.
+                    give_up_on(handshakes, give_up).await;
+                    request = next_request;
+                    continue 'outer;
+                }
             }
         }
-        n_connections_option = n_connections_receiver.recv().await;
+    }
+}
+
+/// Stops the handshakes that are still in progress and waits until they have given their
+/// connections back, so that the pool does not close a connection that a handshake still owns.
+async fn give_up_on(
+    mut handshakes: JoinSet<Option<UnixConnectionElt>>,
+    give_up: watch::Sender<()>,
+) {
+    drop(give_up);
+    while handshakes.join_next().await.is_some() {}
+}
+
+/// Waits for the hello frame of the run `run_id` on a pooled connection, acknowledges it, and gives
+/// the connection back so that it can be handed to the precedence stream. Gives the connection back
+/// without a handshake once `given_up` is closed.
+async fn pooled_handshake<R, W>(
+    transport: ReusableTransport<R, W>,
+    server_connection: RawFd,
+    mut server_connection_borrowed: Connection<R, W>,
+    run_id: RunId,
+    mut given_up: watch::Receiver<()>,
+) -> Option<UnixConnectionElt>
+where
+    R: AsyncReadExt + Unpin,
+    W: AsyncWriteExt + Unpin,
+{
+    loop {
+        let frame = tokio::select! {
+            frame = server_connection_borrowed.read_frame_exact() => frame,
+            _ = given_up.changed() => {
+                unsafe {
+                    (transport.management.unborrow)(server_connection_borrowed.into_split());
+                }
+                return None;
+            }
+        };
+        match frame {
+            Ok(Some(frame)) => {
+                debug!("Received initial frame: {:?}", frame);
+                if frame.kind != FrameKind::Hello {
+                    warn!(
+                        "Expected initial frame to be a hello frame, but got {:?}. This is not strictly an error condition because it is possible for frames from prior runs to be received by the server.",
+                        frame
+                    );
+                    continue;
+                }
+                if frame.run_id != run_id.0 {
+                    warn!(
+                        "Received frame with run_id {} but expected {}",
+                        frame.run_id, run_id.0
+                    );
+                    continue;
+                }
+                if let Err(e) = server_connection_borrowed
+                    .write_frame(&Frame::hello(
+                        frame.precedence_id,
+                        FederateId(frame.federate_id),
+                        frame.run_id,
+                    ))
+                    .await
+                {
+                    warn!("Failed to acknowledge handshake: {}", e);
+                    return None;
+                }
+                unsafe {
+                    (transport.management.unborrow)(server_connection_borrowed.into_split());
+                }
+                return Some((
+                    server_connection,
+                    FederateId(frame.federate_id),
+                    RunId(frame.run_id),
+                    frame.pid(),
+                ));
+            }
+            Ok(None) => {
+                eprintln!("A client disconnected without sending a frame");
+                return None;
+            }
+            Err(e) => {
+                reject_handshake(&mut server_connection_borrowed, &e).await;
+                return None;
+            }
+        }
+    }
+}
+
+/// Waits for the next request for connections, closing connections that go unused in the meantime.
+/// The first `n_in_use` connections of the pool may still be in use by the current run, so they
+/// are kept.
+async fn next_request<R, W>(
+    requests: &mut mpsc::Receiver<(Vec<FederateId>, RunId)>,
+    pool: &mut ConnectionPool<R, W>,
+    n_in_use: usize,
+) -> Option<(Vec<FederateId>, RunId)>
+where
+    R: AsyncReadExt + Unpin,
+    W: AsyncWriteExt + Unpin,
+{
+    loop {
+        match tokio::time::timeout(POOL_IDLE_TIMEOUT, requests.recv()).await {
+            Ok(request) => return request,
+            Err(_) => pool.shrink(n_in_use, Instant::now()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pool_grows_on_demand_and_shrinks_when_idle() {
+        let t0 = Instant::now();
+        let mut pool = ConnectionPool::new(UNIX_REUSABLE_TRANSPORT, 2);
+        assert_eq!(pool.reserve(5, t0).len(), 5);
+        assert_eq!(pool.len(), 5);
+        let t1 = t0 + POOL_IDLE_TIMEOUT / 2;
+        pool.reserve(3, t1);
+        // Nothing has been idle for long enough yet.
+        assert_eq!(pool.len(), 5);
+        let t2 = t0 + POOL_IDLE_TIMEOUT;
+        pool.reserve(1, t2);
+        assert_eq!(pool.len(), 3);
+        // The connections of a run that is still going on are never closed.
+        pool.shrink(1, t2 + POOL_IDLE_TIMEOUT * 2);
+        assert_eq!(pool.len(), 1);
     }
 }
@@ -0,0 +1,174 @@
+//! Tracepoints that several threads of a process invoke.
+//!
+//! A [`HookInvocation`] tells the invocations of a hook apart by how many times the hook has been
+//! invoked before, which is a race when threads invoke the same hook concurrently, and which says
+//! nothing about the thread that made the invocation. A thread may name itself with
+//! [`set_thread_namespace`], after which
+//! [`BlockingClient::next_invocation`](crate::client::BlockingClient::next_invocation) counts the
+//! invocations of each hook by that thread on their own, under the hook id that
+//! [`HookId::in_thread`] gives. A precedence refers to an invocation of a particular thread by that
+//! hook id, so that "the second `send` of the thread `worker-1`" is `send/worker-1` with sequence
+//! number 1.
+//!
+//! The invocations of threads without a namespace share the count of their hook. Their sequence
+//! numbers are assigned atomically, in the order in which the threads get to the tracepoint, so
+//! they depend on scheduling; the numbers that were assigned can be seen in the interleaving of a
+//! run that records it.
+
+use std::{cell::RefCell, collections::HashMap, sync::Mutex};
+
+use crate::{HookId, HookInvocation, SequenceNumberByFileAndLine};
+
+thread_local! {
+    static NAMESPACE: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Puts the invocations that the calling thread makes from now on in `namespace`, or takes them
+/// out of any namespace if it is `None`. Threads should use distinct namespaces.
+pub fn set_thread_namespace(namespace: Option<String>) {
+    NAMESPACE.with(|current| *current.borrow_mut() = namespace);
+}
+
+pub fn thread_namespace() -> Option<String> {
+    NAMESPACE.with(|current| current.borrow().clone())
+}
+
+/// The number of invocations of each hook so far.
+#[derive(Debug, Default)]
+pub(crate) struct SequenceNumbers(Mutex<HashMap<HookId, u32>>);
+
+impl SequenceNumbers {
+    /// The next invocation of `hid` by the calling thread.
+    pub(crate) fn next(&self, hid: HookId) -> HookInvocation {
+        let hid = match thread_namespace() {
+            Some(namespace) => hid.in_thread(&namespace),
+            None => hid,
+        };
+        let mut counts = self.0.lock().unwrap();
+        let count = counts.entry(hid.clone()).or_insert(0);
+        let seqnum = SequenceNumberByFileAndLine(*count);
+        *count += 1;
+        HookInvocation { hid, seqnum }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::{
+        client::BlockingClient,
+        server::{run_in_memory, Update},
+        FederateId, Precedence,
+    };
+
+    #[test]
+    fn test_sequence_numbers_are_assigned_once_per_namespace() {
+        let sequence_numbers = Arc::new(SequenceNumbers::default());
+        let hid = HookId::new("work".into(), FederateId(0));
+        let threads: Vec<_> = [None, None, Some("a"), Some("b")]
+            .into_iter()
+            .map(|namespace| {
+                let sequence_numbers = Arc::clone(&sequence_numbers);
+                let hid = hid.clone();
+                std::thread::spawn(move || {
+                    set_thread_namespace(namespace.map(String::from));
+                    (0..100)
+                        .map(|_| sequence_numbers.next(hid.clone()))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        let mut invocations: Vec<_> = threads
+            .into_iter()
+            .flat_map(|thread| thread.join().unwrap())
+            .collect();
+        invocations.sort_by_key(|invocation| (invocation.hid.to_string(), invocation.seqnum));
+        let expected: Vec<_> = [("work", 200), ("work/a", 100), ("work/b", 100)]
+            .into_iter()
+            .flat_map(|(hid, n)| {
+                (0..n).map(move |seqnum| HookInvocation::from_short((hid, 0, seqnum)))
+            })
+            .collect();
+        assert_eq!(invocations, expected);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_precedences_order_the_invocations_of_particular_threads() {
+        let scratch_dir = crate::test_scratch_dir("threads");
+        let (mut server_handle, connector) = run_in_memory(1).await;
+        let (updates, acks, reports) = &mut server_handle.updates_acks[0];
+        // Both threads of federate 0 invoke "work" twice. The first invocation of thread a waits
+        // for the second of thread b.
+        let precedence = Precedence::from_list(
+            1,
+            &[(("work/b", 0, 1), &[("work/a", 0, 0)])],
+            scratch_dir.clone(),
+            0,
+        );
+        updates.send(Update::Start(precedence)).await.unwrap();
+        let evars = acks.recv().await.unwrap().unwrap();
+        let order = tokio::task::spawn_blocking(move || {
+            let (client, join_handle) = BlockingClient::start_in_memory(
+                &connector,
+                &evars,
+                FederateId(0),
+                Duration::from_secs(10),
+            );
+            let client = Arc::new(client);
+            let order = Arc::new(Mutex::new(vec![]));
+            let threads: Vec<_> = ["a", "b"]
+                .into_iter()
+                .map(|namespace| {
+                    let client = Arc::clone(&client);
+                    let order = Arc::clone(&order);
+                    std::thread::spawn(move || {
+                        set_thread_namespace(Some(namespace.into()));
+                        if namespace == "b" {
+                            // Give thread a the chance to go first if it is not held back.
+                            std::thread::sleep(Duration::from_millis(50));
+                        }
+                        for _ in 0..2 {
+                            let invocation =
+                                client.next_invocation(HookId::new("work".into(), FederateId(0)));
+                            // Recorded between the wait and the notification, so that an
+                            // invocation that is released is recorded after the one it waited for.
+                            client.tracepoint_maybe_wait(invocation.clone());
+                            order.lock().unwrap().push(invocation.to_string());
+                            client.tracepoint_maybe_notify(invocation);
+                        }
+                    })
+                })
+                .collect();
+            for thread in threads {
+                thread.join().unwrap();
+            }
+            let client = Arc::into_inner(client).unwrap();
+            client.halt.send(()).unwrap();
+            drop(client);
+            join_handle.join().unwrap();
+            Arc::into_inner(order).unwrap().into_inner().unwrap()
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            order[..3],
+            [
+                "work/b[0]@federate0",
+                "work/b[1]@federate0",
+                "work/a[0]@federate0"
+            ]
+        );
+        updates.send(Update::Finish).await.unwrap();
+        let report = reports.recv().await.unwrap();
+        assert!(report.is_clean(), "{}", report);
+        assert!(report.timeouts.is_empty(), "{}", report);
+        updates.send(Update::Halt).await.unwrap();
+        server_handle.join().await;
+        std::fs::remove_dir_all(scratch_dir).unwrap();
+    }
+}
@@ -0,0 +1,284 @@
+//! An optional record of when each precedence was enforced during a run.
+//!
+//! When [`Precedence::record_timeline`] is set, the server and every client keep a [`Recorder`].
+//! Each client writes its events to its own file in the scratch dir of the run when it shuts down,
+//! and when the run ends the server merges them with its own events into a single timeline, which
+//! is written next to `precedences.ord`.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    attributes::Attributes, pattern::HookInvocationPattern, FederateId, HookId, HookInvocation,
+};
+
+pub const TIMELINE_FILE_NAME: &str = "timeline.ord";
+const CLIENT_TIMELINE_FILE_PREFIX: &str = "timeline-";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EventKind {
+    /// A client sent a notification to the server.
+    Notify(HookInvocation),
+    /// The server forwarded a notification to the federate of one of its waiters.
+    Forward {
+        notification: HookInvocation,
+        waiter: HookInvocation,
+    },
+    /// Like `Forward`, but for a waiter of a pattern edge.
+    ForwardToPattern {
+        notification: HookInvocation,
+        waiter: HookInvocationPattern,
+    },
+    /// A waiting hook invocation was allowed to proceed.
+    WakeUp {
+        hook_invocation: HookInvocation,
+        waited: Duration,
+    },
+    /// A hook invocation was held back by a [`crate::delay::Delay`].
+    Delayed {
+        hook_invocation: HookInvocation,
+        delay: Duration,
+    },
+    /// A hook invocation gave up waiting.
+    Timeout {
+        hook_invocation: HookInvocation,
+        waited: Duration,
+    },
+    /// A notification reached a waiter that had already timed out.
+    LateNotification {
+        notification: HookInvocation,
+        waiter: HookInvocation,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Event {
+    /// Wall-clock time, so that events recorded by different processes can be interleaved.
+    pub time: SystemTime,
+    /// The federate that recorded the event, or `None` for the server.
+    pub recorded_by: Option<FederateId>,
+    pub kind: EventKind,
+}
+
+/// How much the waits at one hook delayed execution during a run.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WaitStats {
+    pub n_waits: u32,
+    pub n_timeouts: u32,
+    pub total_wait: Duration,
+    pub max_wait: Duration,
+}
+
+#[derive(Debug)]
+pub struct Recorder {
+    recorded_by: Option<FederateId>,
+    events: Mutex<Vec<Event>>,
+    /// The hook invocations that have given up, with the attributes that they reported.
+    timed_out: Mutex<HashMap<HookInvocation, Attributes>>,
+}
+
+impl Recorder {
+    pub fn new(recorded_by: Option<FederateId>) -> Self {
+        Self {
+            recorded_by,
+            events: Mutex::new(vec![]),
+            timed_out: Mutex::new(HashMap::new()),
+        }
+    }
+    pub fn record(&self, kind: EventKind) {
+        let event = Event {
+            time: SystemTime::now(),
+            recorded_by: self.recorded_by,
+            kind,
+        };
+        self.events.lock().unwrap().push(event);
+    }
+    pub fn wake_up(&self, hook_invocation: HookInvocation, waited: Duration) {
+        self.record(EventKind::WakeUp {
+            hook_invocation,
+            waited,
+        });
+    }
+    pub fn timeout(
+        &self,
+        hook_invocation: HookInvocation,
+        attributes: Attributes,
+        waited: Duration,
+    ) {
+        self.timed_out
+            .lock()
+            .unwrap()
+            .insert(hook_invocation.clone(), attributes);
+        self.record(EventKind::Timeout {
+            hook_invocation,
+            waited,
+        });
+    }
+    /// Records that `notification` has arrived for `waiter`, if `waiter` has already given up.
+    pub fn arrived(&self, notification: &HookInvocation, waiter: &HookInvocation) {
+        if self.timed_out.lock().unwrap().contains_key(waiter) {
+            self.record(EventKind::LateNotification {
+                notification: notification.clone(),
+                waiter: waiter.clone(),
+            });
+        }
+    }
+    /// Like [`Recorder::arrived`], but for every invocation matching `waiter` that has given up.
+    pub fn arrived_for_pattern(
+        &self,
+        notification: &HookInvocation,
+        waiter: &HookInvocationPattern,
+    ) {
+        let late: Vec<_> = self
+            .timed_out
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(hook_invocation, attributes)| waiter.matches(hook_invocation, attributes))
+            .map(|(hook_invocation, _)| hook_invocation.clone())
+            .collect();
+        for hook_invocation in late {
+            self.record(EventKind::LateNotification {
+                notification: notification.clone(),
+                waiter: hook_invocation,
+            });
+        }
+    }
+    pub fn take_events(&self) -> Vec<Event> {
+        std::mem::take(&mut *self.events.lock().unwrap())
+    }
+    /// Writes the events recorded so far to the file from which [`merge`] will pick them up.
+    pub fn save(&self, scratch_dir: &Path) {
+        let Some(fedid) = self.recorded_by else {
+            panic!("only clients save their timelines; the server merges them");
+        };
+        let path = scratch_dir.join(format!("{}{}.ord", CLIENT_TIMELINE_FILE_PREFIX, fedid.0));
+        if let Err(e) = std::fs::write(&path, rmp_serde::to_vec(&self.take_events()).unwrap()) {
+            warn!("Failed to write timeline to {:?}: {}", path, e);
+        }
+    }
+}
+
+/// Merges the timelines saved by the clients with `server_events` into a single timeline ordered by
+/// time, which replaces the timelines of the clients in `scratch_dir`. Returns the path of the
+/// merged timeline and the merged events.
+pub async fn merge(scratch_dir: &Path, server_events: Vec<Event>) -> (PathBuf, Vec<Event>) {
+    let mut events = server_events;
+    match tokio::fs::read_dir(scratch_dir).await {
+        Ok(mut entries) => {
+            while let Ok(Some(entry)) = entries.next_entry().await {
+                let is_client_timeline = entry
+                    .file_name()
+                    .to_str()
+                    .is_some_and(|name| name.starts_with(CLIENT_TIMELINE_FILE_PREFIX));
+                if !is_client_timeline {
+                    continue;
+                }
+                match tokio::fs::read(entry.path()).await.map(|bytes| {
+                    rmp_serde::from_slice::<Vec<Event>>(&bytes).map_err(|e| e.to_string())
+                }) {
+                    Ok(Ok(client_events)) => events.extend(client_events),
+                    Ok(Err(e)) => warn!("Failed to parse timeline {:?}: {}", entry.path(), e),
+                    Err(e) => warn!("Failed to read timeline {:?}: {}", entry.path(), e),
+                }
+                let _ = tokio::fs::remove_file(entry.path()).await;
+            }
+        }
+        Err(e) => warn!(
+            "Failed to read scratch dir {:?}; the timelines of the clients are lost: {}",
+            scratch_dir, e
+        ),
+    }
+    events.sort_by_key(|event| event.time);
+    let path = scratch_dir.join(TIMELINE_FILE_NAME);
+    if let Err(e) = tokio::fs::write(&path, rmp_serde::to_vec(&events).unwrap()).await {
+        warn!("Failed to write timeline to {:?}: {}", path, e);
+    }
+    (path, events)
+}
+
+pub fn wait_stats(events: &[Event]) -> HashMap<HookId, WaitStats> {
+    let mut ret: HashMap<HookId, WaitStats> = HashMap::new();
+    for event in events {
+        let (hook_invocation, waited, timed_out) = match &event.kind {
+            EventKind::WakeUp {
+                hook_invocation,
+                waited,
+            } => (hook_invocation, *waited, false),
+            EventKind::Timeout {
+                hook_invocation,
+                waited,
+            } => (hook_invocation, *waited, true),
+            _ => continue,
+        };
+        let stats = ret.entry(hook_invocation.hid.clone()).or_default();
+        stats.n_waits += 1;
+        stats.n_timeouts += timed_out as u32;
+        stats.total_wait += waited;
+        stats.max_wait = stats.max_wait.max(waited);
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_merge_keeps_server_events_without_scratch_dir() {
+        let dir = std::env::temp_dir().join(format!("ordserv-missing-{}", std::process::id()));
+        let server = Recorder::new(None);
+        server.record(EventKind::Notify(HookInvocation::from_short(("A", 0, 0))));
+        let (_, events) = merge(&dir, server.take_events()).await;
+        assert_eq!(events.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_merge_interleaves_client_timelines() {
+        let dir = crate::test_scratch_dir("timeline");
+        let a = HookInvocation::from_short(("A", 0, 0));
+        let b = HookInvocation::from_short(("B", 1, 0));
+        let server = Recorder::new(None);
+        let client0 = Recorder::new(Some(FederateId(0)));
+        let client1 = Recorder::new(Some(FederateId(1)));
+        client0.record(EventKind::Notify(a.clone()));
+        server.record(EventKind::Forward {
+            notification: a.clone(),
+            waiter: b.clone(),
+        });
+        client1.wake_up(b.clone(), Duration::from_millis(3));
+        client1.timeout(b.clone(), Attributes::new(), Duration::from_millis(5));
+        client1.arrived(&a, &b);
+        client0.save(&dir);
+        client1.save(&dir);
+        let (path, events) = merge(&dir, server.take_events()).await;
+        assert_eq!(events.len(), 5);
+        assert!(events.windows(2).all(|pair| pair[0].time <= pair[1].time));
+        assert_eq!(
+            events
+                .iter()
+                .filter(|e| matches!(e.kind, EventKind::LateNotification { .. }))
+                .count(),
+            1
+        );
+        assert_eq!(
+            wait_stats(&events)[&b.hid],
+            WaitStats {
+                n_waits: 2,
+                n_timeouts: 1,
+                total_wait: Duration::from_millis(8),
+                max_wait: Duration::from_millis(5),
+            }
+        );
+        let saved: Vec<Event> = rmp_serde::from_slice(&std::fs::read(path).unwrap()).unwrap();
+        assert_eq!(saved, events);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
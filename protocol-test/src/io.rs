@@ -9,14 +9,17 @@ use std::{
 use csv::ReaderBuilder;
 use log::{error, warn};
 use ordering_server::{
-  server::ServerSubHandle, FederateId, HookInvocation, Precedence, RunId,
-  SequenceNumberByFileAndLine, ORDSERV_WAIT_TIMEOUT_MILLISECONDS_ENV_VAR,
+  report::RunReport,
+  server::{ServerSubHandle, Update},
+  FederateId, HookInvocation, Precedence, RunId, SequenceNumberByFileAndLine,
+  ORDSERV_WAIT_TIMEOUT_MILLISECONDS_ENV_VAR,
 };
 use rand::distributions::{Alphanumeric, DistString};
+use streaming_transpositions::OgRank;
 
 use crate::{
   env::EnvironmentUpdate,
-  exec::{ExecResult, Executable},
+  exec::{ExecResult, Executable, Status},
   state::CommitHash,
   testing::TestRuns,
   ConstraintList, HookId, HookInvocationCounts, ThreadId, TraceRecord, Traces,
@@ -329,7 +332,8 @@ pub async fn run_with_parameters(
   let unpacked = conl.to_pairs_sorted(&clr.read().unwrap().clr);
   let mut sender2waiters = HashMap::new();
   for (waiter, sender) in unpacked
-    .into_iter()
+    .iter()
+    .copied()
     .filter(|(waiter, sender)| waiter != sender)
   {
     sender2waiters
@@ -342,9 +346,38 @@ pub async fn run_with_parameters(
     n_connections: hic.n_processes,
     scratch_dir: tmp.0.clone(),
     run_id: RunId(rctx.run_id),
+    record_timeline: false,
+    pattern_edges: vec![],
+    joins: HashMap::new(),
+    delays: conl
+      .delays()
+      .iter()
+      .map(|(hinvoc, delay)| (hic.ogrank2hinvoc[hinvoc.idx()].clone(), *delay))
+      .collect(),
+    record_interleaving: false,
+    step: false,
+    hooks: vec![],
+  };
+  rctx
+    .ordserv
+    .0
+    .send(Update::Start(precedence))
+    .await
+    .unwrap();
+  let mut evars = match rctx.ordserv.1.recv().await.unwrap() {
+    Ok(evars) => evars,
+    Err(e) => {
+      return (
+        tmp,
+        Err(ExecResult {
+          status: Status::Rejected,
+          selected_output: vec![],
+          stderr: e.to_string(),
+          run_report: None,
+        }),
+      )
+    }
   };
-  rctx.ordserv.0.send(Some(precedence)).await.unwrap();
-  let mut evars = rctx.ordserv.1.recv().await.unwrap();
   evars.0.push((
     ORDSERV_WAIT_TIMEOUT_MILLISECONDS_ENV_VAR.into(),
     ORDSERV_WAIT_TIMEOUT_MILLISECONDS.into(),
@@ -353,10 +386,47 @@ pub async fn run_with_parameters(
     C_ORDERING_CLIENT_LIBRARY_PATH_ENV_VAR.into(),
     C_ORDERING_CLIENT_LIBRARY_PATH.into(),
   ));
-  let traces = get_traces(executable, &tmp, EnvironmentUpdate::new(rctx.tid, &evars.0)).await;
+  let mut traces = get_traces(executable, &tmp, EnvironmentUpdate::new(rctx.tid, &evars.0)).await;
+  rctx.ordserv.0.send(Update::Finish).await.unwrap();
+  let report = rctx.ordserv.2.recv().await.unwrap();
+  // A wait that timed out while a hook invocation was delayed may have timed out because of the
+  // delay, so it says nothing about whether the pairs can be ordered.
+  if conl.delays().is_empty() {
+    record_infeasible(hic, &unpacked, &report, &clr);
+  }
+  match &mut traces {
+    Err(e) => e.run_report = Some(report),
+    Ok(_) if !report.is_clean() => warn!("Successful run had protocol anomalies: {}", report),
+    Ok(_) => {}
+  }
   (tmp, traces)
 }
 
+fn record_infeasible(
+  hic: &HookInvocationCounts,
+  pairs: &[(OgRank, OgRank)],
+  report: &RunReport,
+  clr: &RwLock<TestRuns>,
+) {
+  if report.timeouts.is_empty() {
+    return;
+  }
+  let mut clr = clr.write().unwrap();
+  for timeout in &report.timeouts {
+    for notifier in &timeout.unsatisfied_by {
+      let pair = pairs.iter().find(|(waiter, sender)| {
+        hic.ogrank2hinvoc[waiter.idx()] == timeout.waiter
+          && hic.ogrank2hinvoc[sender.idx()] == *notifier
+      });
+      if let Some(pair) = pair {
+        if !clr.infeasible.contains(pair) {
+          clr.infeasible.push(*pair);
+        }
+      }
+    }
+  }
+}
+
 pub fn clean(scratch: &Path) {
   for entry in scratch.read_dir().expect("failed to read scratch dir") {
     let entry = entry.expect("failed to read scratch dir entry");
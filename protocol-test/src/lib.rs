@@ -7,7 +7,7 @@ pub mod testing;
 use std::{collections::HashMap, fs::File};
 
 use lf_trace_reader::TraceRecord;
-use ordering_server::{HookId, HookInvocation};
+use ordering_server::{delay::Delay, HookId, HookInvocation};
 
 use csv::Reader;
 use once_cell::sync::OnceCell;
@@ -17,6 +17,8 @@ use serde::{Deserialize, Serialize};
 use streaming_transpositions::OgRank;
 
 pub static CONCURRENCY_LIMIT: OnceCell<usize> = OnceCell::new();
+/// Whether to follow each ordering run with a run that only delays a hook invocation.
+pub static EXPLORE_DELAYS: OnceCell<bool> = OnceCell::new();
 
 const TEST_TIMEOUT_SECS: u64 = 1;
 const MAX_ERROR_LINES: usize = 20;
@@ -39,6 +41,7 @@ pub mod exec {
   };
 
   use log::{debug, error, warn};
+  use ordering_server::report::RunReport;
   use serde::{Deserialize, Serialize};
   use tokio::io::{AsyncBufReadExt, AsyncRead};
 
@@ -53,6 +56,9 @@ pub mod exec {
     Timeout,
     TerminatedBySignal,
     Termination(i32),
+    /// The ordering server found that the precedence could not be satisfied, so the executable
+    /// was never run.
+    Rejected,
   }
 
   impl Status {
@@ -61,6 +67,7 @@ pub mod exec {
         Status::Timeout => None,
         Status::TerminatedBySignal => None,
         Status::Termination(status) => Some(*status),
+        Status::Rejected => None,
       }
     }
     pub fn is_success(&self) -> bool {
@@ -68,6 +75,7 @@ pub mod exec {
         Status::Timeout => false,
         Status::TerminatedBySignal => false,
         Status::Termination(status) => *status == 0,
+        Status::Rejected => false,
       }
     }
     pub fn is_timeout(&self) -> bool {
@@ -75,6 +83,7 @@ pub mod exec {
         Status::Timeout => true,
         Status::TerminatedBySignal => false,
         Status::Termination(_) => false,
+        Status::Rejected => false,
       }
     }
     fn from_result(result: Option<std::process::ExitStatus>) -> Self {
@@ -95,6 +104,9 @@ pub mod exec {
     pub status: Status,
     pub selected_output: Vec<String>,
     pub stderr: String,
+    /// What the ordering server observed during the run, if it got far enough to be reported.
+    #[serde(default)]
+    pub run_report: Option<RunReport>,
   }
 
   impl Display for ExecResult {
@@ -102,6 +114,9 @@ pub mod exec {
       write!(f, "status: {:?}", self.status)?;
       write!(f, "\nselected output:\n{:?}", self.selected_output)?;
       write!(f, "\nstderr:\n{}\n\n", self.stderr)?;
+      if let Some(report) = &self.run_report {
+        write!(f, "ordering server report: {}\n\n", report)?;
+      }
       Ok(())
     }
   }
@@ -258,6 +273,7 @@ pub mod exec {
         status: Status::from_result(result),
         selected_output: rselected_output.recv().await.unwrap_or_default(),
         stderr: rerr.recv().await.unwrap_or_default().join("\n"),
+        run_report: None,
       }
     }
   }
@@ -418,6 +434,10 @@ pub struct ConstraintList {
   notifier_delta_idxs: [i16; DELAY_VECTOR_CHUNK_SIZE],
   parent: Option<ConstraintListIndex>,
   length: u32,
+  /// Hook invocations to delay in addition to the pairs to order. Unlike the pairs, these are not
+  /// inherited from the parent.
+  #[serde(default)]
+  delays: Vec<(OgRank, Delay)>,
 }
 
 impl ConstraintList {
@@ -431,6 +451,7 @@ impl ConstraintList {
       notifier_delta_idxs,
       parent: None,
       length,
+      delays: vec![],
     }
   }
   pub fn new_from_block(
@@ -449,8 +470,23 @@ impl ConstraintList {
       notifier_delta_idxs,
       parent: None,
       length,
+      delays: vec![],
     }
   }
+  /// A list that orders nothing and only delays `hinvoc`, so that what happens in its run can be
+  /// attributed to the delay alone.
+  pub fn delay_only(hinvoc: OgRank, delay: Delay, length: u32) -> Self {
+    Self {
+      waiter_idxs: [0; DELAY_VECTOR_CHUNK_SIZE],
+      notifier_delta_idxs: [0; DELAY_VECTOR_CHUNK_SIZE],
+      parent: None,
+      length,
+      delays: vec![(hinvoc, delay)],
+    }
+  }
+  pub fn delays(&self) -> &[(OgRank, Delay)] {
+    &self.delays
+  }
   pub fn num_of_pairs(&self, clr: &ConstraintListRegistry) -> usize {
     let mut current = Some(self);
     let mut ret = 0;
@@ -2,7 +2,7 @@ use std::path::PathBuf;
 
 use clap::Parser;
 
-use protocol_test::{state::State, CONCURRENCY_LIMIT};
+use protocol_test::{state::State, CONCURRENCY_LIMIT, EXPLORE_DELAYS};
 
 const DEFAULT_CONCURRENCY_LIMIT: usize = 400;
 
@@ -22,6 +22,10 @@ struct Cli {
 
   #[arg(short, long)]
   frequency_of_save_in_seconds: Option<u32>,
+
+  /// Follow each ordering run with a run that delays a single hook invocation.
+  #[arg(long)]
+  explore_delays: bool,
 }
 
 const DEFAULT_SCRATCH_DIR: &str = "scratch";
@@ -37,6 +41,9 @@ fn main() {
   CONCURRENCY_LIMIT
     .set(args.concurrency.unwrap_or(DEFAULT_CONCURRENCY_LIMIT))
     .expect("impossible for the limit to already be set");
+  EXPLORE_DELAYS
+    .set(args.explore_delays)
+    .expect("impossible for the flag to already be set");
   std::fs::create_dir_all(&scratch_dir).expect("failed to create scratch dir");
   let mut state = State::load(args.src_dir, scratch_dir);
   let save_interval = args
@@ -95,6 +95,11 @@ impl TestId {
     let hash128 = u128::from_le_bytes(hash_array);
     Self(hash128)
   }
+  /// A seed for the `iteration`th delay run of this test.
+  pub fn delay_seed(&self, iteration: u32) -> u64 {
+    let folded = (self.0 as u64) ^ ((self.0 >> 64) as u64);
+    folded ^ (iteration as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+  }
 }
 
 impl Display for TestId {
@@ -8,8 +8,9 @@ use std::{
 
 use colored::Colorize;
 use log::{error, info};
+use ordering_server::delay::Delay;
 use priority_queue::DoublePriorityQueue;
-use rand::{seq::IteratorRandom, SeedableRng};
+use rand::{rngs::StdRng, seq::IteratorRandom, Rng, SeedableRng};
 use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
 use serde::{ser::SerializeStruct, Deserialize, Serialize};
 use streaming_transpositions::{
@@ -18,17 +19,17 @@ use streaming_transpositions::{
 };
 
 // const RANDOM_ORDERING_GEOMETRIC_R: f64 = 0.5;
-const MAX_NUM_FEDERATES_PER_TEST: usize = 48;
 const HEALTH_CHECK_FREQUENCY: u32 = 200;
 const MAX_N_RUNS_BEFORE_STOPPING: usize = 5000;
+const MAX_PERTURBATION_DELAY: Duration = Duration::from_millis(20);
 
 use crate::{
   exec::{ExecResult, Executable},
   io::{run_with_parameters, RunContext},
   outputvector::{OutputVector, OutputVectorRegistry, OvrDelta, OvrReg, VectorfyStatus},
   state::{InitialState, KnownCountsState, State, TestId},
-  ConstraintList, ConstraintListIndex, ConstraintListRegistry, ThreadId, TraceRecord,
-  CONCURRENCY_LIMIT, TEST_TIMEOUT_SECS,
+  ConstraintList, ConstraintListIndex, ConstraintListRegistry, HookInvocationCounts, ThreadId,
+  TraceRecord, CONCURRENCY_LIMIT, EXPLORE_DELAYS, TEST_TIMEOUT_SECS,
 };
 #[derive(Debug)]
 pub struct AccumulatingTracesState {
@@ -181,6 +182,8 @@ fn reconstruct_test_runs(
   let pair_iterator = trdeltas.last().unwrap().pair_iterator.clone();
   let done = trdeltas.last().unwrap().done;
   let initial_cumsum_in_current_pass = trdeltas.last().unwrap().initial_cumsum_in_current_pass;
+  let infeasible = trdeltas.last().unwrap().infeasible.clone();
+  let n_delay_runs = trdeltas.last().unwrap().n_delay_runs;
   for trdelta in trdeltas {
     for dvrd in trdelta.clr_delta {
       clr.push(dvrd);
@@ -205,6 +208,8 @@ fn reconstruct_test_runs(
     pair_iterator,
     done,
     initial_cumsum_in_current_pass,
+    infeasible,
+    n_delay_runs,
   }
 }
 
@@ -225,13 +230,17 @@ pub struct TestRuns {
   pub pair_iterator: BigSmallIterator,
   pub done: bool,
   pub initial_cumsum_in_current_pass: CumSum,
+  /// (waiter, notifier) pairs that the ordering server could not enforce.
+  pub infeasible: Vec<(OgRank, OgRank)>,
+  /// The number of delay-only runs handed out so far; it seeds the next one.
+  pub n_delay_runs: u32,
 }
 impl Serialize for TestRuns {
   fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
   where
     S: serde::Serializer,
   {
-    let mut ret = serializer.serialize_struct("TestRunsDelta", 7)?;
+    let mut ret = serializer.serialize_struct("TestRunsDelta", 9)?;
     ret
       .serialize_field("clr_delta", &self.clr[self.clr_saved_up_to.0 as usize..])
       .unwrap();
@@ -254,10 +263,34 @@ impl Serialize for TestRuns {
         &self.initial_cumsum_in_current_pass,
       )
       .unwrap();
+    ret.serialize_field("infeasible", &self.infeasible).unwrap();
+    ret
+      .serialize_field("n_delay_runs", &self.n_delay_runs)
+      .unwrap();
     ret.end()
   }
 }
 impl TestRuns {
+  /// Whether making `waiter` wait for `notifier` is known to be unenforceable. This is the case if
+  /// some pair that could not be enforced has a waiter that does not happen before `waiter` and a
+  /// notifier that does not happen after `notifier` in program order.
+  pub fn is_infeasible(
+    &self,
+    hic: &HookInvocationCounts,
+    waiter: OgRank,
+    notifier: OgRank,
+  ) -> bool {
+    let waiter = &hic.ogrank2hinvoc[waiter.idx()];
+    let notifier = &hic.ogrank2hinvoc[notifier.idx()];
+    self.infeasible.iter().any(|(w, n)| {
+      let w = &hic.ogrank2hinvoc[w.idx()];
+      let n = &hic.ogrank2hinvoc[n.idx()];
+      w.hid == waiter.hid
+        && w.seqnum >= waiter.seqnum
+        && n.hid == notifier.hid
+        && n.seqnum <= notifier.seqnum
+    })
+  }
   pub fn update_saved_up_to_for_saving_deltas(&mut self) {
     self.clr_saved_up_to = ConstraintListIndex(self.clr.len() as u32);
     self.raws_saved_up_to = self.raw_traces.len();
@@ -275,6 +308,10 @@ struct TestRunsDelta {
   pair_iterator: BigSmallIterator,
   done: bool,
   initial_cumsum_in_current_pass: CumSum,
+  #[serde(default)]
+  infeasible: Vec<(OgRank, OgRank)>,
+  #[serde(default)]
+  n_delay_runs: u32,
 }
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Copy)]
 pub struct CoarseTraceHash(pub u64);
@@ -366,6 +403,8 @@ impl AccumulatingTracesState {
             pair_iterator: BigSmallIterator::new(OgRank(kcs.metadata(id).hic.len() as u32)),
             done: false,
             initial_cumsum_in_current_pass: CumSum(0),
+            infeasible: vec![],
+            n_delay_runs: 0,
           })),
         )
       })
@@ -397,7 +436,29 @@ impl AccumulatingTracesState {
   pub fn get_dt(&self) -> Duration {
     self.dt
   }
+  /// Delays a single hook invocation without ordering any pair, so that a failure is attributed to
+  /// the delay alone. The rank and the delay are determined by the test and the number of delay
+  /// runs that preceded this one, so any delay run can be reproduced.
+  fn get_delay_constraint_vector(&self, id: &TestId) -> ConstraintList {
+    let length = self.kcs.metadata(id).hic.len() as u32;
+    let iteration = {
+      let mut guard = self.runs[id].write().unwrap();
+      guard.n_delay_runs += 1;
+      guard.n_delay_runs - 1
+    };
+    let mut rng = StdRng::seed_from_u64(id.delay_seed(iteration));
+    ConstraintList::delay_only(
+      OgRank(rng.gen_range(0..length)),
+      Delay::Uniform {
+        min: Duration::ZERO,
+        max: MAX_PERTURBATION_DELAY,
+        seed: rng.gen(),
+      },
+      length,
+    )
+  }
   fn get_constraint_vector(&self, id: &TestId) -> ConstraintList {
+    let length = self.kcs.metadata(id).hic.len() as u32;
     let mut guard = self.runs[id].write().unwrap();
     let filter = |before: OgRank, after: OgRank| {
       let before_hinvoc = &self.kcs.metadata(id).hic.ogrank2hinvoc[before.idx()];
@@ -408,7 +469,10 @@ impl AccumulatingTracesState {
     loop {
       let power = guard.pair_iterator.power();
       if let Some((i_after, i_before)) = guard.pair_iterator.next() {
-        if guard.strans_hook.contains(i_before, i_after) || !filter(i_before, i_after) {
+        if guard.strans_hook.contains(i_before, i_after)
+          || !filter(i_before, i_after)
+          || guard.is_infeasible(&self.kcs.metadata(id).hic, i_after, i_before)
+        {
           continue;
         }
         if guard.pair_iterator.power() != power {
@@ -431,7 +495,7 @@ impl AccumulatingTracesState {
       }
     }
     assert!(before > after);
-    ConstraintList::singleton(after, before, self.kcs.metadata(id).hic.len() as u32)
+    ConstraintList::singleton(after, before, length)
   }
   async fn get_run(
     &self,
@@ -633,8 +697,8 @@ impl AccumulatingTracesState {
     executables: &Vec<(TestId, Executable)>,
     my_ovr: OutputVectorRegistry,
   ) {
-    let mut ordserv_handle =
-      ordering_server::server::run_reusing_connections(1, MAX_NUM_FEDERATES_PER_TEST).await;
+    // The connection pool grows to fit the largest test that this thread runs.
+    let mut ordserv_handle = ordering_server::server::run_reusing_connections(1, 0).await;
     let ordserv = &mut ordserv_handle.updates_acks[0];
     let mut rctx = RunContext {
       scratch: &scratch,
@@ -645,42 +709,47 @@ impl AccumulatingTracesState {
     let mut successes = 0;
     while std::time::Instant::now() - t0 < std::time::Duration::from_secs(time_seconds as u64) {
       if let Some((id, exe)) = Self::get_executable(tidx, &self_immut.runs, executables) {
-        let conl = self_immut.get_constraint_vector(&id);
-        let clr = Arc::clone(&self_immut.runs[&id]);
-        let run = self_immut.get_run(&id, &exe, &conl, clr, &mut rctx).await;
-        rctx.run_id += 1;
-        if run.is_ok() {
-          successes += 1;
+        let mut conls = vec![self_immut.get_constraint_vector(&id)];
+        if *EXPLORE_DELAYS.get().unwrap_or(&false) {
+          conls.push(self_immut.get_delay_constraint_vector(&id));
         }
-        if rctx.run_id % HEALTH_CHECK_FREQUENCY == 0 || run.is_err() {
-          info!(
-            "Thread {} health check. Success rate: {} / {} ({}). Speed: {} runs/second.",
-            tidx,
-            successes,
-            rctx.run_id,
-            (successes as f64) / (rctx.run_id as f64),
-            rctx.run_id as f64 / (std::time::Instant::now() - t0).as_secs_f64()
-          );
-        }
-        let mut entry = self_immut.runs.get(&id).unwrap().write().unwrap();
-        entry.clr.push(conl);
-        let idx = ConstraintListIndex(entry.clr.len() as u32 - 1);
-        match run {
-          Ok((hook_orcr, out_orcr, trhash, status)) => {
-            entry.strans_hook.record(hook_orcr.0.clone(), hook_orcr.1);
-            entry.strans_out.record(out_orcr.0.clone(), out_orcr.1);
-            let ov = OutputVector::new(out_orcr.0, Arc::clone(&my_ovr));
-            entry
-              .iomats
-              .entry(trhash.0)
-              .or_insert(HashMap::new())
-              .entry(trhash.1)
-              .or_insert(vec![])
-              .push(ov);
-            entry.raw_traces.push((idx, Ok((ov, trhash, status))));
+        for conl in conls {
+          let clr = Arc::clone(&self_immut.runs[&id]);
+          let run = self_immut.get_run(&id, &exe, &conl, clr, &mut rctx).await;
+          rctx.run_id += 1;
+          if run.is_ok() {
+            successes += 1;
           }
-          Err(err) => {
-            entry.raw_traces.push((idx, Err(err)));
+          if rctx.run_id % HEALTH_CHECK_FREQUENCY == 0 || run.is_err() {
+            info!(
+              "Thread {} health check. Success rate: {} / {} ({}). Speed: {} runs/second.",
+              tidx,
+              successes,
+              rctx.run_id,
+              (successes as f64) / (rctx.run_id as f64),
+              rctx.run_id as f64 / (std::time::Instant::now() - t0).as_secs_f64()
+            );
+          }
+          let mut entry = self_immut.runs.get(&id).unwrap().write().unwrap();
+          entry.clr.push(conl);
+          let idx = ConstraintListIndex(entry.clr.len() as u32 - 1);
+          match run {
+            Ok((hook_orcr, out_orcr, trhash, status)) => {
+              entry.strans_hook.record(hook_orcr.0.clone(), hook_orcr.1);
+              entry.strans_out.record(out_orcr.0.clone(), out_orcr.1);
+              let ov = OutputVector::new(out_orcr.0, Arc::clone(&my_ovr));
+              entry
+                .iomats
+                .entry(trhash.0)
+                .or_insert(HashMap::new())
+                .entry(trhash.1)
+                .or_insert(vec![])
+                .push(ov);
+              entry.raw_traces.push((idx, Ok((ov, trhash, status))));
+            }
+            Err(err) => {
+              entry.raw_traces.push((idx, Err(err)));
+            }
           }
         }
       } else {
@@ -688,8 +757,12 @@ impl AccumulatingTracesState {
         tokio::time::sleep(std::time::Duration::from_secs(time_seconds as u64)).await;
       }
     }
-    ordserv_handle.updates_acks[0].0.send(None).await.unwrap();
-    ordserv_handle.join_handle.await.unwrap();
+    ordserv_handle.updates_acks[0]
+      .0
+      .send(ordering_server::server::Update::Halt)
+      .await
+      .unwrap();
+    ordserv_handle.join().await;
   }
 }
 #[derive(Debug, Clone, Copy)]
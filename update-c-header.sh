@@ -0,0 +1,2 @@
+# Regenerates the header of the C client from its source.
+ORDSERV_UPDATE_C_HEADER=1 cargo test -p c-ordering-client --test header